@@ -0,0 +1,342 @@
+extern crate rustc_serialize;
+
+use std::ascii::AsciiExt;
+use std::collections::BTreeMap;
+
+use dkim::{DkimSignature, DkimVerifier, DkimAlgorithm, DkimReason};
+use dkim::{DkimKeyLookup, lookup_public_key};
+use dkim::{Canonicalizer, CanonicalizationType};
+use dkim::{parse_dkim_signature, strip_whitespace, canonicalize_signature_header};
+use dkim::{hash_headers, verify_signature};
+
+use self::rustc_serialize::base64::FromBase64;
+
+mod result;
+mod sealer;
+
+pub use self::result::{ArcResult, ArcChainStatus, ArcReason};
+pub use self::sealer::{ArcSealer, ArcSealError};
+
+// RFC 8617 section 4.2.1
+pub const MAX_INSTANCE: u32 = 50;
+
+pub const AUTHENTICATION_RESULTS: &'static str = "ARC-Authentication-Results";
+pub const MESSAGE_SIGNATURE: &'static str = "ARC-Message-Signature";
+pub const SEAL: &'static str = "ARC-Seal";
+
+type Header = (String, String, Vec<u8>);
+
+// The tags of an ARC-Seal header
+pub struct ArcSeal {
+    instance: u32,
+    chain_validation: ArcChainStatus,
+    algorithm: DkimAlgorithm,
+    sdid: String,
+    selector: String,
+    signature: String
+}
+
+impl ArcSeal {
+    pub fn parse(seal: &str) -> Option<ArcSeal> {
+        let tags = match parse_dkim_signature(seal) {
+            Ok(tags) => tags,
+            Err(_) => return None
+        };
+        // a seal signs the ARC headers, not the message
+        if tags.contains_key(&"h") {
+            return None;
+        }
+
+        let tag = |name: &str| tags.get(&name).map(|v| v.to_string());
+        match (tag("i").and_then(|i| i.parse().ok()),
+               tag("cv").and_then(|cv| ArcChainStatus::from_name(&cv.to_ascii_lowercase())),
+               tag("a").and_then(|a| DkimAlgorithm::from_name(&a)),
+               tag("d"), tag("s"), tag("b")) {
+            (Some(instance), Some(cv), Some(algorithm), Some(sdid), Some(selector), Some(b)) =>
+                Some(ArcSeal {
+                    instance: instance,
+                    chain_validation: cv,
+                    algorithm: algorithm,
+                    sdid: sdid,
+                    selector: selector,
+                    signature: strip_whitespace(&b)
+                }),
+            _ => None
+        }
+    }
+
+    pub fn instance(&self) -> u32 {
+        self.instance
+    }
+
+    pub fn chain_validation(&self) -> ArcChainStatus {
+        self.chain_validation
+    }
+}
+
+// The three headers added by one ARC intermediary
+pub struct ArcSet {
+    pub instance: u32,
+    pub authentication_results: Header,
+    pub message_signature: Header,
+    pub seal: Header,
+    // the position of the message signature in the header block
+    message_signature_index: usize
+}
+
+#[derive(Default)]
+struct PartialSet {
+    authentication_results: Option<Header>,
+    message_signature: Option<(usize, Header)>,
+    seal: Option<Header>
+}
+
+// Groups the ARC headers of a message into sets, ordered by instance, and
+// checks that they form a complete chain
+pub fn parse_arc_sets(headers: &[Header]) -> Result<Vec<ArcSet>, ArcReason> {
+    let mut partial_sets: BTreeMap<u32, PartialSet> = BTreeMap::new();
+
+    for (index, header) in headers.iter().enumerate() {
+        let name = header.0.trim();
+        let is_arc_header = [AUTHENTICATION_RESULTS, MESSAGE_SIGNATURE, SEAL].iter()
+            .any(|n| name.eq_ignore_ascii_case(n));
+        if !is_arc_header {
+            continue;
+        }
+
+        let instance = try!(parse_instance(&header.1));
+        if instance < 1 || instance > MAX_INSTANCE {
+            return Err(ArcReason::InstanceOutOfRange(instance));
+        }
+
+        let set = partial_sets.entry(instance).or_insert(PartialSet::default());
+        let duplicate = if name.eq_ignore_ascii_case(AUTHENTICATION_RESULTS) {
+            mem_set(&mut set.authentication_results, header.clone())
+        }
+        else if name.eq_ignore_ascii_case(MESSAGE_SIGNATURE) {
+            mem_set(&mut set.message_signature, (index, header.clone()))
+        }
+        else {
+            mem_set(&mut set.seal, header.clone())
+        };
+        if duplicate {
+            return Err(ArcReason::DuplicateHeader(instance));
+        }
+    }
+
+    let mut sets = vec![];
+    for (expected, (instance, set)) in (1..).zip(partial_sets.into_iter()) {
+        if instance != expected {
+            return Err(ArcReason::SetIncomplete(expected));
+        }
+        match set {
+            PartialSet {
+                authentication_results: Some(aar),
+                message_signature: Some((index, ams)),
+                seal: Some(seal)
+            } => sets.push(ArcSet {
+                instance: instance,
+                authentication_results: aar,
+                message_signature: ams,
+                seal: seal,
+                message_signature_index: index
+            }),
+            _ => return Err(ArcReason::SetIncomplete(instance))
+        }
+    }
+
+    Ok(sets)
+}
+
+// Stores value, returning whether there was one already
+fn mem_set<T>(slot: &mut Option<T>, value: T) -> bool {
+    let duplicate = slot.is_some();
+    *slot = Some(value);
+    duplicate
+}
+
+// Every ARC header starts with i=, including ARC-Authentication-Results
+// where the rest of the value isn't a tag list
+fn parse_instance(value: &str) -> Result<u32, ArcReason> {
+    let first = value.split(';').next().unwrap_or("");
+    let mut tag = first.splitn(2, '=');
+    match (tag.next().map(|t| t.trim()), tag.next().map(|v| v.trim().parse::<u32>())) {
+        (Some("i"), Some(Ok(instance))) => Ok(instance),
+        _ => Err(ArcReason::BadInstance(first.trim().to_string()))
+    }
+}
+
+// Checks the seal of the newest set in sets, which covers every set up to
+// and including it (RFC 8617 section 5.1.2)
+pub fn verify_seal(sets: &[ArcSet], key_lookup: &DkimKeyLookup) -> Result<(), DkimReason> {
+    let latest = &sets[sets.len() - 1];
+    let seal = match ArcSeal::parse(&latest.seal.1) {
+        Some(seal) => seal,
+        None => return Err(DkimReason::BadSignatureEncoding)
+    };
+
+    let public_key = try!(lookup_public_key(key_lookup, &seal.selector, &seal.sdid));
+    if public_key.key_type() != seal.algorithm.key_type() {
+        return Err(DkimReason::KeyTypeMismatch);
+    }
+
+    let hash = try!(seal_hash(sets, seal.algorithm));
+    let signature = match seal.signature.from_base64() {
+        Ok(s) => s,
+        Err(_) => return Err(DkimReason::BadSignatureEncoding)
+    };
+
+    if verify_signature(seal.algorithm, &public_key, &hash, &signature) {
+        Ok(())
+    }
+    else {
+        Err(DkimReason::SignatureMismatch)
+    }
+}
+
+// The hash a seal signs: each set's headers in instance order, always with
+// relaxed canonicalization, and the newest seal without its b= value
+fn seal_hash(sets: &[ArcSet], algorithm: DkimAlgorithm) -> Result<Vec<u8>, DkimReason> {
+    let mut canon = Canonicalizer::head(CanonicalizationType::Relaxed);
+    let mut headers = vec![];
+
+    for (i, set) in sets.iter().enumerate() {
+        let mut set_headers = vec![&set.authentication_results, &set.message_signature];
+        if i + 1 < sets.len() {
+            set_headers.push(&set.seal);
+        }
+        for &&(ref name, ref value, ref raw) in set_headers.iter() {
+            headers.extend(canon.canonicalize(name.clone(), value.clone(), raw.clone()));
+        }
+    }
+
+    let (ref name, ref value, ref raw) = sets[sets.len() - 1].seal;
+    let seal = canonicalize_signature_header(&mut *canon, name, value, raw);
+    hash_headers(algorithm, &headers, &seal).map_err(|_| DkimReason::HashError)
+}
+
+// Validates the ARC chain of a message.  As with DkimVerifier, the headers are
+// given up front and the body is streamed through.
+pub struct ArcValidator {
+    sets: Result<Vec<ArcSet>, ArcReason>,
+    // the message signature verifier of each set, in instance order
+    message_signatures: Vec<Result<DkimVerifier, ArcReason>>
+}
+
+impl ArcValidator {
+    pub fn new(headers: &[Header]) -> ArcValidator {
+        let sets = parse_arc_sets(headers);
+        let mut message_signatures = vec![];
+
+        match sets {
+            Ok(ref sets) => for set in sets.iter() {
+                let (ref name, ref value, ref raw) = set.message_signature;
+                let verifier = match DkimSignature::parse_arc(value) {
+                    Ok((_, signature)) => {
+                        let mut verifier = DkimVerifier::new(signature, name.clone(), value.clone(), raw.clone());
+                        for (i, &(ref name, ref value, ref raw)) in headers.iter().enumerate() {
+                            if i != set.message_signature_index {
+                                verifier.add_header(name.clone(), value.clone(), raw.clone());
+                            }
+                        }
+                        Ok(verifier)
+                    }
+                    Err(e) => Err(ArcReason::MessageSignatureSyntax(set.instance, e))
+                };
+                message_signatures.push(verifier);
+            },
+            Err(_) => ()
+        }
+
+        ArcValidator { sets: sets, message_signatures: message_signatures }
+    }
+
+    #[allow(unused_must_use)]
+    pub fn update_body(&mut self, data: &Vec<u8>) {
+        for verifier in self.message_signatures.iter_mut() {
+            match *verifier {
+                Ok(ref mut verifier) => { verifier.update_body(data); }
+                Err(_) => ()
+            }
+        }
+    }
+
+    // RFC 8617 section 5.2
+    pub fn finalize(self, key_lookup: &DkimKeyLookup) -> ArcResult {
+        let sets = match self.sets {
+            Ok(sets) => sets,
+            Err(reason) => return ArcResult::fail(0, reason)
+        };
+        if sets.is_empty() {
+            return ArcResult::none();
+        }
+        let latest = sets.len() as u32;
+
+        for set in sets.iter() {
+            let cv = match ArcSeal::parse(&set.seal.1) {
+                Some(seal) => seal.chain_validation,
+                None => return ArcResult::fail(latest, ArcReason::SealSyntax(set.instance))
+            };
+            if cv == ArcChainStatus::Fail {
+                return ArcResult::fail(latest, ArcReason::ChainMarkedFailed(set.instance));
+            }
+            let expected = if set.instance == 1 { ArcChainStatus::None } else { ArcChainStatus::Pass };
+            if cv != expected {
+                return ArcResult::fail(latest, ArcReason::BadChainValidation(set.instance));
+            }
+        }
+
+        // the newest message signature must verify; older ones only
+        // tell us how far back the message is unchanged
+        let mut oldest_pass = latest;
+        for (set, verifier) in sets.iter().zip(self.message_signatures.into_iter()).rev() {
+            let reason = match verifier {
+                Ok(verifier) => verify_message_signature(set.instance, verifier, key_lookup),
+                Err(reason) => Some(reason)
+            };
+            match reason {
+                None => oldest_pass = set.instance,
+                Some(reason) if set.instance == latest => return ArcResult::fail(latest, reason),
+                Some(_) => break
+            }
+        }
+
+        for i in (0..sets.len()).rev() {
+            match verify_seal(&sets[..i + 1], key_lookup) {
+                Ok(_) => (),
+                Err(reason) => return ArcResult::fail(latest, ArcReason::Seal(sets[i].instance, reason))
+            }
+        }
+
+        ArcResult::pass(latest, oldest_pass)
+    }
+}
+
+fn verify_message_signature(instance: u32, verifier: DkimVerifier, 
+                            key_lookup: &DkimKeyLookup) -> Option<ArcReason> {
+    let public_key = lookup_public_key(key_lookup, verifier.signature().selector(), 
+                                       verifier.signature().sdid());
+    let result = match public_key {
+        Ok(public_key) => verifier.finalize_body(&public_key),
+        Err(reason) => verifier.reject(reason)
+    };
+    match result.reason {
+        None => None,
+        Some(reason) => Some(ArcReason::MessageSignature(instance, reason))
+    }
+}
+
+#[test]
+fn test_parse_arc_seal() {
+    let seal = ArcSeal::parse("i=2; a=rsa-sha256; cv=Pass; d=example.net; s=brisbane; b=abc\r\n def").unwrap();
+    assert_eq!(2, seal.instance());
+    assert_eq!(ArcChainStatus::Pass, seal.chain_validation());
+    assert_eq!("abcdef", seal.signature);
+
+    assert!(ArcSeal::parse("i=2; a=rsa-sha256; cv=pass; d=example.net; s=brisbane; h=from; b=abc").is_none());
+    assert!(ArcSeal::parse("i=2; a=rsa-sha256; cv=maybe; d=example.net; s=brisbane; b=abc").is_none());
+
+    assert_eq!(Ok(12), parse_instance(" i = 12; lists.example.org; dkim=pass"));
+    assert_eq!(Err(ArcReason::BadInstance("lists.example.org".to_string())),
+               parse_instance("lists.example.org; dkim=pass"));
+}
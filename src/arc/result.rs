@@ -0,0 +1,74 @@
+use dkim::{DkimReason, DkimSignatureParseError};
+
+// The chain validation status, as reported in cv= (RFC 8617 section 4.4)
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ArcChainStatus {
+    None,
+    Pass,
+    Fail
+}
+
+impl ArcChainStatus {
+    pub fn from_name(name: &str) -> Option<ArcChainStatus> {
+        match name {
+            "none" => Some(ArcChainStatus::None),
+            "pass" => Some(ArcChainStatus::Pass),
+            "fail" => Some(ArcChainStatus::Fail),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ArcChainStatus::None => "none",
+            ArcChainStatus::Pass => "pass",
+            ArcChainStatus::Fail => "fail"
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ArcReason {
+    // the ARC headers don't form a valid chain of sets
+    BadInstance(String),
+    InstanceOutOfRange(u32),
+    DuplicateHeader(u32),
+    SetIncomplete(u32),
+    SealSyntax(u32),
+    MessageSignatureSyntax(u32, DkimSignatureParseError),
+
+    // a sealer found the chain had already failed
+    ChainMarkedFailed(u32),
+    // the cv= of a seal is wrong for its instance
+    BadChainValidation(u32),
+
+    // the newest message signature, or a seal, did not verify
+    MessageSignature(u32, DkimReason),
+    Seal(u32, DkimReason)
+}
+
+// The outcome of validating a message's ARC chain
+#[derive(Debug, PartialEq, Clone)]
+pub struct ArcResult {
+    pub status: ArcChainStatus,
+    pub reason: Option<ArcReason>,
+    // the highest instance in the chain, or 0 if there are no ARC sets
+    pub instance: u32,
+    // the oldest instance whose message signature, and those of all newer
+    // instances, still verifies
+    pub oldest_pass: Option<u32>
+}
+
+impl ArcResult {
+    pub fn none() -> ArcResult {
+        ArcResult { status: ArcChainStatus::None, reason: None, instance: 0, oldest_pass: None }
+    }
+
+    pub fn fail(instance: u32, reason: ArcReason) -> ArcResult {
+        ArcResult { status: ArcChainStatus::Fail, reason: Some(reason), instance: instance, oldest_pass: None }
+    }
+
+    pub fn pass(instance: u32, oldest_pass: u32) -> ArcResult {
+        ArcResult { status: ArcChainStatus::Pass, reason: None, instance: instance, oldest_pass: Some(oldest_pass) }
+    }
+}
@@ -0,0 +1,172 @@
+use std::ascii::AsciiExt;
+
+use dkim::{DkimSigningKey, DkimSigningError, BodyHasher, CanonicalizationType};
+use dkim::{select_headers, sign_headers, base64};
+
+use super::{ArcResult, ArcChainStatus, parse_arc_sets};
+use super::{MAX_INSTANCE, AUTHENTICATION_RESULTS, MESSAGE_SIGNATURE, SEAL};
+
+#[derive(Debug)]
+pub enum ArcSealError {
+    // the message already has the maximum number of ARC sets
+    ChainTooLong,
+    // the chain was reported as valid, but its sets can't be parsed
+    BadChain,
+    Signing(DkimSigningError)
+}
+
+// Adds our ARC set to messages we forward (RFC 8617 section 5.1)
+pub struct ArcSealer {
+    key: DkimSigningKey,
+    selector: String,
+    sdid: String,
+    header_fields: Vec<String>
+}
+
+impl ArcSealer {
+    pub fn new(key: DkimSigningKey, selector: &str, sdid: &str, header_fields: Vec<String>) -> ArcSealer {
+        ArcSealer {
+            key: key,
+            selector: selector.to_string(),
+            sdid: sdid.to_string(),
+            // the message signature must not cover ARC-Seal headers
+            header_fields: header_fields.into_iter()
+                .filter(|h| !h.trim().eq_ignore_ascii_case(SEAL))
+                .collect()
+        }
+    }
+
+    pub fn body_hasher(&self) -> BodyHasher {
+        BodyHasher::new(self.key.algorithm(), CanonicalizationType::Relaxed, None)
+    }
+
+    // Produces the ARC-Seal, ARC-Message-Signature and ARC-Authentication-Results
+    // headers to add to a message, in that order, as (name, value) pairs.
+    // authentication_results is the value of our Authentication-Results
+    // header, and chain the result of validating the message's existing
+    // ARC chain.
+    pub fn seal(&self, headers: &[(String, String, Vec<u8>)], body_hash: &[u8],
+                authentication_results: &str, chain: &ArcResult)
+        -> Result<Vec<(String, String)>, ArcSealError> {
+
+        let instance = chain.instance + 1;
+        if instance > MAX_INSTANCE {
+            return Err(ArcSealError::ChainTooLong);
+        }
+
+        let aar = header(AUTHENTICATION_RESULTS, format!("i={}; {}", instance, authentication_results));
+
+        let unsigned = format!("i={}; a={}; c=relaxed/relaxed; d={}; s={};\r\n\th={};\r\n\tbh={};\r\n\tb=",
+                               instance,
+                               self.key.algorithm().name(),
+                               self.sdid,
+                               self.selector,
+                               self.header_fields.connect(":"),
+                               base64(body_hash));
+        let ams_value = try!(sign_headers(&self.key, CanonicalizationType::Relaxed,
+                                          &select_headers(&self.header_fields, headers),
+                                          MESSAGE_SIGNATURE, &unsigned).map_err(ArcSealError::Signing));
+        let ams = header(MESSAGE_SIGNATURE, ams_value);
+
+        // a failed chain isn't carried forward, so only our set is sealed
+        let sets = match chain.status {
+            ArcChainStatus::Fail => vec![],
+            _ => try!(parse_arc_sets(headers).map_err(|_| ArcSealError::BadChain))
+        };
+        if chain.status != ArcChainStatus::Fail && sets.len() as u32 != chain.instance {
+            return Err(ArcSealError::BadChain);
+        }
+
+        let seal = {
+            let mut sealed = vec![];
+            for set in sets.iter() {
+                sealed.push(&set.authentication_results);
+                sealed.push(&set.message_signature);
+                sealed.push(&set.seal);
+            }
+            sealed.push(&aar);
+            sealed.push(&ams);
+
+            let unsigned = format!("i={}; a={}; cv={}; d={}; s={};\r\n\tb=",
+                                   instance,
+                                   self.key.algorithm().name(),
+                                   chain.status.name(),
+                                   self.sdid,
+                                   self.selector);
+            try!(sign_headers(&self.key, CanonicalizationType::Relaxed, &sealed, SEAL, &unsigned)
+                 .map_err(ArcSealError::Signing))
+        };
+
+        let (_, ams_value, _) = ams;
+        let (_, aar_value, _) = aar;
+        Ok(vec![(SEAL.to_string(), seal),
+                (MESSAGE_SIGNATURE.to_string(), ams_value),
+                (AUTHENTICATION_RESULTS.to_string(), aar_value)])
+    }
+}
+
+fn header(name: &str, value: String) -> (String, String, Vec<u8>) {
+    let raw = format!("{}: {}\r\n", name, value).into_bytes();
+    (name.to_string(), value, raw)
+}
+
+#[cfg(test)]
+fn seal_and_validate(sealer: &ArcSealer, headers: &[(String, String, Vec<u8>)], body: &[u8],
+                     chain: &ArcResult) -> (Vec<(String, String, Vec<u8>)>, ArcResult) {
+    use dns::ZoneFile;
+    use dkim::TEST_ED25519_PUBLIC_KEY;
+    use super::ArcValidator;
+
+    let mut body_hasher = sealer.body_hasher();
+    body_hasher.update(&body.to_vec()).unwrap();
+    let added = sealer.seal(headers, &body_hasher.finish().unwrap(),
+                            "forwarder.example.net; dkim=pass header.d=example.com", chain).unwrap();
+
+    let mut sealed_headers: Vec<(String, String, Vec<u8>)> = added.into_iter()
+        .map(|(name, value)| header(&name, value)).collect();
+    sealed_headers.extend(headers.iter().cloned());
+
+    let mut keys = ZoneFile::new();
+    keys.add_txt("test._domainkey.example.net", &format!("v=DKIM1; k=ed25519; p={}", TEST_ED25519_PUBLIC_KEY));
+
+    let mut validator = ArcValidator::new(&sealed_headers);
+    validator.update_body(&body.to_vec());
+    let result = validator.finalize(&keys);
+    (sealed_headers, result)
+}
+
+#[cfg(test)]
+fn test_sealer() -> ArcSealer {
+    use super::rustc_serialize::base64::FromBase64;
+
+    let seed = "nWGxne/9WmC6hEr0kuwsxERJxWl7MmkZcDusAxyuf2A=".from_base64().unwrap();
+    ArcSealer::new(DkimSigningKey::ed25519_from_seed(&seed).unwrap(), "test", "example.net",
+                   vec!["From".to_string(), "To".to_string(), "Subject".to_string(), "ARC-Seal".to_string()])
+}
+
+#[test]
+fn test_seal_chain() {
+    let headers: Vec<(String, String, Vec<u8>)> = vec![
+        ("From", "Joe SixPack <joe@football.example.com>"),
+        ("To", "Suzie Q <suzie@shopping.example.net>"),
+        ("Subject", "Is dinner ready?")].into_iter()
+        .map(|(name, value)| header(name, value.to_string())).collect();
+    let body = b"Hi.\r\n\r\nWe lost the game. Are you hungry yet?\r\n\r\nJoe.\r\n";
+    let sealer = test_sealer();
+
+    let (headers, result) = seal_and_validate(&sealer, &headers, body, &ArcResult::none());
+    assert_eq!(ArcResult::pass(1, 1), result);
+    assert!(headers[0].1.contains("cv=none"));
+    assert_eq!("i=1; forwarder.example.net; dkim=pass header.d=example.com", headers[2].1);
+
+    let (headers, result) = seal_and_validate(&sealer, &headers, body, &result);
+    assert_eq!(ArcResult::pass(2, 1), result);
+    assert!(headers[0].1.contains("cv=pass"));
+
+    // a sealer that finds the chain broken says so, and the chain stays failed
+    let (headers, result) = seal_and_validate(&sealer, &headers, body,
+                                              &ArcResult::fail(2, super::ArcReason::SetIncomplete(1)));
+    assert!(headers[0].1.contains("cv=fail"));
+    assert_eq!(ArcChainStatus::Fail, result.status);
+    assert_eq!(3, result.instance);
+}
@@ -0,0 +1,227 @@
+use events::MessageParserEvent;
+use events::MessageParserStage;
+use events::MessageParserEvent::{Header, EndOfHeaders, BodyChunk, ArcResult, End};
+
+use self::ArcState::{Headers,Body,Finished};
+
+use arc::ArcValidator;
+use dkim::DkimKeyLookup;
+#[cfg(test)]
+use arc::{ArcChainStatus, ArcReason};
+#[cfg(test)]
+use dkim::DkimReason;
+#[cfg(test)]
+use dns::ZoneFile;
+
+// Validates the ARC chain (RFC 8617) of each message, and reports it with an
+// ArcResult event just before End
+pub struct ArcChecker<'a> {
+    state: ArcState,
+    headers: Vec<(String, String, Vec<u8>)>,
+    validator: Option<ArcValidator>,
+    key_lookup: &'a (DkimKeyLookup + 'a),
+    next_stage: &'a mut (MessageParserStage + 'a)
+}
+
+#[derive(Debug, Clone)]
+enum ArcState {
+    Headers,
+    Body,
+    Finished
+}
+
+impl<'a> MessageParserStage for ArcChecker<'a> {
+
+    fn process_event(&mut self, event: MessageParserEvent) {
+        let next_state = match self.state {
+            Headers => self.parse_headers(event),
+            Body => self.parse_body(event),
+            Finished => {
+                self.next_stage.process_event(event);
+                Finished
+            }
+        };
+
+        self.state = next_state;
+    }
+}
+
+impl<'a> ArcChecker<'a> {
+    pub fn new(next_stage: &'a mut MessageParserStage, key_lookup: &'a DkimKeyLookup) -> ArcChecker<'a> {
+        ArcChecker {
+            state: Headers,
+            headers: vec![],
+            validator: None,
+            key_lookup: key_lookup,
+            next_stage: next_stage
+        }
+    }
+
+    fn parse_headers(&mut self, event: MessageParserEvent) -> ArcState {
+        match event {
+            Header(ref name, ref value, ref raw) => {
+                self.headers.push((name.clone(), value.clone(), raw.clone()));
+            }
+            _ => ()
+        }
+
+        match event {
+            EndOfHeaders => {
+                self.validator = Some(ArcValidator::new(&self.headers));
+                self.next_stage.process_event(event);
+                Body
+            }
+            End => {
+                self.validator = Some(ArcValidator::new(&self.headers));
+                self.parse_body(event)
+            }
+            _ => {
+                self.next_stage.process_event(event);
+                Headers
+            }
+        }
+    }
+
+    fn parse_body(&mut self, event: MessageParserEvent) -> ArcState {
+        match event {
+            BodyChunk(ref data) => {
+                match self.validator {
+                    Some(ref mut validator) => validator.update_body(data),
+                    None => ()
+                }
+                self.next_stage.process_event(event.clone());
+                Body
+            }
+            End => {
+                match self.validator.take() {
+                    Some(validator) => {
+                        let result = validator.finalize(self.key_lookup);
+                        self.next_stage.process_event(ArcResult(result));
+                    }
+                    None => ()
+                }
+                self.next_stage.process_event(event);
+                Finished
+            }
+            _ => {
+                self.next_stage.process_event(event);
+                Body
+            }
+        }
+    }
+}
+
+// A message that passed through two intermediaries, the second of which
+// changed the subject
+#[cfg(test)]
+const TEST_ARC_HEADERS: [&'static str; 6] = [
+    "ARC-Seal: i=2; a=rsa-sha256; cv=pass; d=example.net; s=brisbane; t=1117574938; b=mYttZNQWP1Hzm12SKjC0mSqhNrNhUtgWcPp0371Rdlb7eCvTg7yU1XIy6Ae40FV4kNWGhYRzgSWRU8zOLhkKG3k9ThWNAepKvm2NcHFmaSdG8k9vaDGg0aH6etY3pSOLQH2S661Nxof3RVm6pU4v84esw7xViKKmYotPNezu2XA=\r\n",
+    "ARC-Message-Signature: i=2; a=rsa-sha256; c=relaxed/relaxed; d=example.net; s=brisbane; t=1117574938; h=From:To:Subject:Date:Message-ID; bh=2jUSOH9NhtVGCQWNr9BrIAPreKQjO6Sn7XIkfJVOzv8=; b=SEVdsfijYI/e3CnD4uqle8dAsQiNcklsB9ApWCYKktBQjtj96xcPqUKTILcCBPwwoME2ZGy6D2pQ/tNLI7VIaXiwNFln11dwT5cixPKQZ2MYwBQ8FLq7v9Fd2Ibb+2BLJKVTr5KzIrI9okYu6OZ6Bxud5knnYXzzAIH5V9pEEZA=\r\n",
+    "ARC-Authentication-Results: i=2; forwarder.example.net; arc=pass\r\n",
+    "ARC-Seal: i=1; a=rsa-sha256; cv=none; d=example.org; s=brisbane; t=1117574938; b=My9chjTAaeENivy9i5KKNYwARghahZQuPinysaT4rfq2hgjOzDRPES3hmqpcLwJ2NHraecxSAc8NBjwpT/ecKMMgsPkcVH/Z5yrdPoNlvcOobUcgaF8KGakkMexl3tnb1UQ7wmrDXp6jXLkbFRoD5GpklYSkgtWcX5NqYDoNxJo=\r\n",
+    "ARC-Message-Signature: i=1; a=rsa-sha256; c=relaxed/relaxed; d=example.org; s=brisbane; t=1117574938; h=From:To:Subject:Date:Message-ID; bh=2jUSOH9NhtVGCQWNr9BrIAPreKQjO6Sn7XIkfJVOzv8=; b=teQxoG7z80+1P8HtOJHEv/j6mR64EIAanTEQ/RKtC7vN6p2vQdfkale6SAPgOWHDuPQo73VxKDZLn2mpMPtWBHmC31Dc1p1uDPhXE9QVLBw6lx4gtiCTjeNFqE5pm2O57myvXUFwSZ4ZtyoLHk+CRoFn2eH9UPCee0X+tEwYksY=\r\n",
+    "ARC-Authentication-Results: i=1; lists.example.org; dkim=pass header.d=example.com\r\n"];
+
+#[cfg(test)]
+fn test_arc_message(arc_headers: &[&str], subject: &str) -> String {
+    format!("{}From: Joe SixPack <joe@football.example.com>\r\n\
+             To: Suzie Q <suzie@shopping.example.net>\r\n\
+             Subject: {}\r\n\
+             Date: Fri, 11 Jul 2003 21:00:37 -0700 (PDT)\r\n\
+             Message-ID: <20030712040037.46341.5F8J@football.example.com>\r\n\
+             \r\n\
+             Hi.\r\n\r\nWe lost the game. Are you hungry yet?\r\n\r\nJoe.\r\n",
+            arc_headers.concat(), subject)
+}
+
+#[cfg(test)]
+fn test_arc_keys() -> ZoneFile {
+    use dkim::TEST_PUBLIC_KEY;
+
+    let mut keys = ZoneFile::new();
+    keys.add_txt("brisbane._domainkey.example.org", &format!("v=DKIM1; k=rsa; p={}", TEST_PUBLIC_KEY));
+    keys.add_txt("brisbane._domainkey.example.net", &format!("v=DKIM1; k=rsa; p={}", TEST_PUBLIC_KEY));
+    keys
+}
+
+#[cfg(test)]
+fn test_arc_result(msg: String, keys: &ZoneFile) -> ::arc::ArcResult {
+    use message_parser_sink::MessageParserSink;
+    use reader_parser::ReaderParser;
+    use message_scanner::MessageScanner;
+    use header_parser::HeaderParser;
+    use events::MessageParserFilter;
+
+    let mut sink = MessageParserSink::new();
+    {
+        let r = msg.as_bytes();
+        let mut arc = ArcChecker::new(&mut sink, keys);
+        let mut parser: HeaderParser = MessageParserFilter::new(&mut arc);
+        let mut scanner: MessageScanner = MessageParserFilter::new(&mut parser);
+        let mut rp = ReaderParser::new(&mut scanner, r);
+
+        rp.read_to_end();
+    }
+
+    let results: Vec<::arc::ArcResult> = sink.events().into_iter().filter_map(|e| match e {
+        ArcResult(result) => Some(result),
+        _ => None
+    }).collect();
+    assert_eq!(1, results.len());
+    results[0].clone()
+}
+
+#[test]
+fn arc_pass_test() {
+    let keys = test_arc_keys();
+
+    // the first message signature no longer verifies after the subject change
+    let result = test_arc_result(test_arc_message(&TEST_ARC_HEADERS, "[list] Is dinner ready?"), &keys);
+    assert_eq!(ArcChainStatus::Pass, result.status);
+    assert_eq!(2, result.instance);
+    assert_eq!(Some(2), result.oldest_pass);
+
+    // before the second intermediary, the message was unchanged
+    let result = test_arc_result(test_arc_message(&TEST_ARC_HEADERS[3..], "Is dinner ready?"), &keys);
+    assert_eq!(ArcChainStatus::Pass, result.status);
+    assert_eq!(1, result.instance);
+    assert_eq!(Some(1), result.oldest_pass);
+}
+
+#[test]
+fn arc_none_test() {
+    let result = test_arc_result(test_arc_message(&[], "Is dinner ready?"), &test_arc_keys());
+    assert_eq!(::arc::ArcResult::none(), result);
+}
+
+#[test]
+fn arc_fail_test() {
+    let keys = test_arc_keys();
+
+    // the newest message signature must verify
+    let result = test_arc_result(test_arc_message(&TEST_ARC_HEADERS, "Is dinner ready?"), &keys);
+    assert_eq!(ArcChainStatus::Fail, result.status);
+    assert_eq!(Some(ArcReason::MessageSignature(2, DkimReason::SignatureMismatch)), result.reason);
+
+    // a set is missing its ARC-Authentication-Results
+    let mut headers = TEST_ARC_HEADERS.to_vec();
+    headers.remove(2);
+    let result = test_arc_result(test_arc_message(&headers, "[list] Is dinner ready?"), &keys);
+    assert_eq!(Some(ArcReason::SetIncomplete(2)), result.reason);
+
+    // the sets are numbered from 1
+    let result = test_arc_result(test_arc_message(&TEST_ARC_HEADERS[..3], "[list] Is dinner ready?"), &keys);
+    assert_eq!(Some(ArcReason::SetIncomplete(1)), result.reason);
+
+    // the first seal covers the first ARC-Authentication-Results
+    let mut headers = TEST_ARC_HEADERS.to_vec();
+    headers[5] = "ARC-Authentication-Results: i=1; lists.example.org; dkim=fail header.d=example.com\r\n";
+    let result = test_arc_result(test_arc_message(&headers, "[list] Is dinner ready?"), &keys);
+    assert_eq!(Some(ArcReason::Seal(2, DkimReason::SignatureMismatch)), result.reason);
+
+    // a seal's cv= must be consistent with its instance
+    let mut headers = TEST_ARC_HEADERS.to_vec();
+    headers[0] = "ARC-Seal: i=2; a=rsa-sha256; cv=fail; d=example.net; s=brisbane; b=AAAA\r\n";
+    let result = test_arc_result(test_arc_message(&headers, "[list] Is dinner ready?"), &keys);
+    assert_eq!(Some(ArcReason::ChainMarkedFailed(2)), result.reason);
+}
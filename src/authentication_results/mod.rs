@@ -0,0 +1,209 @@
+use dkim::DkimResult;
+use arc::ArcResult;
+use dmarc::DmarcResult;
+use spf::{SpfResult, SpfIdentity};
+
+pub use self::parser::{AuthenticationResultsParseError, trusted_authentication_results};
+
+mod parser;
+
+// The value of an Authentication-Results header (RFC 8601)
+#[derive(Debug, PartialEq, Clone)]
+pub struct AuthenticationResults {
+    pub authserv_id: String,
+    pub version: Option<u32>,
+    pub results: Vec<MethodResult>
+}
+
+// One method's result, such as "dkim=pass header.d=example.com"
+#[derive(Debug, PartialEq, Clone)]
+pub struct MethodResult {
+    pub method: String,
+    pub result: String,
+    pub reason: Option<String>,
+    pub properties: Vec<Property>,
+    pub comments: Vec<String>
+}
+
+// A ptype.property=value item, such as header.d=example.com
+#[derive(Debug, PartialEq, Clone)]
+pub struct Property {
+    pub ptype: String,
+    pub property: String,
+    pub value: String
+}
+
+impl MethodResult {
+    pub fn new(method: &str, result: &str) -> MethodResult {
+        MethodResult {
+            method: method.to_string(),
+            result: result.to_string(),
+            reason: None,
+            properties: vec![],
+            comments: vec![]
+        }
+    }
+
+    pub fn add_property(&mut self, ptype: &str, property: &str, value: &str) {
+        self.properties.push(Property {
+            ptype: ptype.to_string(),
+            property: property.to_string(),
+            value: value.to_string()
+        });
+    }
+
+    pub fn from_dkim(dkim: &DkimResult) -> MethodResult {
+        let mut result = MethodResult::new("dkim", dkim.status.name());
+        result.reason = dkim.reason.as_ref().map(|r| r.description().to_string());
+        match dkim.sdid {
+            Some(ref sdid) => result.add_property("header", "d", sdid),
+            None => ()
+        }
+        match dkim.selector {
+            Some(ref selector) => result.add_property("header", "s", selector),
+            None => ()
+        }
+        match dkim.identity {
+            Some(ref identity) => result.add_property("header", "i", identity),
+            None => ()
+        }
+        result
+    }
+
+    pub fn from_arc(arc: &ArcResult) -> MethodResult {
+        let mut result = MethodResult::new("arc", arc.status.name());
+        // RFC 8617 only reports the oldest passing instance when it isn't the first
+        match arc.oldest_pass {
+            Some(oldest_pass) if oldest_pass > 1 =>
+                result.add_property("header", "oldest-pass", &oldest_pass.to_string()),
+            _ => ()
+        }
+        result
+    }
+
+    pub fn from_spf(spf: &SpfResult) -> MethodResult {
+        let mut result = MethodResult::new("spf", spf.status.name());
+        match spf.identity {
+            SpfIdentity::Helo => result.add_property("smtp", "helo", &spf.domain),
+            SpfIdentity::MailFrom => result.add_property("smtp", "mailfrom", &spf.sender)
+        }
+        result
+    }
+
+    pub fn from_dmarc(dmarc: &DmarcResult) -> MethodResult {
+        let mut result = MethodResult::new("dmarc", dmarc.status.name());
+        match dmarc.from_domain {
+            Some(ref from_domain) => result.add_property("header", "from", from_domain),
+            None => ()
+        }
+        result
+    }
+
+    fn format(&self) -> String {
+        let mut formatted = format!("{}={}", self.method, self.result);
+        match self.reason {
+            Some(ref reason) => formatted.push_str(&format!(" reason={}", quote(reason))),
+            None => ()
+        }
+        for p in self.properties.iter() {
+            formatted.push_str(&format!(" {}.{}={}", p.ptype, p.property, quote(&p.value)));
+        }
+        formatted
+    }
+}
+
+impl AuthenticationResults {
+    pub fn new(authserv_id: &str) -> AuthenticationResults {
+        AuthenticationResults {
+            authserv_id: authserv_id.to_string(),
+            version: None,
+            results: vec![]
+        }
+    }
+
+    pub fn add_result(&mut self, result: MethodResult) -> &mut AuthenticationResults {
+        self.results.push(result);
+        self
+    }
+
+    pub fn add_dkim_result(&mut self, result: &DkimResult) -> &mut AuthenticationResults {
+        self.add_result(MethodResult::from_dkim(result))
+    }
+
+    pub fn add_arc_result(&mut self, result: &ArcResult) -> &mut AuthenticationResults {
+        self.add_result(MethodResult::from_arc(result))
+    }
+
+    pub fn add_spf_result(&mut self, result: &SpfResult) -> &mut AuthenticationResults {
+        self.add_result(MethodResult::from_spf(result))
+    }
+
+    pub fn add_dmarc_result(&mut self, result: &DmarcResult) -> &mut AuthenticationResults {
+        self.add_result(MethodResult::from_dmarc(result))
+    }
+
+    // The header value, with each result on its own folded line
+    pub fn format(&self) -> String {
+        let mut formatted = self.authserv_id.clone();
+        match self.version {
+            Some(version) => formatted.push_str(&format!(" {}", version)),
+            None => ()
+        }
+        if self.results.is_empty() {
+            formatted.push_str("; none");
+        }
+        for result in self.results.iter() {
+            formatted.push_str(";\r\n\t");
+            formatted.push_str(&result.format());
+        }
+        formatted
+    }
+}
+
+// Values that aren't a MIME token (RFC 2045), or an address or domain, are
+// sent as a quoted-string
+fn quote(value: &str) -> String {
+    let is_token = !value.is_empty() && value.chars().all(|c| {
+        c > ' ' && c < '\x7f' && !"()<>,;:\\\"/[]?=".contains(c)
+    });
+    if is_token {
+        value.to_string()
+    }
+    else {
+        let mut quoted = "\"".to_string();
+        for c in value.chars() {
+            if c == '"' || c == '\\' {
+                quoted.push('\\');
+            }
+            quoted.push(c);
+        }
+        quoted.push('"');
+        quoted
+    }
+}
+
+#[test]
+fn test_format_authentication_results() {
+    use dkim::{DkimReason, test_dkim_result};
+
+    let mut results = AuthenticationResults::new("mx.example.org");
+    results.add_dkim_result(&test_dkim_result("example.com", None));
+    results.add_dkim_result(&test_dkim_result("example.com", Some(DkimReason::BodyHashMismatch)))
+        .add_arc_result(&ArcResult::pass(2, 2));
+
+    assert_eq!("mx.example.org;\r\n\
+                \tdkim=pass header.d=example.com header.s=brisbane header.i=@example.com;\r\n\
+                \tdkim=fail reason=\"body hash did not verify\" header.d=example.com header.s=brisbane header.i=@example.com;\r\n\
+                \tarc=pass header.oldest-pass=2",
+               results.format());
+
+    assert_eq!("mx.example.org; none", AuthenticationResults::new("mx.example.org").format());
+}
+
+#[test]
+fn test_quote() {
+    assert_eq!("example.com", quote("example.com"));
+    assert_eq!("joe@example.com", quote("joe@example.com"));
+    assert_eq!("\"a \\\"b\\\"; c\"", quote("a \"b\"; c"));
+    assert_eq!("\"\"", quote(""));
+}
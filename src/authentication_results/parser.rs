@@ -0,0 +1,323 @@
+use std::ascii::AsciiExt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use super::{AuthenticationResults, MethodResult, Property};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum AuthenticationResultsParseError {
+    MissingAuthservId,
+    BadVersion(String),
+    MissingMethod,
+    MissingResult(String),
+    BadProperty(String),
+    UnterminatedComment,
+    UnterminatedQuotedString,
+    UnexpectedCharacter(char),
+    UnexpectedEnd
+}
+
+type ParseResult<T> = Result<T, AuthenticationResultsParseError>;
+
+impl AuthenticationResults {
+    // Parses the value of an Authentication-Results header (RFC 8601 section 2.2).
+    // Comments inside a result are kept with it; any others are dropped.
+    pub fn parse(value: &str) -> ParseResult<AuthenticationResults> {
+        let mut lexer = Lexer { chars: value.chars().peekable() };
+
+        try!(lexer.skip_cfws());
+        let authserv_id = try!(lexer.value());
+        if authserv_id.is_empty() {
+            return Err(AuthenticationResultsParseError::MissingAuthservId);
+        }
+        let mut results = AuthenticationResults::new(&authserv_id);
+
+        try!(lexer.skip_cfws());
+        if lexer.peek().map_or(false, |c| c.is_digit(10)) {
+            let version = lexer.token();
+            results.version = Some(try!(version.parse().map_err(|_| AuthenticationResultsParseError::BadVersion(version.clone()))));
+            try!(lexer.skip_cfws());
+        }
+
+        while lexer.peek().is_some() {
+            try!(lexer.expect(';'));
+            let mut comments = try!(lexer.skip_cfws());
+            let method = lexer.keyword();
+            if method.is_empty() {
+                return Err(AuthenticationResultsParseError::MissingMethod);
+            }
+
+            // "none" in place of the first result says no methods were applied
+            if method.eq_ignore_ascii_case("none") && results.results.is_empty() {
+                try!(lexer.skip_cfws());
+                match lexer.peek() {
+                    Some(c) => return Err(AuthenticationResultsParseError::UnexpectedCharacter(c)),
+                    None => break
+                }
+            }
+
+            // only version 1 of each method is defined, so the version is ignored
+            if lexer.peek() == Some('/') {
+                lexer.chars.next();
+                comments.extend(try!(lexer.skip_cfws()).into_iter());
+                lexer.token();
+            }
+
+            let result = try!(lexer.method_result(method, comments));
+            results.add_result(result);
+        }
+
+        Ok(results)
+    }
+
+    // Whether these results were added by one of the hosts we trust.  Anyone
+    // can add an Authentication-Results header, so only trusted ones should
+    // be acted on (RFC 8601 section 5).
+    pub fn is_trusted(&self, trusted_authserv_ids: &[String]) -> bool {
+        trusted_authserv_ids.iter().any(|id| id.eq_ignore_ascii_case(&self.authserv_id))
+    }
+}
+
+// Parses each Authentication-Results header, keeping those from trusted
+// authserv-ids.  Headers that can't be parsed are skipped.
+pub fn trusted_authentication_results(headers: &[(String, String, Vec<u8>)], trusted_authserv_ids: &[String])
+    -> Vec<AuthenticationResults> {
+
+    headers.iter()
+        .filter(|&&(ref name, _, _)| name.trim().eq_ignore_ascii_case("Authentication-Results"))
+        .filter_map(|&(_, ref value, _)| AuthenticationResults::parse(value).ok())
+        .filter(|results| results.is_trusted(trusted_authserv_ids))
+        .collect()
+}
+
+struct Lexer<'a> {
+    chars: Peekable<Chars<'a>>
+}
+
+impl<'a> Lexer<'a> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().cloned()
+    }
+
+    fn expect(&mut self, expected: char) -> ParseResult<()> {
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(AuthenticationResultsParseError::UnexpectedCharacter(c)),
+            None => Err(AuthenticationResultsParseError::UnexpectedEnd)
+        }
+    }
+
+    // Skips whitespace and comments, returning the comments
+    fn skip_cfws(&mut self) -> ParseResult<Vec<String>> {
+        let mut comments = vec![];
+        loop {
+            match self.peek() {
+                Some(' ') | Some('\t') | Some('\r') | Some('\n') => {
+                    self.chars.next();
+                }
+                Some('(') => {
+                    self.chars.next();
+                    comments.push(try!(self.comment()));
+                }
+                _ => return Ok(comments)
+            }
+        }
+    }
+
+    // The text of a comment, after its opening parenthesis.  Nested comments
+    // are kept as part of the text.
+    fn comment(&mut self) -> ParseResult<String> {
+        let mut comment = String::new();
+        let mut depth = 0;
+        loop {
+            match self.chars.next() {
+                Some('\\') => match self.chars.next() {
+                    Some(c) => comment.push(c),
+                    None => return Err(AuthenticationResultsParseError::UnterminatedComment)
+                },
+                Some(')') if depth == 0 => return Ok(comment.trim().to_string()),
+                Some(c) => {
+                    match c {
+                        '(' => depth = depth + 1,
+                        ')' => depth = depth - 1,
+                        _ => ()
+                    }
+                    comment.push(c);
+                }
+                None => return Err(AuthenticationResultsParseError::UnterminatedComment)
+            }
+        }
+    }
+
+    fn read_while<F>(&mut self, pred: F) -> String where F: Fn(char) -> bool {
+        let mut s = String::new();
+        while let Some(c) = self.peek() {
+            if !pred(c) {
+                break;
+            }
+            s.push(c);
+            self.chars.next();
+        }
+        s
+    }
+
+    // A method, ptype or property name
+    fn keyword(&mut self) -> String {
+        self.read_while(|c| c.is_alphanumeric() && c.is_ascii() || c == '-' || c == '_')
+    }
+
+    // A MIME token (RFC 2045)
+    fn token(&mut self) -> String {
+        self.read_while(is_token_char)
+    }
+
+    fn quoted_string(&mut self) -> ParseResult<String> {
+        let mut s = String::new();
+        self.chars.next();
+        loop {
+            match self.chars.next() {
+                Some('\\') => match self.chars.next() {
+                    Some(c) => s.push(c),
+                    None => return Err(AuthenticationResultsParseError::UnterminatedQuotedString)
+                },
+                Some('"') => return Ok(s),
+                Some(c) => s.push(c),
+                None => return Err(AuthenticationResultsParseError::UnterminatedQuotedString)
+            }
+        }
+    }
+
+    // A token or quoted-string
+    fn value(&mut self) -> ParseResult<String> {
+        if self.peek() == Some('"') {
+            self.quoted_string()
+        }
+        else {
+            Ok(self.token())
+        }
+    }
+
+    // A property value, which may also be an address or domain name, and
+    // so can contain '@' and other characters not allowed in a token
+    fn property_value(&mut self) -> ParseResult<String> {
+        if self.peek() == Some('"') {
+            self.quoted_string()
+        }
+        else {
+            Ok(self.read_while(|c| c > ' ' && c < '\x7f' && c != ';' && c != '(' && c != ')' && c != '"'))
+        }
+    }
+
+    // The rest of a resinfo, after the method
+    fn method_result(&mut self, method: String, mut comments: Vec<String>) -> ParseResult<MethodResult> {
+        comments.extend(try!(self.skip_cfws()).into_iter());
+        try!(self.expect('=').map_err(|_| AuthenticationResultsParseError::MissingResult(method.clone())));
+        comments.extend(try!(self.skip_cfws()).into_iter());
+        let result = self.keyword();
+        if result.is_empty() {
+            return Err(AuthenticationResultsParseError::MissingResult(method));
+        }
+
+        let mut method_result = MethodResult::new(&method, &result.to_ascii_lowercase());
+        loop {
+            comments.extend(try!(self.skip_cfws()).into_iter());
+            match self.peek() {
+                None | Some(';') => break,
+                _ => ()
+            }
+
+            let ptype = self.keyword();
+            if ptype.is_empty() {
+                return Err(AuthenticationResultsParseError::UnexpectedCharacter(self.peek().unwrap()));
+            }
+            comments.extend(try!(self.skip_cfws()).into_iter());
+
+            if ptype.eq_ignore_ascii_case("reason") && self.peek() == Some('=') {
+                self.chars.next();
+                comments.extend(try!(self.skip_cfws()).into_iter());
+                method_result.reason = Some(try!(self.value()));
+                continue;
+            }
+
+            try!(self.expect('.').map_err(|_| AuthenticationResultsParseError::BadProperty(ptype.clone())));
+            comments.extend(try!(self.skip_cfws()).into_iter());
+            let property = self.keyword();
+            comments.extend(try!(self.skip_cfws()).into_iter());
+            try!(self.expect('=').map_err(|_| AuthenticationResultsParseError::BadProperty(format!("{}.{}", ptype, property))));
+            comments.extend(try!(self.skip_cfws()).into_iter());
+            let value = try!(self.property_value());
+
+            method_result.properties.push(Property {
+                ptype: ptype.to_ascii_lowercase(),
+                property: property.to_ascii_lowercase(),
+                value: value
+            });
+        }
+
+        method_result.comments = comments;
+        Ok(method_result)
+    }
+}
+
+fn is_token_char(c: char) -> bool {
+    c > ' ' && c < '\x7f' && !"()<>@,;:\\\"/[]?=".contains(c)
+}
+
+#[test]
+fn test_parse_authentication_results() {
+    let results = AuthenticationResults::parse(
+        "mx.example.org 1;\r\n\
+         \tdkim=pass (good signature) header.d=example.com header.i=@example.com;\r\n\
+         \tspf=fail reason=\"not in the list\" smtp.mailfrom=joe@example.com;\r\n\
+         \tdkim/1 = fail ( bad (nested) ) header . s = brisbane").unwrap();
+
+    assert_eq!("mx.example.org", results.authserv_id);
+    assert_eq!(Some(1), results.version);
+    assert_eq!(3, results.results.len());
+
+    let dkim = &results.results[0];
+    assert_eq!(("dkim", "pass"), (&dkim.method[..], &dkim.result[..]));
+    assert_eq!(vec!["good signature".to_string()], dkim.comments);
+    assert_eq!(Property { ptype: "header".to_string(), property: "d".to_string(), value: "example.com".to_string() },
+               dkim.properties[0]);
+    assert_eq!("@example.com", dkim.properties[1].value);
+
+    let spf = &results.results[1];
+    assert_eq!(Some("not in the list".to_string()), spf.reason);
+    assert_eq!(("smtp", "mailfrom", "joe@example.com"),
+               (&spf.properties[0].ptype[..], &spf.properties[0].property[..], &spf.properties[0].value[..]));
+
+    let dkim = &results.results[2];
+    assert_eq!("fail", dkim.result);
+    assert_eq!(vec!["bad (nested)".to_string()], dkim.comments);
+    assert_eq!("brisbane", dkim.properties[0].value);
+
+    let results = AuthenticationResults::parse("\"mx example\" (no checks); none").unwrap();
+    assert_eq!("mx example", results.authserv_id);
+    assert!(results.results.is_empty());
+
+    assert_eq!(Err(AuthenticationResultsParseError::MissingAuthservId), AuthenticationResults::parse("; dkim=pass"));
+    assert_eq!(Err(AuthenticationResultsParseError::MissingResult("dkim".to_string())),
+               AuthenticationResults::parse("mx.example.org; dkim"));
+    assert_eq!(Err(AuthenticationResultsParseError::UnterminatedComment),
+               AuthenticationResults::parse("mx.example.org; dkim=pass (oops"));
+}
+
+#[test]
+fn test_trusted_authentication_results() {
+    let header = |value: &str| ("Authentication-Results".to_string(), value.to_string(), vec![]);
+    let headers = vec![header("mx.example.org; dkim=pass header.d=example.com"),
+                       header("evil.example.net; dkim=pass header.d=example.com"),
+                       header("MX.example.org; spf=pass smtp.mailfrom=example.com"),
+                       header("mx.example.org; dkim")];
+
+    let results = trusted_authentication_results(&headers, &["mx.example.org".to_string()]);
+    assert_eq!(2, results.len());
+    assert_eq!("dkim", results[0].results[0].method);
+    assert_eq!("spf", results[1].results[0].method);
+
+    // the formatted header parses back to the same results
+    let mut formatted = results[0].clone();
+    formatted.results[0].reason = Some("key (2048 bits)".to_string());
+    assert_eq!(Ok(formatted.clone()), AuthenticationResults::parse(&formatted.format()));
+}
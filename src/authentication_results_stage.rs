@@ -0,0 +1,132 @@
+use std::mem;
+
+use events::MessageParserEvent;
+use events::MessageParserStage;
+use events::MessageParserEvent::{Header, HeaderName, HeaderValue, EndOfHeaders, DkimResult, ArcResult, SpfResult, DmarcResult, End};
+
+use authentication_results::{AuthenticationResults, MethodResult};
+
+// Adds an Authentication-Results header reporting the DkimResult, ArcResult,
+// SpfResult and DmarcResult events from earlier stages.  Those only arrive at the end of
+// the message, so it's held back until End and the header is inserted just
+// before EndOfHeaders.
+//
+// Holding it back means every event of the message is kept in memory until
+// End, body chunks included, so a message costs a little more than its own
+// size.  Nothing here limits that: the size of the messages fed to the
+// pipeline should be bounded before they get this far, as an SMTP server
+// does with SIZE.
+pub struct AuthenticationResultsStage<'a> {
+    authserv_id: String,
+    events: Vec<MessageParserEvent>,
+    results: AuthenticationResults,
+    next_stage: &'a mut (MessageParserStage + 'a)
+}
+
+impl<'a> AuthenticationResultsStage<'a> {
+    pub fn new(next_stage: &'a mut MessageParserStage, authserv_id: &str) -> AuthenticationResultsStage<'a> {
+        AuthenticationResultsStage {
+            authserv_id: authserv_id.to_string(),
+            events: vec![],
+            results: AuthenticationResults::new(authserv_id),
+            next_stage: next_stage
+        }
+    }
+
+    fn emit_header(&mut self) {
+        let mut results = mem::replace(&mut self.results, AuthenticationResults::new(&self.authserv_id));
+        if !results.results.iter().any(|r| r.method == "dkim") {
+            results.add_result(MethodResult::new("dkim", "none"));
+        }
+
+        let name = "Authentication-Results".to_string();
+        let value = results.format();
+        let raw = format!("{}: {}\r\n", name, value).into_bytes();
+        self.next_stage.process_event(HeaderName(format!("{}:", name)));
+        self.next_stage.process_event(HeaderValue(format!(" {}\r\n", value)));
+        self.next_stage.process_event(Header(name, value, raw));
+    }
+
+    fn replay(&mut self) {
+        let mut emitted = false;
+        for e in mem::replace(&mut self.events, vec![]).into_iter() {
+            match e {
+                EndOfHeaders if !emitted => {
+                    self.emit_header();
+                    emitted = true;
+                }
+                _ => ()
+            }
+            self.next_stage.process_event(e);
+        }
+
+        // a message with no body never sees EndOfHeaders
+        if !emitted {
+            self.emit_header();
+        }
+    }
+}
+
+impl<'a> MessageParserStage for AuthenticationResultsStage<'a> {
+    fn process_event(&mut self, event: MessageParserEvent) {
+        match event {
+            DkimResult(ref result) => {
+                self.results.add_dkim_result(result);
+            }
+            ArcResult(ref result) => {
+                self.results.add_arc_result(result);
+            }
+            SpfResult(ref result) => {
+                self.results.add_spf_result(result);
+            }
+            DmarcResult(ref result) => {
+                self.results.add_dmarc_result(result);
+            }
+            End => {
+                self.replay();
+                self.next_stage.process_event(End);
+                return;
+            }
+            _ => ()
+        }
+        self.events.push(event);
+    }
+}
+
+#[test]
+fn authentication_results_stage_test() {
+    use message_parser_sink::MessageParserSink;
+    use reader_parser::ReaderParser;
+    use message_scanner::MessageScanner;
+    use header_parser::HeaderParser;
+    use dkim_checker::DkimChecker;
+    use events::MessageParserFilter;
+    use dns::ZoneFile;
+    use dkim::TEST_PUBLIC_KEY;
+    use dkim_checker::TEST_MESSAGE;
+
+    let mut keys = ZoneFile::new();
+    keys.add_txt("brisbane._domainkey.example.com", &format!("v=DKIM1; k=rsa; p={}", TEST_PUBLIC_KEY));
+
+    let mut sink = MessageParserSink::new();
+    {
+        let r = TEST_MESSAGE.as_bytes();
+        let mut stage = AuthenticationResultsStage::new(&mut sink, "mx.example.net");
+        let mut dkim = DkimChecker::new(&mut stage, &keys);
+        let mut parser: HeaderParser = MessageParserFilter::new(&mut dkim);
+        let mut scanner: MessageScanner = MessageParserFilter::new(&mut parser);
+        let mut rp = ReaderParser::new(&mut scanner, r);
+
+        rp.read_to_end();
+    }
+
+    let events = sink.events();
+    let end_of_headers = events.iter().position(|e| *e == EndOfHeaders).unwrap();
+    assert_eq!(Header("Authentication-Results".to_string(),
+                      "mx.example.net;\r\n\tdkim=pass header.d=example.com header.s=brisbane \
+                       header.i=@example.com".to_string(),
+                      b"Authentication-Results: mx.example.net;\r\n\tdkim=pass header.d=example.com \
+                        header.s=brisbane header.i=@example.com\r\n".to_vec()),
+               events[end_of_headers - 1]);
+    assert_eq!(End, events[events.len() - 1]);
+}
@@ -0,0 +1,40 @@
+use time;
+
+// A source of the current time, in seconds since the Unix epoch.  Checks
+// that depend on the time take a Clock so that tests, and the replay of
+// archived mail, can pin it.
+pub trait Clock {
+    fn now(&self) -> u64;
+}
+
+pub struct SystemClock;
+
+pub static SYSTEM_CLOCK: SystemClock = SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        time::get_time().sec as u64
+    }
+}
+
+pub struct FixedClock {
+    time: u64
+}
+
+impl FixedClock {
+    pub fn new(time: u64) -> FixedClock {
+        FixedClock { time: time }
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> u64 {
+        self.time
+    }
+}
+
+#[test]
+fn test_clocks() {
+    assert_eq!(1117574938, FixedClock::new(1117574938).now());
+    assert!(SYSTEM_CLOCK.now() > 1117574938);
+}
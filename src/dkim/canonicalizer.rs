@@ -1,5 +1,4 @@
 use std::ops::Index;
-use std::char;
 use std::ascii::AsciiExt;
 
 #[derive(Debug,Clone)]
@@ -35,17 +34,32 @@ pub trait HeaderCanonicalizer {
 }
 
 struct SimpleBodyCanonicalizer {
-    pending_newlines: usize
+    pending_newlines: usize,
+    // a chunk ended with a CR, which may be the start of a CRLF
+    pending_cr: bool
 }
 
 impl SimpleBodyCanonicalizer {
     fn new() -> SimpleBodyCanonicalizer {
-        SimpleBodyCanonicalizer { pending_newlines: 0 } 
+        SimpleBodyCanonicalizer { pending_newlines: 0, pending_cr: false } 
     }
 }
 
 impl BodyCanonicalizer for SimpleBodyCanonicalizer {
     fn canonicalize(&mut self, input: &Vec<u8>) -> Vec<u8> {
+        let mut data = vec![];
+        if self.pending_cr {
+            data.push(b'\r');
+        }
+        data = data + input;
+        self.pending_cr = data.last() == Some(&b'\r');
+        if self.pending_cr {
+            data.pop();
+        }
+        if data.is_empty() {
+            return data;
+        }
+
         let mut output = vec![];
         for _ in (0 .. self.pending_newlines ) {
             output.push(b'\r');
@@ -53,7 +67,7 @@ impl BodyCanonicalizer for SimpleBodyCanonicalizer {
         }
         self.pending_newlines = 0;
 
-        output = output + input;
+        output = output + &data[..];
 
         while output.len() >= 2 &&
                *output.index(output.len() - 1) == b'\n' &&
@@ -66,20 +80,36 @@ impl BodyCanonicalizer for SimpleBodyCanonicalizer {
         output
     }
 
+    // An empty body is canonicalized as a single CRLF (RFC 6376 section 3.4.3)
     fn flush(&mut self) -> Vec<u8> {
+        let mut output = vec![];
+        if self.pending_cr {
+            for _ in (0 .. self.pending_newlines ) {
+                output.push(b'\r');
+                output.push(b'\n');
+            }
+            output.push(b'\r');
+        }
         self.pending_newlines = 0;
-        vec![b'\r', b'\n']
+        self.pending_cr = false;
+        output + b"\r\n"
     }
 }
 
 struct RelaxedBodyCanonicalizer {
     pending_newlines: usize,
-    ws: bool
+    ws: bool,
+    // whether anything but empty lines has been output
+    started: bool,
+    // the last byte seen was a CR, which only ends a line if an LF follows
+    pending_cr: bool
 }
 
 impl RelaxedBodyCanonicalizer {
     fn new() -> RelaxedBodyCanonicalizer {
-        RelaxedBodyCanonicalizer { pending_newlines: 0, ws: false }
+        RelaxedBodyCanonicalizer {
+            pending_newlines: 0, ws: false, started: false, pending_cr: false
+        }
     }
 
     fn flush_newlines(&mut self, output: &mut Vec<u8>) {
@@ -88,46 +118,54 @@ impl RelaxedBodyCanonicalizer {
             output.push(b'\n');
         }
         self.pending_newlines = 0;
+        self.started = true;
+    }
+
+    // Output a byte that is neither WSP nor part of a line ending, preceded by
+    // any newlines and whitespace it makes significant
+    fn push_byte(&mut self, byte: u8, output: &mut Vec<u8>) {
+        self.flush_newlines(output);
+        if self.ws {
+            output.push(b' ');
+            self.ws = false;
+        }
+        output.push(byte);
     }
 
+    // A CR not followed by LF is an ordinary character (RFC 6376 section 3.4.4
+    // only treats CRLF as a line ending)
+    fn push_pending_cr(&mut self, output: &mut Vec<u8>) {
+        if self.pending_cr {
+            self.pending_cr = false;
+            self.push_byte(b'\r', output);
+        }
+    }
 }
 
 impl BodyCanonicalizer for RelaxedBodyCanonicalizer {
+    // Only SP and HTAB count as whitespace (RFC 6376 section 3.4.4); any other
+    // byte, ASCII or not, is passed through
     fn canonicalize(&mut self, input: &Vec<u8>) -> Vec<u8> {
         let mut output = vec![];
 
         for i in input.iter() {
-            match char::from_u32(*i as u32) {
-                Some(c) => {
-                    if c == '\r' {
-                        // do nothing
-                    }
-                    else if c == '\n' {
-                        self.ws = false;
-                        self.pending_newlines = self.pending_newlines + 1;
-                    }
-                    else if self.ws {
-                        self.flush_newlines(&mut output);
-                        if !c.is_whitespace() {
-                            output.push(b' ');
-                            output.push(*i);
-                            self.ws = false;
-                        }
-                    }
-                    else {
-                        self.flush_newlines(&mut output);
-                        self.ws = c.is_whitespace();
-                        if !self.ws {
-                            output.push(*i);
-                        }
-                    }
+            match *i {
+                b'\r' => {
+                    self.push_pending_cr(&mut output);
+                    self.pending_cr = true;
                 }
-                None => {
-                    // an invalid character is techinically invalid, but we 
-                    // don't need to enforce that here:  just pass it through
-                    self.flush_newlines(&mut output);
-                    output.push(*i);
+                b'\n' => {
+                    self.pending_cr = false;
                     self.ws = false;
+                    self.pending_newlines = self.pending_newlines + 1;
+                }
+                b' ' | b'\t' => {
+                    self.push_pending_cr(&mut output);
+                    self.ws = true;
+                }
+                _ => {
+                    self.push_pending_cr(&mut output);
+                    self.push_byte(*i, &mut output);
                 }
             }
         }
@@ -135,9 +173,20 @@ impl BodyCanonicalizer for RelaxedBodyCanonicalizer {
         output
     }
 
+    // An empty body, or one of only empty lines, stays empty; anything else
+    // ends with a CRLF
     fn flush(&mut self) -> Vec<u8> {
+        let mut output = vec![];
+        self.push_pending_cr(&mut output);
+        let started = self.started;
         self.pending_newlines = 0;
-        vec![b'\r', b'\n']
+        self.ws = false;
+        self.started = false;
+        if started {
+            output.push(b'\r');
+            output.push(b'\n');
+        }
+        output
     }
 }
 
@@ -164,26 +213,37 @@ impl RelaxedHeaderCanonicalizer {
 }
 
 impl HeaderCanonicalizer for RelaxedHeaderCanonicalizer {
-    fn canonicalize(&mut self, name: String, value: String, _: Vec<u8> ) -> Vec<u8> {
-        let mut result = name.as_bytes().to_ascii_lowercase();
+    // Works from the raw header, as received, rather than the parsed value
+    // (RFC 6376 section 3.4.2).  Bytes that aren't ASCII are passed through.
+    fn canonicalize(&mut self, name: String, value: String, raw: Vec<u8>) -> Vec<u8> {
+        let (name, value) = match raw.iter().position(|&b| b == b':') {
+            Some(colon) => (raw[..colon].to_vec(), raw[colon + 1..].to_vec()),
+            None => (name.into_bytes(), value.into_bytes())
+        };
+
+        // no whitespace before the colon...
+        let name_end = name.iter().rposition(|&b| b != b' ' && b != b'\t').map_or(0, |i| i + 1);
+        let mut result = name[..name_end].to_ascii_lowercase();
         result.push(b':');
 
+        // ...or after it, or at the end of the value.  Line breaks are
+        // unfolded, and each run of whitespace inside the value becomes a
+        // single space.
         let mut ws = false;
-        for b in value.as_bytes() {
-            let c = match char::from_u32(*b as u32) {
-                Some(x) => x,
-                None => panic!("Could not decode character")
-            };
-            if c.is_whitespace() {
-                if !ws {
-                    ws = true;
-                    result.push(b' ');
+        let mut started = false;
+        for b in value.iter() {
+            match *b {
+                b'\r' | b'\n' => (),
+                b' ' | b'\t' => ws = true,
+                _ => {
+                    if ws && started {
+                        result.push(b' ');
+                    }
+                    ws = false;
+                    started = true;
+                    result.push(*b);
                 }
             }
-            else {
-                ws = false;
-                result.push(*b);
-            }
         }
         result + b"\r\n"
     }
@@ -248,3 +308,179 @@ fn test_relaxed_header_canonicalization() {
 
     assert_eq!(from_utf8(b"test-header:Test-Value test\r\n"), from_utf8(&result));
 }
+
+// The example of RFC 6376 section 3.4.5
+#[cfg(test)]
+const RFC_EXAMPLE_HEADERS: [&'static [u8]; 2] = [b"A: X\r\n", b"B : Y\t\r\n\tZ  \r\n"];
+#[cfg(test)]
+const RFC_EXAMPLE_BODY: &'static [u8] = b" C \r\nD \t E\r\n\r\n\r\n";
+
+#[cfg(test)]
+fn canonicalize_headers(canon: &mut HeaderCanonicalizer, headers: &[&[u8]]) -> Vec<u8> {
+    let mut result = vec![];
+    for raw in headers.iter() {
+        let text = String::from_utf8_lossy(raw).into_owned();
+        let colon = text.find(':').unwrap();
+        let name = text[..colon].to_string();
+        let value = text[colon + 1..].trim().to_string();
+        result.extend(canon.canonicalize(name, value, raw.to_vec()));
+    }
+    result
+}
+
+#[test]
+fn test_rfc_canonicalization_examples() {
+    let mut simple = SimpleHeaderCanonicalizer::new();
+    assert_eq!(b"A: X\r\nB : Y\t\r\n\tZ  \r\n".to_vec(), canonicalize_headers(&mut simple, &RFC_EXAMPLE_HEADERS));
+    let mut relaxed = RelaxedHeaderCanonicalizer::new();
+    assert_eq!(b"a:X\r\nb:Y Z\r\n".to_vec(), canonicalize_headers(&mut relaxed, &RFC_EXAMPLE_HEADERS));
+
+    let mut simple = SimpleBodyCanonicalizer::new();
+    let result = simple.canonicalize(&RFC_EXAMPLE_BODY.to_vec()) + &simple.flush()[..];
+    assert_eq!(b" C \r\nD \t E\r\n".to_vec(), result);
+    let mut relaxed = RelaxedBodyCanonicalizer::new();
+    let result = relaxed.canonicalize(&RFC_EXAMPLE_BODY.to_vec()) + &relaxed.flush()[..];
+    assert_eq!(b" C\r\nD E\r\n".to_vec(), result);
+}
+
+#[test]
+fn test_relaxed_header_canonicalization_raw_bytes() {
+    let mut canon = RelaxedHeaderCanonicalizer::new();
+    let mut canonicalize = |raw: &[u8]| canonicalize_headers(&mut canon, &[raw]);
+
+    // bytes that aren't ASCII are kept as they are, even a UTF-8 no-break space
+    assert_eq!(b"subject:Caf\xc3\xa9 \xc2\xa0bar\r\n".to_vec(),
+               canonicalize(b"Subject: Caf\xc3\xa9 \t \xc2\xa0bar\r\n"));
+    assert_eq!(b"to:joe@example.com, sue@example.com\r\n".to_vec(),
+               canonicalize(b"TO \t:\r\n joe@example.com,\r\n\t sue@example.com \t\r\n"));
+    assert_eq!(b"x-empty:\r\n".to_vec(), canonicalize(b"X-Empty: \r\n"));
+}
+
+// Reads at most chunk_size bytes at a time
+#[cfg(test)]
+struct ChunkedReader<'a> {
+    data: &'a [u8],
+    chunk_size: usize
+}
+
+#[cfg(test)]
+impl<'a> ::std::io::Read for ChunkedReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        let n = *[self.chunk_size, buf.len(), self.data.len()].iter().min().unwrap();
+        for i in 0..n {
+            buf[i] = self.data[i];
+        }
+        self.data = &self.data[n..];
+        Ok(n)
+    }
+}
+
+// Runs a message through the parser, read chunk_size bytes at a time, and
+// returns its headers and body
+#[cfg(test)]
+fn parse_test_message(message: &[u8], chunk_size: usize) -> (Vec<(String, String, Vec<u8>)>, Vec<u8>) {
+    use message_parser_sink::MessageParserSink;
+    use reader_parser::ReaderParser;
+    use message_scanner::MessageScanner;
+    use header_parser::HeaderParser;
+    use events::MessageParserFilter;
+    use events::MessageParserEvent::{Header, BodyChunk};
+
+    let mut sink = MessageParserSink::new();
+    {
+        let reader = ChunkedReader { data: message, chunk_size: chunk_size };
+        let mut parser: HeaderParser = MessageParserFilter::new(&mut sink);
+        let mut scanner: MessageScanner = MessageParserFilter::new(&mut parser);
+        let mut rp = ReaderParser::new(&mut scanner, reader);
+
+        rp.read_to_end();
+    }
+
+    let mut headers = vec![];
+    let mut body = vec![];
+    for event in sink.events().into_iter() {
+        match event {
+            Header(name, value, raw) => headers.push((name, value, raw)),
+            BodyChunk(chunk) => body.extend(chunk.into_iter()),
+            _ => ()
+        }
+    }
+    (headers, body)
+}
+
+// Every way of cutting data in two, and of cutting it into equal chunks
+#[cfg(test)]
+fn chunk_splits(data: &[u8]) -> Vec<Vec<&[u8]>> {
+    let mut splits = vec![];
+    for i in 0..data.len() + 1 {
+        splits.push(vec![&data[..i], &data[i..]]);
+    }
+    for size in 1..data.len() + 1 {
+        splits.push(data.chunks(size).collect());
+    }
+    splits
+}
+
+#[cfg(test)]
+fn check_canonicalized(case: &::std::path::Path, what: &str, expected: &[u8], actual: &[u8]) {
+    if expected != actual {
+        panic!("{}: {}: expected {:?}, got {:?}", case.display(), what,
+               String::from_utf8_lossy(expected), String::from_utf8_lossy(actual));
+    }
+}
+
+// Each directory of data/canonicalization holds a message, and its headers
+// and body as each canonicalization should produce them.  Every canonicalizer
+// has to produce them however the input is split up.
+#[test]
+fn test_canonicalization_corpus() {
+    use std::fs;
+    use std::fs::File;
+    use std::io::Read;
+    use std::path::{Path, PathBuf};
+
+    let read_file = |path: PathBuf| {
+        let mut data = vec![];
+        File::open(&path).and_then(|mut f| f.read_to_end(&mut data)).unwrap();
+        data
+    };
+
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("data").join("canonicalization");
+    let mut cases: Vec<PathBuf> = fs::read_dir(&corpus).unwrap().map(|e| e.unwrap().path()).collect();
+    cases.sort();
+    assert!(!cases.is_empty());
+
+    let canon_types = [(CanonicalizationType::Simple, "simple"), (CanonicalizationType::Relaxed, "relaxed")];
+    for case in cases.iter() {
+        let message = read_file(case.join("message"));
+
+        for &(ref canon_type, canon_name) in canon_types.iter() {
+            let expected_headers = read_file(case.join(&format!("{}-headers", canon_name)));
+            let expected_body = read_file(case.join(&format!("{}-body", canon_name)));
+
+            for &chunk_size in [1, 2, 3, 7, 64, 4096].iter() {
+                let (headers, body) = parse_test_message(&message, chunk_size);
+
+                let mut canon = Canonicalizer::head(canon_type.clone());
+                let mut canonicalized = vec![];
+                for &(ref name, ref value, ref raw) in headers.iter() {
+                    canonicalized.extend(canon.canonicalize(name.clone(), value.clone(), raw.clone()));
+                }
+                check_canonicalized(case, &format!("{} headers read {} bytes at a time", canon_name, chunk_size),
+                                    &expected_headers, &canonicalized);
+
+                for chunks in chunk_splits(&body).iter() {
+                    let mut canon = Canonicalizer::body(canon_type.clone());
+                    let mut canonicalized = vec![];
+                    for chunk in chunks.iter() {
+                        canonicalized.extend(canon.canonicalize(&chunk.to_vec()));
+                    }
+                    canonicalized.extend(canon.flush());
+                    let sizes: Vec<usize> = chunks.iter().map(|c| c.len()).collect();
+                    check_canonicalized(case, &format!("{} body in chunks of {:?}", canon_name, sizes),
+                                        &expected_body, &canonicalized);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::ascii::AsciiExt;
+
+use super::canonicalizer::{Canonicalizer, CanonicalizationType};
+
+// A signed header that differs between the copy in the signature's z= tag
+// and the message as it was received
+#[derive(Debug, PartialEq, Clone)]
+pub struct DkimHeaderChange {
+    pub name: String,
+    // both values are in relaxed canonical form
+    pub signed: String,
+    // None if the header is no longer present
+    pub received: Option<String>
+}
+
+// Decodes a z= tag into header names and values.  The tag is diagnostic
+// only, so a copy that can't be decoded is ignored rather than treated as
+// an error.
+pub fn decode_copied_headers(z: &str) -> Option<Vec<(String, String)>> {
+    let z: String = z.chars().filter(|c| !c.is_whitespace()).collect();
+    let mut headers = vec![];
+
+    for copy in z.split('|') {
+        let decoded = match decode_quoted_printable(copy) {
+            Some(d) => String::from_utf8_lossy(&d).into_owned(),
+            None => return None
+        };
+        let mut split = decoded.splitn(2, ':');
+        match (split.next(), split.next()) {
+            (Some(name), Some(value)) => headers.push((name.to_string(), value.to_string())),
+            _ => return None
+        }
+    }
+
+    Some(headers)
+}
+
+// DKIM-Quoted-Printable (RFC 6376 section 2.11), with whitespace already removed
+fn decode_quoted_printable(encoded: &str) -> Option<Vec<u8>> {
+    let bytes = encoded.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'=' {
+            if i + 2 >= bytes.len() {
+                return None;
+            }
+            let hex = match ::std::str::from_utf8(&bytes[i + 1..i + 3]) {
+                Ok(h) => h,
+                Err(_) => return None
+            };
+            match u8::from_str_radix(hex, 16) {
+                Ok(b) => decoded.push(b),
+                Err(_) => return None
+            }
+            i = i + 3;
+        }
+        else {
+            decoded.push(bytes[i]);
+            i = i + 1;
+        }
+    }
+
+    Some(decoded)
+}
+
+// Compares the copied headers against the received ones.  Like h=, each copy
+// is matched against the bottom-most received header of that name that
+// hasn't already been matched.  Relaxed canonicalization is used whatever
+// the signature specifies, since signers don't preserve the whitespace of
+// the copies.
+pub fn changed_headers(copied: &[(String, String)], 
+                       headers: &[(String, String, Vec<u8>)]) -> Vec<DkimHeaderChange> {
+    let mut canon = Canonicalizer::head(CanonicalizationType::Relaxed);
+    let mut canonicalize = |name: &str, value: &str, raw: Vec<u8>| {
+        let canonicalized = canon.canonicalize(name.to_string(), value.trim().to_string(), raw);
+        String::from_utf8_lossy(&canonicalized).trim_right_matches("\r\n").to_string()
+    };
+
+    let mut used = vec![false; headers.len()];
+    let mut changes = vec![];
+    for &(ref name, ref value) in copied.iter() {
+        let signed = canonicalize(name, value, format!("{}:{}\r\n", name, value).into_bytes());
+
+        let found = (0..headers.len()).rev().find(|&i| {
+            !used[i] && headers[i].0.trim().eq_ignore_ascii_case(name.trim())
+        });
+        let received = match found {
+            Some(i) => {
+                used[i] = true;
+                let (ref name, ref value, ref raw) = headers[i];
+                Some(canonicalize(name, value, raw.clone()))
+            }
+            None => None
+        };
+
+        if received.as_ref() != Some(&signed) {
+            changes.push(DkimHeaderChange { name: name.clone(), signed: signed, received: received });
+        }
+    }
+
+    changes
+}
+
+#[test]
+fn test_decode_copied_headers() {
+    assert_eq!(Some(vec![("From".to_string(), "foo@eng.example.net".to_string()),
+                         ("To".to_string(), "joe@example.com".to_string()),
+                         ("Subject".to_string(), "demo run".to_string()),
+                         ("Date".to_string(), "July 5, 2005 3:44:08 PM -0700".to_string())]),
+               decode_copied_headers("From:foo@eng.example.net|To:joe@example.com|\r\n \
+                                      Subject:demo=20run|Date:July=205,=202005=203:44:08=20PM=20-0700"));
+
+    assert_eq!(None, decode_copied_headers("From:foo=2"));
+    assert_eq!(None, decode_copied_headers("From:foo=ZZbar"));
+    assert_eq!(None, decode_copied_headers("From"));
+}
+
+#[test]
+fn test_changed_headers() {
+    let copied = decode_copied_headers("From:joe@example.com|Subject:Is=20dinner=20ready?|\
+                                        Subject:lunch|Date:today").unwrap();
+    let headers: Vec<(String, String, Vec<u8>)> = vec![
+        ("From", "joe@example.com", "From:  joe@example.com\r\n"),
+        ("Subject", "lunch", "Subject: lunch\r\n"),
+        ("Subject", "[SPAM] Is dinner ready?", "Subject: [SPAM] Is dinner ready?\r\n")].iter()
+        .map(|&(n, v, r)| (n.to_string(), v.to_string(), r.bytes().collect())).collect();
+
+    assert_eq!(vec![DkimHeaderChange { name: "Subject".to_string(), 
+                                       signed: "subject:Is dinner ready?".to_string(),
+                                       received: Some("subject:[SPAM] Is dinner ready?".to_string()) },
+                    DkimHeaderChange { name: "Date".to_string(), 
+                                       signed: "date:today".to_string(),
+                                       received: None }],
+               changed_headers(&copied, &headers));
+}
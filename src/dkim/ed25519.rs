@@ -0,0 +1,479 @@
+// Ed25519 (RFC 8032) signing and verification, ported from TweetNaCl.
+//
+// Field elements are sixteen signed 16 bit limbs, and points are in extended
+// coordinates (X, Y, Z, T).  As in TweetNaCl, nothing branches on secret data.
+
+use super::openssl::crypto::hash::hash;
+use super::openssl::crypto::hash::Type::SHA512;
+
+type Gf = [i64; 16];
+type Point = [Gf; 4];
+
+const GF0: Gf = [0; 16];
+const GF1: Gf = [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+// -121665/121666
+const D: Gf = [0x78a3, 0x1359, 0x4dca, 0x75eb, 0xd8ab, 0x4141, 0x0a4d, 0x0070,
+               0xe898, 0x7779, 0x4079, 0x8cc7, 0xfe73, 0x2b6f, 0x6cee, 0x5203];
+const D2: Gf = [0xf159, 0x26b2, 0x9b94, 0xebd6, 0xb156, 0x8283, 0x149a, 0x00e0,
+                0xd130, 0xeef3, 0x80f2, 0x198e, 0xfce7, 0x56df, 0xd9dc, 0x2406];
+// the base point
+const X: Gf = [0xd51a, 0x8f25, 0x2d60, 0xc956, 0xa7b2, 0x9525, 0xc760, 0x692c,
+               0xdc5c, 0xfdd6, 0xe231, 0xc0a4, 0x53fe, 0xcd6e, 0x36d3, 0x2169];
+const Y: Gf = [0x6658, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666,
+               0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666];
+// sqrt(-1)
+const I: Gf = [0xa0b0, 0x4a0e, 0x1b27, 0xc4ee, 0xe478, 0xad2f, 0x1806, 0x2f43,
+               0xd7a7, 0x3dfb, 0x0099, 0x2b4d, 0xdf0b, 0x4fc1, 0x2480, 0x2b83];
+
+// the order of the base point, little endian
+const L: [i64; 32] = [0xed, 0xd3, 0xf5, 0x5c, 0x1a, 0x63, 0x12, 0x58,
+                      0xd6, 0x9c, 0xf7, 0xa2, 0xde, 0xf9, 0xde, 0x14,
+                      0, 0, 0, 0, 0, 0, 0, 0,
+                      0, 0, 0, 0, 0, 0, 0, 0x10];
+
+// A public key that is known to decode to a point on the curve
+#[derive(Clone, PartialEq, Debug)]
+pub struct PublicKey {
+    bytes: Vec<u8>
+}
+
+impl PublicKey {
+    pub fn from_bytes(bytes: &[u8]) -> Option<PublicKey> {
+        if bytes.len() != 32 {
+            return None;
+        }
+        match unpack_neg(bytes) {
+            Some(_) => Some(PublicKey { bytes: bytes.to_vec() }),
+            None => None
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    // RFC 8032 section 5.1.7
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        if signature.len() != 64 || !scalar_is_canonical(&signature[32..]) {
+            return false;
+        }
+        let mut q = match unpack_neg(&self.bytes) {
+            Some(q) => q,
+            None => return false
+        };
+
+        let h = reduce(&sha512(&[&signature[..32], &self.bytes, message]));
+        let mut p = scalar_mult(&mut q, &h);
+        let mut s = [0u8; 32];
+        for i in 0..32 {
+            s[i] = signature[32 + i];
+        }
+        let sb = scalar_base(&s);
+        add(&mut p, &sb);
+
+        let r = pack(&p);
+        let mut diff = 0;
+        for i in 0..32 {
+            diff |= r[i] ^ signature[i];
+        }
+        diff == 0
+    }
+}
+
+pub struct SecretKey {
+    // the clamped secret scalar, then the prefix used to derive nonces
+    expanded: Vec<u8>,
+    public_key: PublicKey
+}
+
+impl SecretKey {
+    // RFC 8032 section 5.1.5
+    pub fn from_seed(seed: &[u8]) -> Option<SecretKey> {
+        if seed.len() != 32 {
+            return None;
+        }
+        let mut expanded = sha512(&[seed]);
+        expanded[0] &= 248;
+        expanded[31] &= 127;
+        expanded[31] |= 64;
+
+        let mut a = [0u8; 32];
+        for i in 0..32 {
+            a[i] = expanded[i];
+        }
+        let public_key = PublicKey { bytes: pack(&scalar_base(&a)).to_vec() };
+        Some(SecretKey { expanded: expanded, public_key: public_key })
+    }
+
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    // RFC 8032 section 5.1.6
+    pub fn sign(&self, message: &[u8]) -> Vec<u8> {
+        let r = reduce(&sha512(&[&self.expanded[32..], message]));
+        let big_r = pack(&scalar_base(&r));
+        let h = reduce(&sha512(&[&big_r, &self.public_key.bytes, message]));
+
+        let mut x = [0i64; 64];
+        for i in 0..32 {
+            x[i] = r[i] as i64;
+        }
+        for i in 0..32 {
+            for j in 0..32 {
+                x[i + j] += h[i] as i64 * self.expanded[j] as i64;
+            }
+        }
+
+        let mut signature = big_r.to_vec();
+        signature.extend(mod_l(&mut x).iter().cloned());
+        signature
+    }
+}
+
+fn sha512(parts: &[&[u8]]) -> Vec<u8> {
+    let mut data = vec![];
+    for part in parts.iter() {
+        data.extend(part.iter().cloned());
+    }
+    hash(SHA512, &data)
+}
+
+fn car25519(o: &mut Gf) {
+    for i in 0..16 {
+        o[i] += 1 << 16;
+        let c = o[i] >> 16;
+        if i < 15 {
+            o[i + 1] += c - 1;
+        }
+        else {
+            o[0] += 38 * (c - 1);
+        }
+        o[i] -= c << 16;
+    }
+}
+
+// Swaps p and q if b is 1, without branching on b
+fn select(p: &mut Gf, q: &mut Gf, b: i64) {
+    let c = !(b - 1);
+    for i in 0..16 {
+        let t = c & (p[i] ^ q[i]);
+        p[i] ^= t;
+        q[i] ^= t;
+    }
+}
+
+fn pack25519(n: &Gf) -> [u8; 32] {
+    let mut t = *n;
+    car25519(&mut t);
+    car25519(&mut t);
+    car25519(&mut t);
+    for _ in 0..2 {
+        let mut m = GF0;
+        m[0] = t[0] - 0xffed;
+        for i in 1..15 {
+            m[i] = t[i] - 0xffff - ((m[i - 1] >> 16) & 1);
+            m[i - 1] &= 0xffff;
+        }
+        m[15] = t[15] - 0x7fff - ((m[14] >> 16) & 1);
+        let b = (m[15] >> 16) & 1;
+        m[14] &= 0xffff;
+        select(&mut t, &mut m, 1 - b);
+    }
+
+    let mut o = [0u8; 32];
+    for i in 0..16 {
+        o[2 * i] = (t[i] & 0xff) as u8;
+        o[2 * i + 1] = (t[i] >> 8) as u8;
+    }
+    o
+}
+
+fn unpack25519(n: &[u8]) -> Gf {
+    let mut o = GF0;
+    for i in 0..16 {
+        o[i] = n[2 * i] as i64 + ((n[2 * i + 1] as i64) << 8);
+    }
+    o[15] &= 0x7fff;
+    o
+}
+
+fn neq25519(a: &Gf, b: &Gf) -> bool {
+    pack25519(a) != pack25519(b)
+}
+
+fn parity(a: &Gf) -> u8 {
+    pack25519(a)[0] & 1
+}
+
+fn fadd(a: &Gf, b: &Gf) -> Gf {
+    let mut o = GF0;
+    for i in 0..16 {
+        o[i] = a[i] + b[i];
+    }
+    o
+}
+
+fn fsub(a: &Gf, b: &Gf) -> Gf {
+    let mut o = GF0;
+    for i in 0..16 {
+        o[i] = a[i] - b[i];
+    }
+    o
+}
+
+fn fmul(a: &Gf, b: &Gf) -> Gf {
+    let mut t = [0i64; 31];
+    for i in 0..16 {
+        for j in 0..16 {
+            t[i + j] += a[i] * b[j];
+        }
+    }
+    for i in 0..15 {
+        t[i] += 38 * t[i + 16];
+    }
+    let mut o = GF0;
+    for i in 0..16 {
+        o[i] = t[i];
+    }
+    car25519(&mut o);
+    car25519(&mut o);
+    o
+}
+
+fn fsquare(a: &Gf) -> Gf {
+    fmul(a, a)
+}
+
+// a^(p - 2)
+fn invert(i: &Gf) -> Gf {
+    let mut c = *i;
+    for a in (0..254).rev() {
+        c = fsquare(&c);
+        if a != 2 && a != 4 {
+            c = fmul(&c, i);
+        }
+    }
+    c
+}
+
+// a^((p - 5) / 8)
+fn pow2523(i: &Gf) -> Gf {
+    let mut c = *i;
+    for a in (0..251).rev() {
+        c = fsquare(&c);
+        if a != 1 {
+            c = fmul(&c, i);
+        }
+    }
+    c
+}
+
+fn add(p: &mut Point, q: &Point) {
+    let a = fmul(&fsub(&p[1], &p[0]), &fsub(&q[1], &q[0]));
+    let b = fmul(&fadd(&p[0], &p[1]), &fadd(&q[0], &q[1]));
+    let c = fmul(&fmul(&p[3], &q[3]), &D2);
+    let d = fmul(&p[2], &q[2]);
+    let d = fadd(&d, &d);
+    let e = fsub(&b, &a);
+    let f = fsub(&d, &c);
+    let g = fadd(&d, &c);
+    let h = fadd(&b, &a);
+
+    p[0] = fmul(&e, &f);
+    p[1] = fmul(&h, &g);
+    p[2] = fmul(&g, &f);
+    p[3] = fmul(&e, &h);
+}
+
+fn cswap(p: &mut Point, q: &mut Point, b: i64) {
+    for i in 0..4 {
+        select(&mut p[i], &mut q[i], b);
+    }
+}
+
+fn pack(p: &Point) -> [u8; 32] {
+    let zi = invert(&p[2]);
+    let tx = fmul(&p[0], &zi);
+    let ty = fmul(&p[1], &zi);
+    let mut r = pack25519(&ty);
+    r[31] ^= parity(&tx) << 7;
+    r
+}
+
+fn scalar_mult(q: &mut Point, s: &[u8; 32]) -> Point {
+    let mut p = [GF0, GF1, GF1, GF0];
+    for i in (0..256).rev() {
+        let b = ((s[i / 8] >> (i & 7)) & 1) as i64;
+        cswap(&mut p, q, b);
+        let sum = p;
+        add(q, &sum);
+        let double = p;
+        add(&mut p, &double);
+        cswap(&mut p, q, b);
+    }
+    p
+}
+
+fn scalar_base(s: &[u8; 32]) -> Point {
+    let mut q = [X, Y, GF1, fmul(&X, &Y)];
+    scalar_mult(&mut q, s)
+}
+
+// Decodes a point and negates it, or returns None if it isn't on the curve
+fn unpack_neg(p: &[u8]) -> Option<Point> {
+    let y = unpack25519(p);
+    let num = fsquare(&y);
+    let den = fmul(&num, &D);
+    let num = fsub(&num, &GF1);
+    let den = fadd(&GF1, &den);
+
+    let den2 = fsquare(&den);
+    let den4 = fsquare(&den2);
+    let den6 = fmul(&den4, &den2);
+    let mut t = fmul(&fmul(&den6, &num), &den);
+    t = pow2523(&t);
+    t = fmul(&fmul(&fmul(&t, &num), &den), &den);
+    let mut x = fmul(&t, &den);
+
+    if neq25519(&fmul(&fsquare(&x), &den), &num) {
+        x = fmul(&x, &I);
+    }
+    if neq25519(&fmul(&fsquare(&x), &den), &num) {
+        return None;
+    }
+
+    if parity(&x) == (p[31] >> 7) {
+        x = fsub(&GF0, &x);
+    }
+    let t = fmul(&x, &y);
+    Some([x, y, GF1, t])
+}
+
+// Reduces x modulo L
+fn mod_l(x: &mut [i64; 64]) -> [u8; 32] {
+    for i in (32..64).rev() {
+        let mut carry = 0;
+        let mut j = i - 32;
+        while j < i - 12 {
+            x[j] += carry - 16 * x[i] * L[j - (i - 32)];
+            carry = (x[j] + 128) >> 8;
+            x[j] -= carry << 8;
+            j += 1;
+        }
+        x[j] += carry;
+        x[i] = 0;
+    }
+
+    let mut carry = 0;
+    for j in 0..32 {
+        x[j] += carry - (x[31] >> 4) * L[j];
+        carry = x[j] >> 8;
+        x[j] &= 255;
+    }
+    for j in 0..32 {
+        x[j] -= carry * L[j];
+    }
+
+    let mut r = [0u8; 32];
+    for i in 0..32 {
+        x[i + 1] += x[i] >> 8;
+        r[i] = (x[i] & 255) as u8;
+    }
+    r
+}
+
+// Reduces a 64 byte hash modulo L
+fn reduce(h: &[u8]) -> [u8; 32] {
+    let mut x = [0i64; 64];
+    for i in 0..64 {
+        x[i] = h[i] as i64;
+    }
+    mod_l(&mut x)
+}
+
+// RFC 8032 requires S < L, so that a signature can't be altered by adding
+// a multiple of L to it
+fn scalar_is_canonical(s: &[u8]) -> bool {
+    for i in (0..32).rev() {
+        let l = L[i] as u8;
+        if s[i] < l {
+            return true;
+        }
+        if s[i] > l {
+            return false;
+        }
+    }
+    false
+}
+
+#[test]
+fn test_rfc8032_vectors() {
+    use super::rustc_serialize::hex::FromHex;
+
+    // RFC 8032 section 7.1, tests 1 to 3
+    let vectors = [
+        ("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+         "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+         "",
+         "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b"),
+        ("4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+         "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+         "72",
+         "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00"),
+        ("c5aa8df43f9f837bedb7442f31dcb7b166d38535076f094b85ce3a2e0b4458f7",
+         "fc51cd8e6218a1a38da47ed00230f0580816ed13ba3303ac5deb911548908025",
+         "af82",
+         "6291d657deec24024827e69c3abe01a30ce548a284743a445e3680d7db5ac3ac18ff9b538d16f290ae67f760984dc6594a7c15e9716ed28dc027beceea1ec40a")];
+
+    for &(seed, public, message, signature) in vectors.iter() {
+        let key = SecretKey::from_seed(&seed.from_hex().unwrap()).unwrap();
+        let message = message.from_hex().unwrap();
+        let signature = signature.from_hex().unwrap();
+
+        assert_eq!(public.from_hex().unwrap(), key.public_key().as_bytes().to_vec());
+        assert_eq!(signature, key.sign(&message));
+
+        let public_key = PublicKey::from_bytes(&public.from_hex().unwrap()).unwrap();
+        assert!(public_key.verify(&message, &signature));
+
+        let mut altered = message.clone();
+        altered.push(0);
+        assert!(!public_key.verify(&altered, &signature));
+
+        let mut bad_signature = signature.clone();
+        bad_signature[0] ^= 1;
+        assert!(!public_key.verify(&message, &bad_signature));
+        assert!(!public_key.verify(&message, &signature[..63]));
+    }
+}
+
+#[test]
+fn test_non_canonical_signature() {
+    use super::rustc_serialize::hex::FromHex;
+
+    let key = SecretKey::from_seed(&[7; 32]).unwrap();
+    let mut signature = key.sign(b"message");
+    assert!(key.public_key().verify(b"message", &signature));
+
+    // adding L to S gives an equivalent signature that must be rejected
+    let l = "edd3f55c1a631258d69cf7a2def9de1400000000000000000000000000000010".from_hex().unwrap();
+    let mut carry = 0u16;
+    for i in 0..32 {
+        let sum = signature[32 + i] as u16 + l[i] as u16 + carry;
+        signature[32 + i] = sum as u8;
+        carry = sum >> 8;
+    }
+    assert_eq!(0, carry);
+    assert!(!key.public_key().verify(b"message", &signature));
+}
+
+#[test]
+fn test_public_key_from_bytes() {
+    assert!(PublicKey::from_bytes(&[0; 31]).is_none());
+    // y = 2 has no x on the curve
+    let mut not_on_curve = [0u8; 32];
+    not_on_curve[0] = 2;
+    assert!(PublicKey::from_bytes(&not_on_curve).is_none());
+}
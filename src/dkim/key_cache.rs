@@ -0,0 +1,205 @@
+use std::ascii::AsciiExt;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use clock::{Clock, SYSTEM_CLOCK};
+use dns::DnsError;
+
+use super::DkimKeyLookup;
+
+// How long, in seconds, key records and missing keys are cached by default
+pub const DEFAULT_KEY_TTL: u64 = 3600;
+pub const DEFAULT_NEGATIVE_KEY_TTL: u64 = 300;
+
+struct CachedKey {
+    record: Result<String, DnsError>,
+    expires: u64
+}
+
+struct CacheState {
+    keys: HashMap<String, CachedKey>,
+    hits: usize,
+    misses: usize
+}
+
+// A DkimKeyLookup that remembers the answers of another one.  Key records are
+// kept for positive_ttl seconds and missing keys for negative_ttl; temporary
+// failures aren't kept at all.  Once capacity keys are cached, the one closest
+// to expiring makes way for the next.  It can be shared between threads.
+pub struct DkimKeyCache<'a, L> {
+    lookup: L,
+    capacity: usize,
+    positive_ttl: u64,
+    negative_ttl: u64,
+    state: Mutex<CacheState>,
+    clock: &'a (Clock + Sync + 'a)
+}
+
+impl<'a, L: DkimKeyLookup> DkimKeyCache<'a, L> {
+    pub fn new(lookup: L, capacity: usize) -> DkimKeyCache<'a, L> {
+        DkimKeyCache::with_clock(lookup, capacity, DEFAULT_KEY_TTL, DEFAULT_NEGATIVE_KEY_TTL, &SYSTEM_CLOCK)
+    }
+
+    pub fn with_clock(lookup: L, capacity: usize, positive_ttl: u64, negative_ttl: u64,
+                      clock: &'a (Clock + Sync)) -> DkimKeyCache<'a, L> {
+        DkimKeyCache {
+            lookup: lookup,
+            capacity: capacity,
+            positive_ttl: positive_ttl,
+            negative_ttl: negative_ttl,
+            state: Mutex::new(CacheState { keys: HashMap::new(), hits: 0, misses: 0 }),
+            clock: clock
+        }
+    }
+
+    pub fn hits(&self) -> usize {
+        self.state.lock().unwrap().hits
+    }
+
+    pub fn misses(&self) -> usize {
+        self.state.lock().unwrap().misses
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().keys.len()
+    }
+
+    fn cached(&self, name: &str, now: u64) -> Option<Result<String, DnsError>> {
+        let mut state = self.state.lock().unwrap();
+        let record = match state.keys.get(name) {
+            Some(key) if key.expires > now => Some(key.record.clone()),
+            _ => None
+        };
+        match record {
+            Some(_) => state.hits += 1,
+            None => state.misses += 1
+        }
+        record
+    }
+
+    fn insert(&self, name: String, record: Result<String, DnsError>, now: u64) {
+        let ttl = match record {
+            Ok(_) => self.positive_ttl,
+            Err(DnsError::NotFound) => self.negative_ttl,
+            Err(DnsError::TempFail(_)) => return
+        };
+        if ttl == 0 || self.capacity == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if !state.keys.contains_key(&name) && state.keys.len() >= self.capacity {
+            let expired: Vec<String> = state.keys.iter()
+                .filter(|&(_, key)| key.expires <= now)
+                .map(|(name, _)| name.clone())
+                .collect();
+            for name in expired.iter() {
+                state.keys.remove(name);
+            }
+
+            if state.keys.len() >= self.capacity {
+                let oldest = state.keys.iter()
+                    .min_by(|&(_, key)| key.expires)
+                    .map(|(name, _)| name.clone());
+                match oldest {
+                    Some(oldest) => { state.keys.remove(&oldest); }
+                    None => ()
+                }
+            }
+        }
+        state.keys.insert(name, CachedKey { record: record, expires: now + ttl });
+    }
+}
+
+impl<'a, L: DkimKeyLookup> DkimKeyLookup for DkimKeyCache<'a, L> {
+    fn lookup_key(&self, selector: &str, sdid: &str) -> Result<String, DnsError> {
+        let name = format!("{}._domainkey.{}", selector, sdid.trim_right_matches('.')).to_ascii_lowercase();
+        let now = self.clock.now();
+        match self.cached(&name, now) {
+            Some(record) => return record,
+            None => ()
+        }
+
+        // the lock isn't held while looking up, so that other threads aren't
+        // kept waiting on a slow lookup
+        let record = self.lookup.lookup_key(selector, sdid);
+        self.insert(name, record.clone(), now);
+        record
+    }
+}
+
+#[cfg(test)]
+struct SteppedClock {
+    time: Mutex<u64>
+}
+
+#[cfg(test)]
+impl Clock for SteppedClock {
+    fn now(&self) -> u64 {
+        *self.time.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+struct TestLookup {
+    zone: ::dns::ZoneFile,
+    lookups: Mutex<usize>
+}
+
+#[cfg(test)]
+impl DkimKeyLookup for TestLookup {
+    fn lookup_key(&self, selector: &str, sdid: &str) -> Result<String, DnsError> {
+        *self.lookups.lock().unwrap() += 1;
+        if sdid == "tempfail.example.com" {
+            return Err(DnsError::TempFail("SERVFAIL".to_string()));
+        }
+        self.zone.lookup_key(selector, sdid)
+    }
+}
+
+#[test]
+fn test_key_cache() {
+    use dns::ZoneFile;
+
+    let mut zone = ZoneFile::new();
+    zone.add_txt("brisbane._domainkey.example.com", "v=DKIM1; p=MIGfMA0G");
+    zone.add_txt("sydney._domainkey.example.com", "v=DKIM1; p=CSqGSIb3");
+    let lookup = TestLookup { zone: zone, lookups: Mutex::new(0) };
+    let clock = SteppedClock { time: Mutex::new(1117574938) };
+    let cache = DkimKeyCache::with_clock(lookup, 2, 3600, 300, &clock);
+    let lookups = || *cache.lookup.lookups.lock().unwrap();
+
+    assert_eq!(Ok("v=DKIM1; p=MIGfMA0G".to_string()), cache.lookup_key("brisbane", "example.com"));
+    assert_eq!(Ok("v=DKIM1; p=MIGfMA0G".to_string()), cache.lookup_key("Brisbane", "Example.com."));
+    assert_eq!(1, lookups());
+    assert_eq!((1, 1), (cache.hits(), cache.misses()));
+
+    // missing keys are cached too, but not temporary failures
+    assert_eq!(Err(DnsError::NotFound), cache.lookup_key("perth", "example.com"));
+    assert_eq!(Err(DnsError::NotFound), cache.lookup_key("perth", "example.com"));
+    assert_eq!(2, lookups());
+    cache.lookup_key("brisbane", "tempfail.example.com").unwrap_err();
+    cache.lookup_key("brisbane", "tempfail.example.com").unwrap_err();
+    assert_eq!(4, lookups());
+    assert_eq!(2, cache.len());
+
+    // the missing key expires first
+    *clock.time.lock().unwrap() += 300;
+    assert_eq!(Err(DnsError::NotFound), cache.lookup_key("perth", "example.com"));
+    assert_eq!(5, lookups());
+    assert!(cache.lookup_key("brisbane", "example.com").is_ok());
+    assert_eq!(5, lookups());
+
+    // a full cache drops the key closest to expiring, here perth's
+    assert!(cache.lookup_key("sydney", "example.com").is_ok());
+    assert_eq!(2, cache.len());
+    assert!(cache.lookup_key("brisbane", "example.com").is_ok());
+    assert_eq!(Err(DnsError::NotFound), cache.lookup_key("perth", "example.com"));
+    assert_eq!(7, lookups());
+    assert_eq!((4, 7), (cache.hits(), cache.misses()));
+
+    // and key records expire after the positive TTL
+    *clock.time.lock().unwrap() += 3600;
+    assert!(cache.lookup_key("sydney", "example.com").is_ok());
+    assert_eq!(8, lookups());
+}
@@ -0,0 +1,40 @@
+use dns::{DnsError, ZoneFile};
+
+use super::{DkimPublicKey, DkimReason};
+
+// Retrieves the key record published at selector._domainkey.sdid
+pub trait DkimKeyLookup {
+    fn lookup_key(&self, selector: &str, sdid: &str) -> Result<String, DnsError>;
+}
+
+impl DkimKeyLookup for ZoneFile {
+    fn lookup_key(&self, selector: &str, sdid: &str) -> Result<String, DnsError> {
+        let name = format!("{}._domainkey.{}", selector, sdid);
+        let records = try!(self.txt(&name));
+        match records.into_iter().next() {
+            Some(record) => Ok(record),
+            None => Err(DnsError::NotFound)
+        }
+    }
+}
+
+// Retrieves and parses a key record, with lookup failures reported the way
+// RFC 6376 section 6.1.2 requires
+pub fn lookup_public_key(key_lookup: &DkimKeyLookup, selector: &str, sdid: &str) 
+    -> Result<DkimPublicKey, DkimReason> {
+    match key_lookup.lookup_key(selector, sdid) {
+        Ok(record) => DkimPublicKey::parse(&record),
+        Err(DnsError::NotFound) => Err(DkimReason::KeyNotFound),
+        Err(DnsError::TempFail(_)) => Err(DkimReason::KeyUnavailable)
+    }
+}
+
+#[test]
+fn test_zone_file_key_lookup() {
+    let mut zone = ZoneFile::new();
+    zone.add_txt("brisbane._domainkey.example.com", "v=DKIM1; k=rsa; p=MIGfMA0G CSqG");
+
+    assert_eq!(Ok("v=DKIM1; k=rsa; p=MIGfMA0G CSqG".to_string()),
+               zone.lookup_key("Brisbane", "example.com."));
+    assert_eq!(Err(DnsError::NotFound), zone.lookup_key("sydney", "example.com"));
+}
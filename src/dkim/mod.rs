@@ -2,8 +2,16 @@ extern crate openssl;
 extern crate rustc_serialize;
 
 mod canonicalizer;
+mod copied_headers;
+mod ed25519;
+mod key_lookup;
+mod key_cache;
+mod public_key;
+mod result;
+mod signer;
 
 use std::collections::HashMap;
+use std::ascii::AsciiExt;
 
 use regex::Regex;
 
@@ -11,7 +19,7 @@ use self::openssl::crypto::hash::Hasher;
 use self::openssl::crypto::hash::Type;
 use self::openssl::crypto::hash::Type::{SHA256,SHA1};
 
-use self::rustc_serialize::base64::{ToBase64,Config};
+use self::rustc_serialize::base64::{ToBase64,FromBase64,Config};
 use self::rustc_serialize::base64::CharacterSet::Standard;
 use self::rustc_serialize::base64::Newline::CRLF;
 
@@ -20,16 +28,23 @@ use self::DkimSignatureParseError::MissingTag;
 
 use std::io::Write;
 
-use self::canonicalizer::{CanonicalizationType, Canonicalizer, BodyCanonicalizer, HeaderCanonicalizer};
+use self::canonicalizer::BodyCanonicalizer;
+pub use self::canonicalizer::{Canonicalizer, HeaderCanonicalizer};
+
+pub use self::canonicalizer::CanonicalizationType;
+
+pub use self::copied_headers::DkimHeaderChange;
+pub use self::key_lookup::{DkimKeyLookup, lookup_public_key};
+pub use self::key_cache::{DkimKeyCache, DEFAULT_KEY_TTL, DEFAULT_NEGATIVE_KEY_TTL};
+pub use self::public_key::{DkimPublicKey, DkimKeyType, DkimKey};
+pub use self::result::{DkimResult, DkimStatus, DkimReason};
+pub use self::signer::{DkimSigner, DkimSigningKey, DkimSigningError, sign_headers};
 
 
-// The DKIM implementation is not yet complete, so not all fields are used
-// Remove the #[allow(dead_code)] once the implementation is complete
-#[allow(dead_code)]
 pub struct DkimSignature {
     // REQUIRED:
     version: u32,
-    hash_type: Type,
+    algorithm: DkimAlgorithm,
     signature: String,
     body_hash: String,
     sdid: String,
@@ -49,12 +64,13 @@ pub struct DkimSignature {
     copied_header_fields: Option<String>
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum DkimSignatureParseError {
     MissingTag(String),
     BadTag(String),
     BadCanonicalization(String),
     BadHashAlgorithm(String),
+    ExpiresBeforeTimestamp
 }
 
 #[derive(Debug)]
@@ -62,126 +78,414 @@ pub enum DkimVerificationError {
     HashError
 }
 
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DkimAlgorithm {
+    RsaSha1,
+    RsaSha256,
+    Ed25519Sha256
+}
+
+impl DkimAlgorithm {
+    pub fn from_name(name: &str) -> Option<DkimAlgorithm> {
+        match name {
+            "rsa-sha256" => Some(DkimAlgorithm::RsaSha256),
+            "rsa-sha1" => Some(DkimAlgorithm::RsaSha1),
+            "ed25519-sha256" => Some(DkimAlgorithm::Ed25519Sha256),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            DkimAlgorithm::RsaSha256 => "rsa-sha256",
+            DkimAlgorithm::RsaSha1 => "rsa-sha1",
+            DkimAlgorithm::Ed25519Sha256 => "ed25519-sha256"
+        }
+    }
+
+    // The hash algorithm name, as used in the h= tag of a key record
+    pub fn hash_name(&self) -> &'static str {
+        match *self {
+            DkimAlgorithm::RsaSha256 => "sha256",
+            DkimAlgorithm::RsaSha1 => "sha1",
+            DkimAlgorithm::Ed25519Sha256 => "sha256"
+        }
+    }
+
+    pub fn key_type(&self) -> DkimKeyType {
+        match *self {
+            DkimAlgorithm::RsaSha256 => DkimKeyType::Rsa,
+            DkimAlgorithm::RsaSha1 => DkimKeyType::Rsa,
+            DkimAlgorithm::Ed25519Sha256 => DkimKeyType::Ed25519
+        }
+    }
+
+    fn hash_type(&self) -> Type {
+        match *self {
+            DkimAlgorithm::RsaSha256 => SHA256,
+            DkimAlgorithm::RsaSha1 => SHA1,
+            DkimAlgorithm::Ed25519Sha256 => SHA256
+        }
+    }
+}
+
+// Checks signature against the hash of the signed data.  Ed25519 signs the
+// SHA-256 hash of the data rather than the data itself (RFC 8463).
+pub fn verify_signature(algorithm: DkimAlgorithm, public_key: &DkimPublicKey, 
+                    hash: &[u8], signature: &[u8]) -> bool {
+    match (algorithm.key_type(), public_key.key()) {
+        (DkimKeyType::Rsa, &DkimKey::Rsa(ref key)) => {
+            key.verify_with_hash(hash, signature, algorithm.hash_type())
+        }
+        (DkimKeyType::Ed25519, &DkimKey::Ed25519(ref key)) => {
+            key.verify(hash, signature)
+        }
+        _ => false
+    }
+}
+
     
 impl DkimSignature {
     pub fn parse(signature: &str) -> Result<DkimSignature, DkimSignatureParseError> {
         let tags = try!(parse_dkim_signature(signature));
+        let version = try!(unwrap_uint_tag_value(&tags, "v"));
+
+        DkimSignature::from_tags(&tags, version, unwrap_string_tag_value(&tags, "i").ok())
+    }
+
+    // ARC-Message-Signature headers have the same tags as DKIM-Signature
+    // headers, except that i= is the ARC instance and there is no v=
+    pub fn parse_arc(signature: &str) -> Result<(u32, DkimSignature), DkimSignatureParseError> {
+        let tags = try!(parse_dkim_signature(signature));
+        let instance = try!(unwrap_uint_tag_value(&tags, "i"));
 
+        Ok((instance, try!(DkimSignature::from_tags(&tags, 1, None))))
+    }
+
+    fn from_tags(tags: &HashMap<&str, &str>, version: u32, auid: Option<String>) 
+        -> Result<DkimSignature, DkimSignatureParseError> {
         let (header_canon, body_canon) = try!(parse_canonicalization(&tags));
 
-        let hash_type = match &(try!(unwrap_string_tag_value(&tags, "a"))) as &str {
-            "rsa-sha256" => SHA256,
-            "rsa-sha1" => SHA1,
-            a => return Err(DkimSignatureParseError::BadHashAlgorithm(a.to_string()))
+        let a = try!(unwrap_string_tag_value(&tags, "a"));
+        let algorithm = match DkimAlgorithm::from_name(&a) {
+            Some(algorithm) => algorithm,
+            None => return Err(DkimSignatureParseError::BadHashAlgorithm(a.clone()))
         };
 
-        Ok(DkimSignature {
-            version:  try!(unwrap_uint_tag_value(&tags, "v")),
-            hash_type: hash_type,
-            signature: try!(unwrap_string_tag_value(&tags, "b")).replace(" ",""),
+        let signature = DkimSignature {
+            version: version,
+            algorithm: algorithm,
+            signature: strip_whitespace(&try!(unwrap_string_tag_value(&tags, "b"))),
             body_hash: match unwrap_string_tag_value(&tags, "bh") {
-                Ok(bh) => Regex::new(r"\s+").unwrap().replace_all(&bh, "").to_string(),
+                Ok(bh) => strip_whitespace(&bh),
                 Err(_) => return Err(MissingTag("bh".to_string()))
             },            
             sdid: try!(unwrap_string_tag_value(&tags, "d")),
-            header_fields: try!(unwrap_string_tag_value(&tags, "h")).split(':').map(|x| x.to_string()).collect(),
+            header_fields: try!(unwrap_string_tag_value(&tags, "h")).split(':').map(|x| x.trim().to_string()).collect(),
             selector: try!(unwrap_string_tag_value(&tags, "s")),
             timestamp: unwrap_uint_tag_value(&tags, "t").ok(),
             expiration: unwrap_uint_tag_value(&tags, "x").ok(),
             header_canon: header_canon,
             body_canon: body_canon,
-            auid: unwrap_string_tag_value(&tags, "i").ok(),
+            auid: auid,
             body_length: unwrap_uint_tag_value(&tags, "l").ok(),
             query_methods: unwrap_string_tag_value(&tags, "q").ok(),
             copied_header_fields: unwrap_string_tag_value(&tags, "z").ok()
-        })
+        };
+
+        match (signature.timestamp, signature.expiration) {
+            (Some(t), Some(x)) if x <= t => 
+                return Err(DkimSignatureParseError::ExpiresBeforeTimestamp),
+            _ => ()
+        }
+
+        Ok(signature)
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn sdid(&self) -> &str {
+        &self.sdid
+    }
+
+    pub fn selector(&self) -> &str {
+        &self.selector
+    }
+
+    // q=, which defaults to dns/txt
+    pub fn query_methods(&self) -> &str {
+        match self.query_methods {
+            Some(ref q) => q,
+            None => "dns/txt"
+        }
+    }
+
+    // The agent or user identifier (i=), which defaults to an empty local
+    // part at the SDID
+    pub fn identity(&self) -> String {
+        match self.auid {
+            Some(ref auid) => auid.clone(),
+            None => format!("@{}", self.sdid)
+        }
+    }
+
+    // The domain of i= must be the same as, or a subdomain of, d=
+    pub fn check_identity(&self) -> Result<(), DkimReason> {
+        let domain = auid_domain(&self.identity()).to_ascii_lowercase();
+        let sdid = self.sdid.to_ascii_lowercase();
+        if domain == sdid || domain.ends_with(&format!(".{}", sdid)) {
+            Ok(())
+        }
+        else {
+            Err(DkimReason::IdentityDomainMismatch)
+        }
+    }
+
+    // now is in seconds since the epoch, and skew is the number of seconds
+    // the signer's clock is allowed to differ from ours
+    pub fn is_expired(&self, now: u64, skew: u64) -> bool {
+        self.expiration.map_or(false, |x| now > x as u64 + skew)
+    }
+
+    pub fn is_future_dated(&self, now: u64, skew: u64) -> bool {
+        self.timestamp.map_or(false, |t| t as u64 > now + skew)
     }
 }
 
 
-pub struct DkimVerifier {
-    signature: DkimSignature,
+// Canonicalizes and hashes a message body, as the signer or verifier of a
+// signature sees it
+pub struct BodyHasher {
     hasher: Hasher,
     body_canon: Box<BodyCanonicalizer>,
-    header_canon: Box<HeaderCanonicalizer>,
+    body_length: Option<u32>,
     body_bytes_hashed: usize,
-    canonicalized_headers: Vec<u8>
+    body_bytes_unsigned: usize
+}
+
+impl BodyHasher {
+    pub fn new(algorithm: DkimAlgorithm, body_canon: CanonicalizationType, 
+               body_length: Option<u32>) -> BodyHasher {
+        BodyHasher {
+            hasher: Hasher::new(algorithm.hash_type()),
+            body_canon: Canonicalizer::body(body_canon),
+            body_length: body_length,
+            body_bytes_hashed: 0,
+            body_bytes_unsigned: 0
+        }
+    }
+
+    // Truncates canonicalized data to what is left of the l= limit, and
+    // returns the number of bytes that were cut off
+    fn limit_body_length(&mut self, data: &mut Vec<u8>) -> usize {
+        let available = match self.body_length {
+            Some(body_length) => (body_length as usize).saturating_sub(self.body_bytes_hashed),
+            None => data.len()
+        };
+        let excess = data.len().saturating_sub(available);
+        data.truncate(available);
+        self.body_bytes_hashed = self.body_bytes_hashed + data.len();
+        excess
+    }
+
+    pub fn update(&mut self, data: &Vec<u8>) -> Result<usize, DkimVerificationError> {
+        let mut canonicalized_data = self.body_canon.canonicalize(data);
+        let excess = self.limit_body_length(&mut canonicalized_data);
+        self.body_bytes_unsigned = self.body_bytes_unsigned + excess;
+        match self.hasher.write_all(&canonicalized_data) {
+            Ok(_) => Ok(canonicalized_data.len()),
+            Err(_) => Err(DkimVerificationError::HashError)
+        }
+    }
+
+    pub fn finish(&mut self) -> Result<Vec<u8>, DkimVerificationError> {
+        // the CRLF that ends the canonicalized body is only counted as
+        // unsigned when it ends content that was cut off
+        let mut data = self.body_canon.flush();
+        let excess = self.limit_body_length(&mut data);
+        if self.body_bytes_unsigned > 0 {
+            self.body_bytes_unsigned = self.body_bytes_unsigned + excess;
+        }
+        match self.hasher.write_all(&data) {
+            Ok(_) => Ok(self.hasher.finish()),
+            Err(_) => Err(DkimVerificationError::HashError)
+        }
+    }
+
+    // The number of canonicalized body bytes beyond the l= limit.  Content
+    // appended to a message after signing ends up here rather than failing
+    // the signature.
+    pub fn unsigned_bytes(&self) -> usize {
+        self.body_bytes_unsigned
+    }
+}
+
+// Picks the headers named in h= from the bottom of the header block up.  A
+// name listed more often than the header appears selects nothing the extra
+// times, so those entries contribute nothing to the hash.
+pub fn select_headers<'h>(header_fields: &[String], headers: &'h [(String, String, Vec<u8>)]) 
+    -> Vec<&'h (String, String, Vec<u8>)> {
+
+    let mut used = vec![false; headers.len()];
+    let mut selected = vec![];
+    for field in header_fields.iter() {
+        let found = (0..headers.len()).rev().find(|&i| {
+            !used[i] && headers[i].0.trim().eq_ignore_ascii_case(field)
+        });
+        match found {
+            Some(i) => {
+                used[i] = true;
+                selected.push(&headers[i]);
+            }
+            None => ()
+        }
+    }
+    selected
+}
+
+// The DKIM-Signature header is hashed last, with the value of its b= tag
+// removed and without its trailing CRLF (RFC 6376 section 3.7)
+pub fn canonicalize_signature_header(header_canon: &mut HeaderCanonicalizer, 
+                                 name: &str, value: &str, raw: &[u8]) -> Vec<u8> {
+    let raw = String::from_utf8_lossy(raw).into_owned();
+
+    let mut result = header_canon.canonicalize(name.to_string(), 
+                                               remove_signature_value(value),
+                                               remove_signature_value(&raw).into_bytes());
+    while result.len() >= 2 && 
+          result[result.len() - 2] == b'\r' && 
+          result[result.len() - 1] == b'\n' {
+        result.pop();
+        result.pop();
+    }
+    result
+}
+
+pub fn hash_headers(algorithm: DkimAlgorithm, canonicalized_headers: &[u8], 
+                signature_header: &[u8]) -> Result<Vec<u8>, DkimVerificationError> {
+    let mut hasher = Hasher::new(algorithm.hash_type());
+    match hasher.write_all(canonicalized_headers)
+                .and_then(|_| hasher.write_all(signature_header)) {
+        Ok(_) => Ok(hasher.finish()),
+        Err(_) => Err(DkimVerificationError::HashError)
+    }
+}
+
+pub struct DkimVerifier {
+    signature: DkimSignature,
+    signature_header: (String, String, Vec<u8>),
+    body_hasher: BodyHasher,
+    header_canon: Box<HeaderCanonicalizer>,
+    headers: Vec<(String, String, Vec<u8>)>
 }
 
 impl DkimVerifier {
-    pub fn new(signature: DkimSignature) -> DkimVerifier {
-        let hash_type = signature.hash_type;
+    pub fn new(signature: DkimSignature, name: String, value: String, raw: Vec<u8>) -> DkimVerifier {
         let header_canon = signature.header_canon.clone();
-        let body_canon = signature.body_canon.clone();
+        let body_hasher = BodyHasher::new(signature.algorithm, 
+                                          signature.body_canon.clone(), 
+                                          signature.body_length);
         DkimVerifier {
             signature: signature,
-            hasher: Hasher::new(hash_type),
+            signature_header: (name, value, raw),
+            body_hasher: body_hasher,
             header_canon: Canonicalizer::head(header_canon),
-            body_canon: Canonicalizer::body(body_canon),
-            body_bytes_hashed: 0,
-            canonicalized_headers: vec![]
+            headers: vec![]
         }
     }
 
+    pub fn signature(&self) -> &DkimSignature {
+        &self.signature
+    }
+
+    // Headers are kept until the signature is verified, since which of them
+    // are signed depends on the whole header block
     pub fn add_header(&mut self, name: String, value: String, raw: Vec<u8>) {
+        self.headers.push((name, value, raw));
+    }
 
-        let canonicalized_header = self.header_canon.canonicalize(name, value, raw);
-        self.canonicalized_headers.extend(canonicalized_header);
+    pub fn update_body(&mut self, data: &Vec<u8>) -> Result<usize, DkimVerificationError> {
+        self.body_hasher.update(data)
     }
 
-    fn limit_body_length(&mut self, data: &mut Vec<u8>) {
-        match self.signature.body_length {
-            Some(body_length) => data.truncate(body_length as usize - self.body_bytes_hashed),
-            None => ()
+    fn header_hash(&mut self) -> Result<Vec<u8>, DkimReason> {
+        let mut canonicalized_headers = vec![];
+        for header in select_headers(&self.signature.header_fields, &self.headers) {
+            let (ref name, ref value, ref raw) = *header;
+            canonicalized_headers.extend(self.header_canon.canonicalize(name.clone(), value.clone(), raw.clone()));
         }
-        self.body_bytes_hashed = self.body_bytes_hashed + data.len();
+
+        let signature_header = {
+            let (ref name, ref value, ref raw) = self.signature_header;
+            canonicalize_signature_header(&mut *self.header_canon, name, value, raw)
+        };
+        hash_headers(self.signature.algorithm, &canonicalized_headers, &signature_header)
+            .map_err(|_| DkimReason::HashError)
     }
 
-    pub fn update_body(&mut self, data: &Vec<u8>) -> Result<usize, DkimVerificationError> {
-        let mut canonicalized_data = self.body_canon.canonicalize(data);
-        self.limit_body_length(&mut canonicalized_data);
-        match self.hasher.write(&canonicalized_data) {
-            Ok(len) => Ok(len),
-            Err(_) => Err(DkimVerificationError::HashError)
+    // Produces the result for a signature that is rejected without being
+    // verified, such as one that has expired or whose key can't be used
+    pub fn reject(self, reason: DkimReason) -> DkimResult {
+        DkimResult::new(&self.signature, Some(reason))
+    }
+
+    pub fn finalize_body(mut self, public_key: &DkimPublicKey) -> DkimResult {
+        let reason = self.verify(public_key).err();
+        let mut result = DkimResult::new(&self.signature, reason);
+        result.testing = public_key.is_testing();
+        result.unsigned_body_bytes = self.body_hasher.unsigned_bytes();
+        if result.status == DkimStatus::Fail {
+            result.changed_headers = self.changed_headers();
         }
+        result
     }
 
-    pub fn finalize_body(mut self) -> Result<DkimResults, DkimVerificationError> {
-        let mut data = self.body_canon.flush();
-        self.limit_body_length(&mut data);
-        match self.hasher.write(&data) {
-            Ok(_) => (),
-            Err(_) => return Err(DkimVerificationError::HashError)
+    // Uses the copies of the signed headers in z=, if there are any, to work
+    // out which headers were changed in transit
+    fn changed_headers(&self) -> Vec<DkimHeaderChange> {
+        let copied = self.signature.copied_header_fields.as_ref()
+            .and_then(|z| copied_headers::decode_copied_headers(z));
+        match copied {
+            Some(copied) => copied_headers::changed_headers(&copied, &self.headers),
+            None => vec![]
         }
-        let result = self.hasher.finish();
-
-        let hash_string = (&result).to_base64(Config{
-            char_set: Standard, pad: true, newline: CRLF, line_length: None}); 
-
-        if hash_string != self.signature.body_hash {
-            let hash_name = match self.signature.hash_type {
-                SHA256 => "sha-256",
-                SHA1 => "sha-1",
-                _ => "err"
-            };
-            println!("hash mismatch {}", hash_name);
-            println!("bh(calc): {}", hash_string);
-            println!("bh(sent): {}\n", self.signature.body_hash);
+    }
+
+    fn verify(&mut self, public_key: &DkimPublicKey) -> Result<(), DkimReason> {
+        try!(public_key.check_signature(&self.signature));
+
+        let body_hash = match self.body_hasher.finish() {
+            Ok(hash) => hash,
+            Err(_) => return Err(DkimReason::HashError)
+        };
+
+        if base64(&body_hash) != self.signature.body_hash {
+            return Err(DkimReason::BodyHashMismatch);
         }
 
-        // TODO: this should return a DkimResults object, so we can actually
-        // check the results
+        let signature = match self.signature.signature.from_base64() {
+            Ok(s) => s,
+            Err(_) => return Err(DkimReason::BadSignatureEncoding)
+        };
+
+        let header_hash = try!(self.header_hash());
 
-        Ok(DkimResults)
+        if verify_signature(self.signature.algorithm, public_key, &header_hash, &signature) {
+            Ok(())
+        }
+        else {
+            Err(DkimReason::SignatureMismatch)
+        }
     }
 }
 
-pub struct DkimResults;
 
-fn parse_dkim_signature(dkim_signature: &str) -> Result<HashMap<&str, &str>,DkimSignatureParseError> {
+pub fn parse_dkim_signature(dkim_signature: &str) -> Result<HashMap<&str, &str>,DkimSignatureParseError> {
     let mut tags_map : HashMap<&str,&str> = HashMap::new();
 
-    let tags = dkim_signature.trim_right_matches(';').split(';');
+    let tags = dkim_signature.trim().trim_right_matches(';').split(';');
     for tag in tags {
         let (name, value) = try!(parse_dkim_tag(tag.trim()));
         tags_map.insert(name, value);
@@ -193,13 +497,36 @@ fn parse_dkim_signature(dkim_signature: &str) -> Result<HashMap<&str, &str>,Dkim
 fn parse_dkim_tag(tag: &str) -> Result<(&str, &str),DkimSignatureParseError> {
     use self::DkimSignatureParseError::BadTag;
 
-    let mut split_tag = tag.splitn(1, '=');
-    match (split_tag.nth(0), split_tag.nth(1)) {
-        (Some(name), Some(value)) => Ok((name, value)),
+    let mut split_tag = tag.splitn(2, '=');
+    match (split_tag.next(), split_tag.next()) {
+        (Some(name), Some(value)) => Ok((name.trim(), value.trim())),
         _ => Err(BadTag(tag.to_string()))
     }
 }
 
+pub fn base64(data: &[u8]) -> String {
+    data.to_base64(Config{
+        char_set: Standard, pad: true, newline: CRLF, line_length: None})
+}
+
+fn auid_domain(auid: &str) -> &str {
+    match auid.rfind('@') {
+        Some(at) => &auid[at + 1..],
+        None => auid
+    }
+}
+
+pub fn strip_whitespace(value: &str) -> String {
+    value.chars().filter(|c| !c.is_whitespace()).collect()
+}
+
+// Empties the value of the b= tag in a DKIM-Signature header, leaving the
+// rest of the header (including any folding whitespace) untouched
+pub fn remove_signature_value(header: &str) -> String {
+    let b_regex = Regex::new(r"(^|[:;])(\s*b\s*=)[^;]*").unwrap();
+    b_regex.replace_all(header, "$1$2")
+}
+
 fn unwrap_tag_value<T, F>(tags: &HashMap<&str,&str>, tag_name: &'static str, transform: F) 
     -> Result<T, DkimSignatureParseError> 
     where F: Fn(&&str) -> Option<T> 
@@ -291,3 +618,215 @@ fn test_parse_canonicalization() {
     });
 }
 
+
+#[cfg(test)]
+pub const TEST_PUBLIC_KEY: &'static str = "MIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQKBgQDHK9CfGoXCEriMrJuRwaX4I+w7Z8VBNwNCZ+d8DQWpKCF5uPTWfEkEDvCT3Bu2X7QRgRQtm6Xp/KUfqaGFJF+JCup+jW7Mhvcsmh1HqvK5WSAbTQbhiqPZ88gl4qnkKJ54IAi+Ka8gsPMP/FUMwrucX2PIz5O3dBOY5KR4Pm8obwIDAQAB";
+
+#[cfg(test)]
+pub const TEST_ED25519_PUBLIC_KEY: &'static str = "11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo=";
+
+// The result of checking a brisbane selector signature from sdid, for tests
+// of what's done with DKIM results
+#[cfg(test)]
+pub fn test_dkim_result(sdid: &str, reason: Option<DkimReason>) -> DkimResult {
+    let signature = DkimSignature::parse(&format!("v=1; a=rsa-sha256; d={}; s=brisbane; h=From; \
+                                                   bh=2jUSOH9NhtVGCQWNr9BrIAPreKQjO6Sn7XIkfJVOzv8=; b=AAAA",
+                                                  sdid)).unwrap();
+    DkimResult::new(&signature, reason)
+}
+
+#[cfg(test)]
+fn test_header(raw: &str) -> (String, String, Vec<u8>) {
+    let mut split = raw.splitn(2, ':');
+    let name = split.next().unwrap().to_string();
+    let value = split.next().unwrap().trim().to_string();
+    (name, value, raw.bytes().collect())
+}
+
+#[cfg(test)]
+fn test_verify(signature_header: &str, public_key: &DkimPublicKey) -> DkimResult {
+    let headers = vec![
+        "From: Joe SixPack <joe@football.example.com>\r\n",
+        "To: Suzie Q <suzie@shopping.example.net>\r\n",
+        "Subject: Is dinner ready?\r\n",
+        "Date: Fri, 11 Jul 2003 21:00:37 -0700 (PDT)\r\n",
+        "Message-ID: <20030712040037.46341.5F8J@football.example.com>\r\n"];
+
+    test_verify_headers(signature_header, &headers, public_key)
+}
+
+#[cfg(test)]
+fn test_verify_headers(signature_header: &str, headers: &[&str], public_key: &DkimPublicKey) -> DkimResult {
+    let body = "Hi.\r\n\r\nWe lost the game. Are you hungry yet?\r\n\r\nJoe.\r\n";
+
+    let (name, value, raw) = test_header(signature_header);
+    let signature = DkimSignature::parse(&value).unwrap();
+    let mut verifier = DkimVerifier::new(signature, name, value, raw);
+    for header in headers.iter() {
+        let (name, value, raw) = test_header(header);
+        verifier.add_header(name, value, raw);
+    }
+    verifier.update_body(&body.bytes().collect()).unwrap();
+    verifier.finalize_body(public_key)
+}
+
+#[test]
+fn test_verify_relaxed_signature() {
+    let signature_header = "DKIM-Signature: v=1; a=rsa-sha256; c=relaxed/relaxed; d=example.com;\r\n s=brisbane; t=1117574938; h=From:To:Subject:Date:Message-ID;\r\n bh=2jUSOH9NhtVGCQWNr9BrIAPreKQjO6Sn7XIkfJVOzv8=;\r\n b=MhLD2q562xToDqRZ9udS1txK2ltR+RRB0mO/576I2pr2KUU1LFp10yMcBlIxNgnQegHDZQ8taYrIa15MzVaF2ud41hEv30aEV9mOAa1+luoy+6Xj98X9hhvpecWXaltLGBE2XUF1LmTej1qONFwc6s9o+bqHeEJGWLn2wIlCtBw=\r\n";
+    let public_key = DkimPublicKey::parse(&format!("p={}", TEST_PUBLIC_KEY)).unwrap();
+
+    assert_eq!(DkimStatus::Pass, test_verify(signature_header, &public_key).status);
+}
+
+#[test]
+fn test_verify_simple_signature() {
+    let signature_header = "DKIM-Signature: v=1; a=rsa-sha256; c=simple/simple; d=example.com; s=brisbane; t=1117574938; h=From:To:Subject:Date:Message-ID; bh=2jUSOH9NhtVGCQWNr9BrIAPreKQjO6Sn7XIkfJVOzv8=; b=MyGDknexEZRuayarQGyqQShlfRlbQ8AzywoKxcwujL3Fby0cfmefR68Nm/uvMTP4z+z++58t0kxkIIlHg+uXtSvtRIJt/SdYAs//X9DFxv3g8bKlkxShRuD3EUXpcVktyD5Ld7morgowtD/JJIydrQM+/nVrXfafBAWgJLwTi+U=\r\n";
+    let public_key = DkimPublicKey::parse(&format!("p={}", TEST_PUBLIC_KEY)).unwrap();
+
+    assert_eq!(DkimStatus::Pass, test_verify(signature_header, &public_key).status);
+}
+
+#[test]
+fn test_verify_ed25519_signature() {
+    // the example from RFC 8463 appendix A.3, signed with the RFC 8032 test key
+    let signature_header = "DKIM-Signature: v=1; a=ed25519-sha256; c=relaxed/relaxed;\r\n d=football.example.com; i=@football.example.com;\r\n q=dns/txt; s=brisbane; t=1528637909; h=from : to :\r\n subject : date : message-id : from : subject : date;\r\n bh=2jUSOH9NhtVGCQWNr9BrIAPreKQjO6Sn7XIkfJVOzv8=;\r\n b=/gCrinpcQOoIfuHNQIbq4pgh9kyIK3AQUdt9OdqQehSwhEIug4D11Bus\r\n Fa3bT3FY5OsU7ZbnKELq+eXdp1Q1Dw==\r\n";
+    let public_key = DkimPublicKey::parse(&format!("v=DKIM1; k=ed25519; p={}", TEST_ED25519_PUBLIC_KEY)).unwrap();
+
+    let result = test_verify(signature_header, &public_key);
+    assert_eq!(DkimStatus::Pass, result.status);
+    assert_eq!(Some(DkimAlgorithm::Ed25519Sha256), result.algorithm);
+
+    // an RSA key can't verify an Ed25519 signature
+    let rsa_key = DkimPublicKey::parse(&format!("p={}", TEST_PUBLIC_KEY)).unwrap();
+    assert_eq!(Some(DkimReason::KeyTypeMismatch), test_verify(signature_header, &rsa_key).reason);
+}
+
+#[test]
+fn test_verify_bad_signature() {
+    let signature_header = "DKIM-Signature: v=1; a=rsa-sha256; c=simple/simple; d=example.com; s=brisbane; t=1117574938; h=From:To:Subject:Date:Message-ID; bh=2jUSOH9NhtVGCQWNr9BrIAPreKQjO6Sn7XIkfJVOzv8=; b=MhLD2q562xToDqRZ9udS1txK2ltR+RRB0mO/576I2pr2KUU1LFp10yMcBlIxNgnQegHDZQ8taYrIa15MzVaF2ud41hEv30aEV9mOAa1+luoy+6Xj98X9hhvpecWXaltLGBE2XUF1LmTej1qONFwc6s9o+bqHeEJGWLn2wIlCtBw=\r\n";
+    let public_key = DkimPublicKey::parse(&format!("p={}", TEST_PUBLIC_KEY)).unwrap();
+
+    let result = test_verify(signature_header, &public_key);
+    assert_eq!(DkimStatus::Fail, result.status);
+    assert_eq!(Some(DkimReason::SignatureMismatch), result.reason);
+}
+
+#[test]
+fn test_verify_repeated_headers() {
+    // Subject is signed twice and From and Reply-To are over-signed
+    let signature_header = "DKIM-Signature: v=1; a=ed25519-sha256; c=relaxed/relaxed; d=example.com; s=brisbane-ed; h=From:Subject:Subject:To:From:Reply-To; bh=2jUSOH9NhtVGCQWNr9BrIAPreKQjO6Sn7XIkfJVOzv8=; b=0BDQMSdXxbEfwbZRCqd0xCm1Aqz1yIK0rCgyj0/84pYbCh0BDN++T4cVP14DB5bQbH0F9tXK9rPu6jskz9NvCw==\r\n";
+    let public_key = DkimPublicKey::parse(&format!("v=DKIM1; k=ed25519; p={}", TEST_ED25519_PUBLIC_KEY)).unwrap();
+    let headers = vec![
+        "Received: from a.example.net\r\n",
+        "From: Joe SixPack <joe@football.example.com>\r\n",
+        "Subject: Is dinner ready?\r\n",
+        "To: Suzie Q <suzie@shopping.example.net>\r\n",
+        "Subject: Re: Is dinner ready?\r\n"];
+
+    assert_eq!(DkimStatus::Pass, test_verify_headers(signature_header, &headers, &public_key).status);
+
+    // the order of repeated headers is signed
+    let mut swapped = headers.clone();
+    swapped.swap(2, 4);
+    assert_eq!(Some(DkimReason::SignatureMismatch),
+               test_verify_headers(signature_header, &swapped, &public_key).reason);
+
+    // an over-signed header can't be added after signing
+    let mut added = headers.clone();
+    added.push("From: Mallory <mallory@example.net>\r\n");
+    assert_eq!(Some(DkimReason::SignatureMismatch),
+               test_verify_headers(signature_header, &added, &public_key).reason);
+
+    // headers that aren't signed can be
+    let mut unsigned = headers.clone();
+    unsigned.insert(0, "Received: from b.example.net\r\n");
+    assert_eq!(DkimStatus::Pass, test_verify_headers(signature_header, &unsigned, &public_key).status);
+}
+
+#[test]
+fn test_signature_times() {
+    let signature = DkimSignature::parse("v=1; a=rsa-sha256; d=example.com; s=brisbane; \
+                                          t=1117574938; x=1118006938; h=from; bh=; b=").unwrap();
+
+    assert!(!signature.is_expired(1118006938, 0));
+    assert!(signature.is_expired(1118006939, 0));
+    assert!(!signature.is_expired(1118006939, 300));
+
+    assert!(!signature.is_future_dated(1117574938, 0));
+    assert!(signature.is_future_dated(1117574937, 0));
+    assert!(!signature.is_future_dated(1117574937, 300));
+
+    assert_eq!(Some(DkimSignatureParseError::ExpiresBeforeTimestamp),
+               DkimSignature::parse("v=1; a=rsa-sha256; d=example.com; s=brisbane; \
+                                     t=1117574938; x=1117574938; h=from; bh=; b=").err());
+}
+
+#[test]
+fn test_signature_identity() {
+    let parse = |tags: &str| DkimSignature::parse(&format!("v=1; a=rsa-sha256; d=Example.com; s=brisbane; \
+                                                            h=from; bh=; b=; {}", tags)).unwrap();
+
+    assert_eq!("@Example.com", parse("").identity());
+    assert_eq!(Ok(()), parse("").check_identity());
+    assert_eq!(Ok(()), parse("i=joe@example.COM").check_identity());
+    assert_eq!(Ok(()), parse("i=joe@football.example.com").check_identity());
+    assert_eq!(Err(DkimReason::IdentityDomainMismatch), parse("i=joe@badexample.com").check_identity());
+    assert_eq!(Err(DkimReason::IdentityDomainMismatch), parse("i=joe@example.net").check_identity());
+}
+
+#[test]
+fn test_verify_copied_headers() {
+    let signature_header = "DKIM-Signature: v=1; a=rsa-sha256; c=relaxed/relaxed; d=example.com;\r\n s=brisbane; h=From:To:Subject;\r\n z=From:Joe=20SixPack=20<joe@football.example.com>|To:Suzie=20Q=20<suzie@shopping.example.net>|\r\n Subject:Is=20dinner=20ready?;\r\n bh=2jUSOH9NhtVGCQWNr9BrIAPreKQjO6Sn7XIkfJVOzv8=;\r\n b=AAAA\r\n";
+    let public_key = DkimPublicKey::parse(&format!("p={}", TEST_PUBLIC_KEY)).unwrap();
+    let headers = vec![
+        "From: Joe SixPack <joe@football.example.com>\r\n",
+        "To: Suzie Q <suzie@shopping.example.net>\r\n",
+        "Subject: [SPAM] Is dinner ready?\r\n"];
+
+    let result = test_verify_headers(signature_header, &headers, &public_key);
+    assert_eq!(Some(DkimReason::SignatureMismatch), result.reason);
+    assert_eq!(vec![DkimHeaderChange { name: "Subject".to_string(),
+                                       signed: "subject:Is dinner ready?".to_string(),
+                                       received: Some("subject:[SPAM] Is dinner ready?".to_string()) }],
+               result.changed_headers);
+}
+
+#[test]
+fn test_body_length_limit() {
+    use self::canonicalizer::CanonicalizationType::Simple;
+
+    let body = b"Hi.\r\n\r\nWe lost the game. Are you hungry yet?\r\n\r\nJoe.\r\n";
+    let hash = |body_length: Option<u32>, chunks: &[&[u8]]| {
+        let mut hasher = BodyHasher::new(DkimAlgorithm::RsaSha256, Simple, body_length);
+        for chunk in chunks.iter() {
+            hasher.update(&chunk.to_vec()).unwrap();
+        }
+        (hasher.finish().unwrap(), hasher.unsigned_bytes())
+    };
+
+    let (signed, unsigned) = hash(None, &[&body[..]]);
+    assert_eq!(0, unsigned);
+
+    // a limit beyond the end of the body has no effect
+    assert_eq!((signed.clone(), 0), hash(Some(1000), &[&body[..]]));
+    assert_eq!((signed.clone(), 0), hash(Some(body.len() as u32), &[&body[..6], &body[6..20], &body[20..]]));
+
+    // content appended after the limit is reported, however it is split
+    let mut appended = body.to_vec();
+    appended.extend(b"Click here!\r\n".iter().cloned());
+    assert_eq!((signed.clone(), 13), hash(Some(body.len() as u32), &[&appended[..]]));
+    assert_eq!((signed.clone(), 13), hash(Some(body.len() as u32), 
+                                          &[&appended[..3], &appended[3..body.len() + 2], &appended[body.len() + 2..]]));
+
+    assert_eq!(hash(None, &[&b"Hi.\r\n"[..]]), hash(Some(5), &[&b"Hi."[..], &b"\r\n"[..]]));
+    assert_eq!(hash(None, &[&b"Hi.\r\n"[..]]).0, hash(Some(5), &[&b"Hi."[..], &b"\r\n\r\nWe"[..]]).0);
+}
+
+#[test]
+fn test_remove_signature_value() {
+    assert_eq!("DKIM-Signature: a=rsa-sha256; b=; bh=abc=\r\n",
+               remove_signature_value("DKIM-Signature: a=rsa-sha256; b=xy\r\n z=; bh=abc=\r\n"));
+    assert_eq!("DKIM-Signature: a=rsa-sha256; bh=abc=; b=",
+               remove_signature_value("DKIM-Signature: a=rsa-sha256; bh=abc=; b=xy\r\n z=\r\n"));
+    assert_eq!("b=; bh=abc=", remove_signature_value("b=xyz; bh=abc="));
+}
@@ -0,0 +1,230 @@
+use std::ascii::AsciiExt;
+use std::fmt;
+
+use super::openssl::crypto::pkey::PKey;
+use super::ed25519;
+use super::{DkimSignature, DkimReason};
+use super::parse_dkim_signature;
+use super::{strip_whitespace, auid_domain};
+use super::rustc_serialize::base64::FromBase64;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DkimKeyType {
+    Rsa,
+    Ed25519
+}
+
+// The key from a record's p= tag, decoded when the record is parsed
+pub enum DkimKey {
+    Rsa(PKey),
+    Ed25519(ed25519::PublicKey)
+}
+
+// PKey can't be copied, compared or printed, so these go through its DER
+// encoding
+impl Clone for DkimKey {
+    fn clone(&self) -> DkimKey {
+        match *self {
+            DkimKey::Rsa(ref key) => {
+                let mut copy = PKey::new();
+                copy.load_pub(&key.save_pub());
+                DkimKey::Rsa(copy)
+            }
+            DkimKey::Ed25519(ref key) => DkimKey::Ed25519(key.clone())
+        }
+    }
+}
+
+impl PartialEq for DkimKey {
+    fn eq(&self, other: &DkimKey) -> bool {
+        match (self, other) {
+            (&DkimKey::Rsa(ref a), &DkimKey::Rsa(ref b)) => a.save_pub() == b.save_pub(),
+            (&DkimKey::Ed25519(ref a), &DkimKey::Ed25519(ref b)) => a == b,
+            _ => false
+        }
+    }
+}
+
+impl fmt::Debug for DkimKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DkimKey::Rsa(ref key) => write!(f, "Rsa({:?})", key.save_pub()),
+            DkimKey::Ed25519(ref key) => write!(f, "Ed25519({:?})", key.as_bytes())
+        }
+    }
+}
+
+// A key record published at selector._domainkey.sdid (RFC 6376 section 3.6.1)
+#[derive(Debug, PartialEq, Clone)]
+pub struct DkimPublicKey {
+    key: DkimKey,
+    hash_algorithms: Option<Vec<String>>,
+    service_types: Vec<String>,
+    testing: bool,
+    strict: bool
+}
+
+impl DkimPublicKey {
+    pub fn parse(record: &str) -> Result<DkimPublicKey, DkimReason> {
+        let tags = match parse_dkim_signature(record) {
+            Ok(tags) => tags,
+            Err(_) => return Err(DkimReason::KeySyntax)
+        };
+
+        match tags.get(&"v") {
+            Some(v) if *v != "DKIM1" => return Err(DkimReason::KeyVersionMismatch),
+            _ => ()
+        }
+
+        let key_type = match tags.get(&"k") {
+            None => DkimKeyType::Rsa,
+            Some(&"rsa") => DkimKeyType::Rsa,
+            Some(&"ed25519") => DkimKeyType::Ed25519,
+            Some(k) => return Err(DkimReason::KeyTypeUnsupported(k.to_string()))
+        };
+
+        let key_data = match tags.get(&"p") {
+            Some(p) if strip_whitespace(p).is_empty() => return Err(DkimReason::KeyRevoked),
+            Some(p) => match strip_whitespace(p).from_base64() {
+                Ok(data) => data,
+                Err(_) => return Err(DkimReason::KeySyntax)
+            },
+            None => return Err(DkimReason::KeySyntax)
+        };
+
+        let key = match key_type {
+            DkimKeyType::Rsa => try!(load_rsa_key(&key_data)),
+            DkimKeyType::Ed25519 => try!(load_ed25519_key(&key_data))
+        };
+
+        Ok(DkimPublicKey {
+            key: key,
+            hash_algorithms: tags.get(&"h").map(|h| split_list(h)),
+            service_types: tags.get(&"s").map_or(vec!["*".to_string()], |s| split_list(s)),
+            testing: tags.get(&"t").map_or(false, |t| split_list(t).contains(&"y".to_string())),
+            strict: tags.get(&"t").map_or(false, |t| split_list(t).contains(&"s".to_string()))
+        })
+    }
+
+    pub fn key_type(&self) -> DkimKeyType {
+        match self.key {
+            DkimKey::Rsa(_) => DkimKeyType::Rsa,
+            DkimKey::Ed25519(_) => DkimKeyType::Ed25519
+        }
+    }
+
+    pub fn key(&self) -> &DkimKey {
+        &self.key
+    }
+
+    pub fn is_testing(&self) -> bool {
+        self.testing
+    }
+
+    // Checks the restrictions the key record places on the signatures it can
+    // be used to verify
+    pub fn check_signature(&self, signature: &DkimSignature) -> Result<(), DkimReason> {
+        if signature.algorithm.key_type() != self.key_type() {
+            return Err(DkimReason::KeyTypeMismatch);
+        }
+
+        let hash_name = signature.algorithm.hash_name().to_string();
+        match self.hash_algorithms {
+            Some(ref hashes) if !hashes.contains(&hash_name) =>
+                return Err(DkimReason::KeyHashNotPermitted),
+            _ => ()
+        }
+
+        if !self.service_types.iter().any(|s| *s == "*" || *s == "email") {
+            return Err(DkimReason::KeyServiceNotEmail);
+        }
+
+        if self.strict {
+            match signature.auid {
+                Some(ref auid) if !auid_domain(auid).eq_ignore_ascii_case(&signature.sdid) =>
+                    return Err(DkimReason::KeyStrictIdentityMismatch),
+                _ => ()
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// RSA keys are DER encoded SubjectPublicKeyInfo.  PKey::load_pub doesn't say
+// when that fails to decode, and verifying with the empty key it leaves would
+// dereference a null pointer, so the key is encoded again to check it loaded.
+fn load_rsa_key(der: &[u8]) -> Result<DkimKey, DkimReason> {
+    let mut key = PKey::new();
+    key.load_pub(der);
+    if key.save_pub().is_empty() {
+        return Err(DkimReason::KeySyntax);
+    }
+    Ok(DkimKey::Rsa(key))
+}
+
+// Ed25519 keys are the 32 byte encoded point from RFC 8032 section 5.1.5
+fn load_ed25519_key(data: &[u8]) -> Result<DkimKey, DkimReason> {
+    match ed25519::PublicKey::from_bytes(data) {
+        Some(key) => Ok(DkimKey::Ed25519(key)),
+        None => Err(DkimReason::KeySyntax)
+    }
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value.split(':').map(|v| v.trim().to_ascii_lowercase()).collect()
+}
+
+#[test]
+fn test_parse_public_key() {
+    use super::TEST_PUBLIC_KEY;
+
+    let key = DkimPublicKey::parse(&format!("v=DKIM1; k=rsa; t=y:s; h=sha256; p={} {}",
+                                            &TEST_PUBLIC_KEY[..100], &TEST_PUBLIC_KEY[100..])).unwrap();
+
+    assert_eq!(DkimKeyType::Rsa, key.key_type());
+    match *key.key() {
+        DkimKey::Rsa(ref rsa) => assert_eq!(TEST_PUBLIC_KEY.from_base64().unwrap(), rsa.save_pub()),
+        _ => panic!("not an RSA key")
+    }
+    assert!(key.is_testing());
+    assert!(key.strict);
+    assert_eq!(Some(vec!["sha256".to_string()]), key.hash_algorithms);
+    assert_eq!(vec!["*".to_string()], key.service_types);
+}
+
+#[test]
+fn test_public_key_errors() {
+    use super::{TEST_PUBLIC_KEY, TEST_ED25519_PUBLIC_KEY};
+
+    assert_eq!(Err(DkimReason::KeyRevoked), DkimPublicKey::parse("v=DKIM1; p="));
+    assert_eq!(Err(DkimReason::KeySyntax), DkimPublicKey::parse("v=DKIM1; k=rsa"));
+    assert_eq!(Err(DkimReason::KeySyntax), DkimPublicKey::parse("v=DKIM1; p=MIG!"));
+    assert_eq!(Err(DkimReason::KeyVersionMismatch),
+               DkimPublicKey::parse(&format!("v=DKIM2; p={}", TEST_PUBLIC_KEY)));
+    assert_eq!(Err(DkimReason::KeyTypeUnsupported("dsa".to_string())),
+               DkimPublicKey::parse(&format!("k=dsa; p={}", TEST_PUBLIC_KEY)));
+    assert_eq!(Err(DkimReason::KeySyntax), DkimPublicKey::parse(&format!("k=ed25519; p={}", TEST_PUBLIC_KEY)));
+    // 32 bytes, but not a point on the curve
+    assert_eq!(Err(DkimReason::KeySyntax),
+               DkimPublicKey::parse("k=ed25519; p=AgAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="));
+
+    // base64 that isn't an RSA SubjectPublicKeyInfo
+    assert_eq!(Err(DkimReason::KeySyntax), DkimPublicKey::parse("k=rsa; p=MIGf"));
+    assert_eq!(Err(DkimReason::KeySyntax), DkimPublicKey::parse(&format!("k=rsa; p={}", TEST_ED25519_PUBLIC_KEY)));
+    assert_eq!(Err(DkimReason::KeySyntax), DkimPublicKey::parse(&format!("k=rsa; p={}", &TEST_PUBLIC_KEY[..120])));
+}
+
+#[test]
+fn test_public_key_restrictions() {
+    use super::TEST_PUBLIC_KEY;
+
+    let signature = DkimSignature::parse("v=1; a=rsa-sha256; d=example.com; s=brisbane; \
+                                          i=joe@football.example.com; h=from; bh=; b=").unwrap();
+    let parse = |tags: &str| DkimPublicKey::parse(&format!("{} p={}", tags, TEST_PUBLIC_KEY)).unwrap();
+
+    assert_eq!(Ok(()), parse("").check_signature(&signature));
+    assert_eq!(Err(DkimReason::KeyHashNotPermitted), parse("h=sha1;").check_signature(&signature));
+    assert_eq!(Err(DkimReason::KeyServiceNotEmail), parse("s=tlsrpt;").check_signature(&signature));
+    assert_eq!(Err(DkimReason::KeyStrictIdentityMismatch), parse("t=s;").check_signature(&signature));
+}
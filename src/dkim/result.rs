@@ -0,0 +1,174 @@
+use super::{DkimSignature, DkimSignatureParseError, DkimAlgorithm, DkimHeaderChange};
+use super::parse_dkim_signature;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DkimStatus {
+    Pass,
+    Fail,
+    Neutral,
+    TempError,
+    PermError
+}
+
+impl DkimStatus {
+    // The result name used in Authentication-Results headers (RFC 8601)
+    pub fn name(&self) -> &'static str {
+        match *self {
+            DkimStatus::Pass => "pass",
+            DkimStatus::Fail => "fail",
+            DkimStatus::Neutral => "neutral",
+            DkimStatus::TempError => "temperror",
+            DkimStatus::PermError => "permerror"
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum DkimReason {
+    // the signature could not be parsed
+    SignatureSyntax(DkimSignatureParseError),
+    BadSignatureEncoding,
+    SignatureExpired,
+    IdentityDomainMismatch,
+
+    // the key record could not be retrieved or used
+    KeyNotFound,
+    KeyUnavailable,
+    KeySyntax,
+    KeyVersionMismatch,
+    KeyRevoked,
+    KeyTypeUnsupported(String),
+    KeyTypeMismatch,
+    KeyHashNotPermitted,
+    KeyServiceNotEmail,
+    KeyStrictIdentityMismatch,
+
+    // the signature did not verify
+    BodyHashMismatch,
+    SignatureMismatch,
+
+    HashError
+}
+
+impl DkimReason {
+    pub fn status(&self) -> DkimStatus {
+        match *self {
+            DkimReason::SignatureSyntax(_) => DkimStatus::Neutral,
+            DkimReason::BadSignatureEncoding => DkimStatus::Neutral,
+            DkimReason::SignatureExpired => DkimStatus::PermError,
+            DkimReason::IdentityDomainMismatch => DkimStatus::PermError,
+            DkimReason::KeyNotFound => DkimStatus::PermError,
+            DkimReason::KeyUnavailable => DkimStatus::TempError,
+            DkimReason::KeySyntax => DkimStatus::PermError,
+            DkimReason::KeyVersionMismatch => DkimStatus::PermError,
+            DkimReason::KeyRevoked => DkimStatus::PermError,
+            DkimReason::KeyTypeUnsupported(_) => DkimStatus::PermError,
+            DkimReason::KeyTypeMismatch => DkimStatus::PermError,
+            DkimReason::KeyHashNotPermitted => DkimStatus::PermError,
+            DkimReason::KeyServiceNotEmail => DkimStatus::PermError,
+            DkimReason::KeyStrictIdentityMismatch => DkimStatus::PermError,
+            DkimReason::BodyHashMismatch => DkimStatus::Fail,
+            DkimReason::SignatureMismatch => DkimStatus::Fail,
+            DkimReason::HashError => DkimStatus::TempError
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match *self {
+            DkimReason::SignatureSyntax(_) => "signature syntax error",
+            DkimReason::BadSignatureEncoding => "signature encoding error",
+            DkimReason::SignatureExpired => "signature expired",
+            DkimReason::IdentityDomainMismatch => "domain mismatch",
+            DkimReason::KeyNotFound => "no key for signature",
+            DkimReason::KeyUnavailable => "key unavailable",
+            DkimReason::KeySyntax => "key syntax error",
+            DkimReason::KeyVersionMismatch => "incompatible key version",
+            DkimReason::KeyRevoked => "key revoked",
+            DkimReason::KeyTypeUnsupported(_) => "unsupported key type",
+            DkimReason::KeyTypeMismatch => "inappropriate key algorithm",
+            DkimReason::KeyHashNotPermitted => "inappropriate hash algorithm",
+            DkimReason::KeyServiceNotEmail => "inappropriate key service type",
+            DkimReason::KeyStrictIdentityMismatch => "key requires exact identity match",
+            DkimReason::BodyHashMismatch => "body hash did not verify",
+            DkimReason::SignatureMismatch => "signature did not verify",
+            DkimReason::HashError => "hash error"
+        }
+    }
+}
+
+// The outcome of checking a single DKIM-Signature header
+#[derive(Debug, PartialEq, Clone)]
+pub struct DkimResult {
+    pub status: DkimStatus,
+    pub reason: Option<DkimReason>,
+    pub sdid: Option<String>,
+    pub selector: Option<String>,
+    pub auid: Option<String>,
+    // the effective signing identity: i= if present, otherwise @d=
+    pub identity: Option<String>,
+    pub algorithm: Option<DkimAlgorithm>,
+    // the signer's key is flagged as being in testing mode (t=y)
+    pub testing: bool,
+    // the signature's timestamp (t=) is in the future
+    pub future_dated: bool,
+    // canonicalized body bytes after the l= limit, which aren't covered by
+    // the signature
+    pub unsigned_body_bytes: usize,
+    // for a failed signature with copies of the signed headers (z=), the
+    // headers that were changed in transit
+    pub changed_headers: Vec<DkimHeaderChange>
+}
+
+impl DkimResult {
+    pub fn new(signature: &DkimSignature, reason: Option<DkimReason>) -> DkimResult {
+        DkimResult {
+            status: reason.as_ref().map_or(DkimStatus::Pass, |r| r.status()),
+            reason: reason,
+            sdid: Some(signature.sdid.clone()),
+            selector: Some(signature.selector.clone()),
+            auid: signature.auid.clone(),
+            identity: Some(signature.identity()),
+            algorithm: Some(signature.algorithm),
+            testing: false,
+            future_dated: false,
+            unsigned_body_bytes: 0,
+            changed_headers: vec![]
+        }
+    }
+
+    // Whatever identifying tags can be recovered are reported for a
+    // signature that could not be parsed
+    pub fn invalid_signature(signature: &str, error: DkimSignatureParseError) -> DkimResult {
+        let tags = parse_dkim_signature(signature).ok();
+        let tag = |name: &str| tags.as_ref()
+            .and_then(|t| t.get(&name))
+            .map(|v| v.to_string());
+
+        DkimResult {
+            status: DkimStatus::Neutral,
+            reason: Some(DkimReason::SignatureSyntax(error)),
+            sdid: tag("d"),
+            selector: tag("s"),
+            auid: tag("i"),
+            identity: tag("i").or(tag("d").map(|d| format!("@{}", d))),
+            algorithm: tag("a").and_then(|a| DkimAlgorithm::from_name(&a)),
+            testing: false,
+            future_dated: false,
+            unsigned_body_bytes: 0,
+            changed_headers: vec![]
+        }
+    }
+}
+
+#[test]
+fn test_invalid_signature_result() {
+    let result = DkimResult::invalid_signature("v=1; a=rsa-sha256; d=example.com; s=brisbane",
+                                               DkimSignatureParseError::MissingTag("b".to_string()));
+
+    assert_eq!(DkimStatus::Neutral, result.status);
+    assert_eq!(Some("example.com".to_string()), result.sdid);
+    assert_eq!(Some("brisbane".to_string()), result.selector);
+    assert_eq!(None, result.auid);
+    assert_eq!(Some("@example.com".to_string()), result.identity);
+    assert_eq!(Some(DkimAlgorithm::RsaSha256), result.algorithm);
+}
@@ -0,0 +1,211 @@
+use std::io::Read;
+
+use super::openssl::crypto::pkey::PKey;
+use super::ed25519;
+
+use super::{DkimAlgorithm, BodyHasher, CanonicalizationType};
+use super::{select_headers, canonicalize_signature_header, hash_headers, base64};
+use super::canonicalizer::Canonicalizer;
+
+#[derive(Debug)]
+pub enum DkimSigningError {
+    BadKey,
+    HashError
+}
+
+pub enum DkimSigningKey {
+    Rsa(PKey),
+    Ed25519(ed25519::SecretKey)
+}
+
+impl DkimSigningKey {
+    pub fn rsa_from_pem<R: Read>(reader: &mut R) -> Result<DkimSigningKey, DkimSigningError> {
+        match PKey::private_key_from_pem(reader) {
+            Ok(key) => Ok(DkimSigningKey::Rsa(key)),
+            Err(_) => Err(DkimSigningError::BadKey)
+        }
+    }
+
+    // seed is the 32 byte Ed25519 private key, as described in RFC 8032
+    pub fn ed25519_from_seed(seed: &[u8]) -> Result<DkimSigningKey, DkimSigningError> {
+        match ed25519::SecretKey::from_seed(seed) {
+            Some(key) => Ok(DkimSigningKey::Ed25519(key)),
+            None => Err(DkimSigningError::BadKey)
+        }
+    }
+
+    pub fn algorithm(&self) -> DkimAlgorithm {
+        match *self {
+            DkimSigningKey::Rsa(_) => DkimAlgorithm::RsaSha256,
+            DkimSigningKey::Ed25519(_) => DkimAlgorithm::Ed25519Sha256
+        }
+    }
+
+    fn sign(&self, hash: &[u8]) -> Vec<u8> {
+        match *self {
+            DkimSigningKey::Rsa(ref key) => key.sign_with_hash(hash, self.algorithm().hash_type()),
+            DkimSigningKey::Ed25519(ref key) => key.sign(hash)
+        }
+    }
+}
+
+pub struct DkimSigner {
+    key: DkimSigningKey,
+    selector: String,
+    sdid: String,
+    header_fields: Vec<String>,
+    header_canon: CanonicalizationType,
+    body_canon: CanonicalizationType
+}
+
+impl DkimSigner {
+    pub fn new(key: DkimSigningKey, selector: &str, sdid: &str, header_fields: Vec<String>,
+               header_canon: CanonicalizationType, body_canon: CanonicalizationType) -> DkimSigner {
+        DkimSigner {
+            key: key,
+            selector: selector.to_string(),
+            sdid: sdid.to_string(),
+            header_fields: header_fields,
+            header_canon: header_canon,
+            body_canon: body_canon
+        }
+    }
+
+    // The body is hashed separately, so it can be streamed rather than held
+    // in memory
+    pub fn body_hasher(&self) -> BodyHasher {
+        BodyHasher::new(self.key.algorithm(), self.body_canon.clone(), None)
+    }
+
+    // Produces the value of a DKIM-Signature header for a message with the
+    // given headers, and body hash from body_hasher()
+    pub fn sign(&self, headers: &[(String, String, Vec<u8>)], body_hash: &[u8])
+        -> Result<String, DkimSigningError> {
+
+        let unsigned = format!("v=1; a={}; c={}/{}; d={}; s={};\r\n\th={};\r\n\tbh={};\r\n\tb=",
+                               self.key.algorithm().name(),
+                               canon_name(&self.header_canon),
+                               canon_name(&self.body_canon),
+                               self.sdid,
+                               self.selector,
+                               self.header_fields.connect(":"),
+                               base64(body_hash));
+
+        sign_headers(&self.key, self.header_canon.clone(), 
+                     &select_headers(&self.header_fields, headers), 
+                     "DKIM-Signature", &unsigned)
+    }
+}
+
+// Signs the given headers followed by a signature header, whose value is
+// given with an empty b= tag at the end, and returns the completed value
+pub fn sign_headers(key: &DkimSigningKey, header_canon: CanonicalizationType,
+                    headers: &[&(String, String, Vec<u8>)], name: &str, unsigned: &str)
+    -> Result<String, DkimSigningError> {
+
+    let mut canon = Canonicalizer::head(header_canon);
+    let mut canonicalized_headers = vec![];
+    for header in headers.iter() {
+        let (ref name, ref value, ref raw) = **header;
+        canonicalized_headers.extend(canon.canonicalize(name.clone(), value.clone(), raw.clone()));
+    }
+    let raw = format!("{}: {}\r\n", name, unsigned);
+    let signature_header = canonicalize_signature_header(&mut *canon, name, unsigned, raw.as_bytes());
+
+    match hash_headers(key.algorithm(), &canonicalized_headers, &signature_header) {
+        Ok(hash) => Ok(format!("{}{}", unsigned, base64(&key.sign(&hash)))),
+        Err(_) => Err(DkimSigningError::HashError)
+    }
+}
+
+fn canon_name(canon: &CanonicalizationType) -> &'static str {
+    match *canon {
+        CanonicalizationType::Simple => "simple",
+        CanonicalizationType::Relaxed => "relaxed"
+    }
+}
+
+#[cfg(test)]
+fn round_trip(key: DkimSigningKey, key_record: &str, header_canon: CanonicalizationType,
+              body_canon: CanonicalizationType, tamper: bool) -> super::DkimResult {
+    use super::{DkimSignature, DkimVerifier, DkimPublicKey};
+    use super::test_header;
+
+    let headers: Vec<(String, String, Vec<u8>)> = vec![
+        "From: Joe SixPack <joe@football.example.com>\r\n",
+        "To: Suzie Q <suzie@shopping.example.net>\r\n",
+        "Subject:  Is dinner\r\n  ready?\r\n"].iter().map(|h| test_header(h)).collect();
+    let body = b"Hi.\r\n\r\nWe lost the game.  Are you hungry yet?\r\n\r\n\r\n".to_vec();
+
+    let header_fields = vec!["from".to_string(), "to".to_string(), "subject".to_string()];
+    let signer = DkimSigner::new(key, "test", "example.com", header_fields, header_canon, body_canon);
+    let mut body_hasher = signer.body_hasher();
+    body_hasher.update(&body).unwrap();
+    let value = signer.sign(&headers, &body_hasher.finish().unwrap()).unwrap();
+
+    let raw = format!("DKIM-Signature: {}\r\n", value).into_bytes();
+    let signature = DkimSignature::parse(&value).unwrap();
+    let mut verifier = DkimVerifier::new(signature, "DKIM-Signature".to_string(), value.clone(), raw);
+    for &(ref name, ref value, ref raw) in headers.iter() {
+        verifier.add_header(name.clone(), value.clone(), raw.clone());
+    }
+    if tamper {
+        verifier.update_body(&b"Hi.\r\n\r\nWe won the game.\r\n".to_vec()).unwrap();
+    }
+    else {
+        verifier.update_body(&body).unwrap();
+    }
+    verifier.finalize_body(&DkimPublicKey::parse(key_record).unwrap())
+}
+
+#[cfg(test)]
+fn rsa_test_key() -> (DkimSigningKey, String) {
+    let mut key = PKey::new();
+    key.gen(1024);
+    let record = format!("v=DKIM1; k=rsa; p={}", base64(&key.save_pub()));
+    (DkimSigningKey::Rsa(key), record)
+}
+
+#[cfg(test)]
+fn ed25519_test_key() -> (DkimSigningKey, String) {
+    use super::rustc_serialize::base64::FromBase64;
+    use super::TEST_ED25519_PUBLIC_KEY;
+
+    let seed = "nWGxne/9WmC6hEr0kuwsxERJxWl7MmkZcDusAxyuf2A=".from_base64().unwrap();
+    let record = format!("v=DKIM1; k=ed25519; p={}", TEST_ED25519_PUBLIC_KEY);
+    (DkimSigningKey::ed25519_from_seed(&seed).unwrap(), record)
+}
+
+#[test]
+fn test_rsa_sign_and_verify() {
+    use super::DkimStatus;
+    use super::canonicalizer::CanonicalizationType::{Simple, Relaxed};
+
+    let (key, record) = rsa_test_key();
+    assert_eq!(DkimStatus::Pass, round_trip(key, &record, Simple, Simple, false).status);
+
+    let (key, record) = rsa_test_key();
+    assert_eq!(DkimStatus::Pass, round_trip(key, &record, Relaxed, Relaxed, false).status);
+}
+
+#[test]
+fn test_ed25519_sign_and_verify() {
+    use super::DkimStatus;
+    use super::canonicalizer::CanonicalizationType::{Simple, Relaxed};
+
+    let (key, record) = ed25519_test_key();
+    assert_eq!(DkimStatus::Pass, round_trip(key, &record, Relaxed, Simple, false).status);
+
+    let (key, record) = ed25519_test_key();
+    assert_eq!(DkimStatus::Pass, round_trip(key, &record, Simple, Relaxed, false).status);
+}
+
+#[test]
+fn test_sign_and_verify_tampered_body() {
+    use super::DkimReason;
+    use super::canonicalizer::CanonicalizationType::Relaxed;
+
+    let (key, record) = ed25519_test_key();
+    assert_eq!(Some(DkimReason::BodyHashMismatch),
+               round_trip(key, &record, Relaxed, Relaxed, true).reason);
+}
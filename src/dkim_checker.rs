@@ -1,24 +1,47 @@
 extern crate openssl;
 
+use std::mem;
+
 use events::MessageParserEvent;
-use events::{MessageParserStage,MessageParserFilter};
-use events::MessageParserEvent::{Header, BodyChunk};
+use events::MessageParserStage;
+use events::MessageParserEvent::{Header, EndOfHeaders, BodyChunk, DkimResult, End};
+
+use self::DkimState::{Headers,Body,Finished};
 
-use self::DkimState::{Start,DkimSignatureSeen,Finished};
+use std::ascii::AsciiExt;
 
 use dkim::DkimSignature;
 use dkim::DkimVerifier;
+use dkim::DkimVerificationError;
+use dkim::{DkimKeyLookup, DkimReason, lookup_public_key};
+use dkim;
+use clock::{Clock, SYSTEM_CLOCK};
+#[cfg(test)]
+use dkim::DkimStatus;
+#[cfg(test)]
+use dns::ZoneFile;
+#[cfg(test)]
+use clock::FixedClock;
 
 pub struct DkimChecker<'a> {
     state: DkimState,
+    headers: Vec<(String, String, Vec<u8>)>,
     signatures: Vec<DkimVerifier>,
+    results: Vec<dkim::DkimResult>,
+    key_lookup: &'a (DkimKeyLookup + 'a),
+    clock: &'a (Clock + 'a),
+    clock_skew: u64,
     next_stage: &'a mut (MessageParserStage + 'a)
 }
 
+// How far, in seconds, a signer's clock may be from ours before a signature
+// is considered expired or future dated
+pub const DEFAULT_CLOCK_SKEW: u64 = 300;
+
 #[derive(Debug, Clone)]
 enum DkimState {
-    Start,
-    DkimSignatureSeen,
+    Headers,
+    Body,
     Finished
 }
 
@@ -26,8 +49,8 @@ impl<'a> MessageParserStage for DkimChecker<'a> {
 
     fn process_event(&mut self, event: MessageParserEvent) {
         let next_state = match self.state {
-            Start => self.parse_dkim_headers(event),
-            DkimSignatureSeen => self.parse_message(event),
+            Headers => self.parse_headers(event),
+            Body => self.parse_body(event),
             Finished => {
                 self.next_stage.process_event(event);
                 Finished
@@ -38,77 +61,134 @@ impl<'a> MessageParserStage for DkimChecker<'a> {
     }
 }
 
-impl<'a> MessageParserFilter<'a> for DkimChecker<'a> {
-    fn new(next_stage: &'a mut MessageParserStage) -> DkimChecker<'a> {
+impl<'a> DkimChecker<'a> {
+    pub fn new(next_stage: &'a mut MessageParserStage, key_lookup: &'a DkimKeyLookup) -> DkimChecker<'a> {
+        DkimChecker::with_clock(next_stage, key_lookup, &SYSTEM_CLOCK, DEFAULT_CLOCK_SKEW)
+    }
+
+    pub fn with_clock(next_stage: &'a mut MessageParserStage, key_lookup: &'a DkimKeyLookup, 
+                      clock: &'a Clock, clock_skew: u64) -> DkimChecker<'a> {
         DkimChecker {
-            state: Start,
+            state: Headers,
+            headers: vec![],
             signatures: vec![],
+            results: vec![],
+            key_lookup: key_lookup,
+            clock: clock,
+            clock_skew: clock_skew,
             next_stage: next_stage
         }
     }
-}
 
-impl<'a> DkimChecker<'a> {
-    fn parse_dkim_headers(&mut self, event: MessageParserEvent) -> DkimState {
-        let dkim_signature_header = "DKIM-Signature".to_string();
+    // The whole header block is collected before any signature is checked,
+    // since signed headers may appear above or below the signature
+    fn parse_headers(&mut self, event: MessageParserEvent) -> DkimState {
         match event {
-            Header(ref name, ref value, _) if *name == dkim_signature_header => {
-                //println!("===>  DKIM-Signature: {}", value);
-                let signature = DkimSignature::parse(&value);
-                match signature {
-                    Ok(s) => {
-                        self.signatures.push( DkimVerifier::new(s) );
-                    }
-                    Err(e) => {
-                        println!("{:?}", e);
-                    }
-                }
-                DkimSignatureSeen
+            Header(ref name, ref value, ref raw) => {
+                self.headers.push((name.clone(), value.clone(), raw.clone()));
+            }
+            _ => ()
+        }
+
+        match event {
+            EndOfHeaders => {
+                self.start_verifiers();
+                self.next_stage.process_event(event);
+                Body
+            }
+            End => {
+                self.start_verifiers();
+                self.parse_body(event)
             }
             _ => {
                 self.next_stage.process_event(event);
-                self.state.clone()
+                Headers
             }
         }
     }
-  
-    // this function is not complete yet, eventually all results will 
-    // be used
-    #[allow(unused_must_use)]
-    fn parse_message(&mut self, event: MessageParserEvent) -> DkimState {
-        match event {
-            Header(ref name, ref value, ref raw) => {
-                for sig in self.signatures.iter_mut() {
-                    sig.add_header(name.clone(), value.clone(), raw.clone());
+
+    fn start_verifiers(&mut self) {
+        for (i, &(ref name, ref value, ref raw)) in self.headers.iter().enumerate() {
+            if !name.trim().eq_ignore_ascii_case("DKIM-Signature") {
+                continue;
+            }
+            match DkimSignature::parse(&value) {
+                Ok(s) => {
+                    let mut verifier = DkimVerifier::new(s, name.clone(), value.clone(), raw.clone());
+                    // a signature doesn't sign itself
+                    for (j, &(ref name, ref value, ref raw)) in self.headers.iter().enumerate() {
+                        if j != i {
+                            verifier.add_header(name.clone(), value.clone(), raw.clone());
+                        }
+                    }
+                    self.signatures.push(verifier);
+                }
+                Err(e) => {
+                    self.results.push(dkim::DkimResult::invalid_signature(&value, e));
                 }
-                self.next_stage.process_event(event.clone());
-                self.state.clone()
             }
+        }
+    }
+  
+    fn parse_body(&mut self, event: MessageParserEvent) -> DkimState {
+        match event {
             BodyChunk(ref data) => {
-                for sig in self.signatures.iter_mut() {
-                    sig.update_body(data);
+                let signatures = mem::replace(&mut self.signatures, vec![]);
+                for mut sig in signatures.into_iter() {
+                    let updated = sig.update_body(data);
+                    self.check_body_update(sig, updated);
                 }
                 self.next_stage.process_event(event.clone());
-                DkimSignatureSeen
+                Body
             }
-            MessageParserEvent::End => {
-                loop {
-                    match self.signatures.pop() {
-                        Some( sig ) => {
-                            sig.finalize_body();
-                        }
-                        None => break
-                    };
+            End => {
+                let signatures = mem::replace(&mut self.signatures, vec![]);
+                for sig in signatures.into_iter() {
+                    let result = self.finalize_signature(sig);
+                    self.results.push(result);
+                }
+                for result in self.results.iter() {
+                    self.next_stage.process_event(DkimResult(result.clone()));
                 }
                 self.next_stage.process_event(event);
                 Finished
             }
             _ => {
                 self.next_stage.process_event(event);
-                DkimSignatureSeen
+                Body
             }
         }
     }
+
+    // A signature whose body can't be hashed is given a temporary error and
+    // isn't checked any further
+    fn check_body_update(&mut self, sig: DkimVerifier, updated: Result<usize, DkimVerificationError>) {
+        match updated {
+            Ok(_) => self.signatures.push(sig),
+            Err(_) => self.results.push(sig.reject(DkimReason::HashError))
+        }
+    }
+
+    fn finalize_signature(&self, sig: DkimVerifier) -> dkim::DkimResult {
+        let now = self.clock.now();
+        if sig.signature().is_expired(now, self.clock_skew) {
+            return sig.reject(DkimReason::SignatureExpired);
+        }
+        match sig.signature().check_identity() {
+            Err(reason) => return sig.reject(reason),
+            Ok(_) => ()
+        }
+        let future_dated = sig.signature().is_future_dated(now, self.clock_skew);
+
+        let public_key = lookup_public_key(self.key_lookup, sig.signature().selector(), 
+                                           sig.signature().sdid());
+        let mut result = match public_key {
+            Ok(public_key) => sig.finalize_body(&public_key),
+            Err(reason) => sig.reject(reason)
+        };
+        result.future_dated = future_dated;
+        result
+    }
 }
 
 #[test]
@@ -122,11 +202,153 @@ Body".to_string();
 
     let expected_events = vec![];
 
-    test_message_parser(s, expected_events);
+    test_message_parser(s, &ZoneFile::new(), expected_events);
+}
+
+#[cfg(test)]
+pub const TEST_MESSAGE: &'static str = "DKIM-Signature: v=1; a=rsa-sha256; c=relaxed/relaxed; d=example.com;\r\n s=brisbane; t=1117574938; h=From:To:Subject:Date:Message-ID;\r\n bh=2jUSOH9NhtVGCQWNr9BrIAPreKQjO6Sn7XIkfJVOzv8=;\r\n b=MhLD2q562xToDqRZ9udS1txK2ltR+RRB0mO/576I2pr2KUU1LFp10yMcBlIxNgnQegHDZQ8taYrIa15MzVaF2ud41hEv30aEV9mOAa1+luoy+6Xj98X9hhvpecWXaltLGBE2XUF1LmTej1qONFwc6s9o+bqHeEJGWLn2wIlCtBw=\r\n\
+From: Joe SixPack <joe@football.example.com>\r\n\
+To: Suzie Q <suzie@shopping.example.net>\r\n\
+Subject: Is dinner ready?\r\n\
+Date: Fri, 11 Jul 2003 21:00:37 -0700 (PDT)\r\n\
+Message-ID: <20030712040037.46341.5F8J@football.example.com>\r\n\
+\r\n\
+Hi.\r\n\r\nWe lost the game. Are you hungry yet?\r\n\r\nJoe.\r\n";
+
+#[cfg(test)]
+fn test_result(reason: Option<DkimReason>) -> MessageParserEvent {
+    DkimResult(dkim::test_dkim_result("example.com", reason))
+}
+
+#[test]
+fn dkim_pass_test() {
+    let mut keys = ZoneFile::new();
+    keys.add_txt("brisbane._domainkey.example.com", 
+                 &format!("v=DKIM1; k=rsa; p={}", dkim::TEST_PUBLIC_KEY));
+
+    let expected_events = vec![test_result(None)];
+
+    test_message_parser(TEST_MESSAGE.to_string(), &keys, expected_events);
+}
+
+#[test]
+fn dkim_multiple_signatures_test() {
+    let mut keys = ZoneFile::new();
+    keys.add_txt("brisbane._domainkey.example.com", 
+                 &format!("v=DKIM1; k=rsa; p={}", dkim::TEST_PUBLIC_KEY));
+
+    // signatures above, between and below the headers they sign
+    let msg = "DKIM-Signature: v=1; a=rsa-sha256; c=simple/simple; d=example.com; s=brisbane; t=1117574938; h=From:To:Subject:Date:Message-ID; bh=2jUSOH9NhtVGCQWNr9BrIAPreKQjO6Sn7XIkfJVOzv8=; b=MyGDknexEZRuayarQGyqQShlfRlbQ8AzywoKxcwujL3Fby0cfmefR68Nm/uvMTP4z+z++58t0kxkIIlHg+uXtSvtRIJt/SdYAs//X9DFxv3g8bKlkxShRuD3EUXpcVktyD5Ld7morgowtD/JJIydrQM+/nVrXfafBAWgJLwTi+U=\r\n\
+               From: Joe SixPack <joe@football.example.com>\r\n\
+               To: Suzie Q <suzie@shopping.example.net>\r\n\
+               DKIM-Signature: v=1; a=rsa-sha256; d=example.com; s=brisbane; h=From:To; bh=2jUSOH9NhtVGCQWNr9BrIAPreKQjO6Sn7XIkfJVOzv8=; b=AAAA\r\n\
+               Subject: Is dinner ready?\r\n\
+               Date: Fri, 11 Jul 2003 21:00:37 -0700 (PDT)\r\n\
+               Message-ID: <20030712040037.46341.5F8J@football.example.com>\r\n\
+               DKIM-Signature: v=1; a=rsa-sha256; c=relaxed/simple; d=example.com;\r\n s=brisbane; t=1117574938; h=From:To:Subject:Date:Message-ID;\r\n bh=2jUSOH9NhtVGCQWNr9BrIAPreKQjO6Sn7XIkfJVOzv8=;\r\n b=rwYui+UZdH/dCm3KqIv4GvVTWqIxEZyx0eULQuju5GQcASlIfyGry+gDBBjPMppxK8eotojTkQYFupZR90exEm/DbFd722AUeYfAOSxwFlSoG0SfiBJ0NDqXMmMPzAy87ke7QLR+J43tLzjgxYsl4qh5dmzmuNbgX7KqTbqEGEg=\r\n\
+               \r\n\
+               Hi.\r\n\r\nWe lost the game. Are you hungry yet?\r\n\r\nJoe.\r\n";
+
+    let expected_events = vec![test_result(None),
+                               test_result(Some(DkimReason::SignatureMismatch))];
+
+    let events = test_message_parser(msg.to_string(), &keys, expected_events);
+    let results = events.iter().filter(|e| match **e { DkimResult(_) => true, _ => false }).count();
+    assert_eq!(3, results);
+    // both the signature above the headers and the one below them verify
+    let passes = events.iter().filter(|e| match **e {
+        DkimResult(ref result) => result.status == DkimStatus::Pass,
+        _ => false
+    }).count();
+    assert_eq!(2, passes);
+}
+
+#[test]
+fn dkim_missing_key_test() {
+    let expected_events = vec![test_result(Some(DkimReason::KeyNotFound))];
+
+    test_message_parser(TEST_MESSAGE.to_string(), &ZoneFile::new(), expected_events);
+}
+
+#[test]
+fn dkim_body_hash_error_test() {
+    use message_parser_sink::MessageParserSink;
+
+    let raw = &TEST_MESSAGE[..TEST_MESSAGE.find("\r\nFrom:").unwrap() + 2];
+    let value = raw["DKIM-Signature:".len()..].trim().to_string();
+
+    let mut sink = MessageParserSink::new();
+    {
+        let keys = ZoneFile::new();
+        let mut dkim = DkimChecker::new(&mut sink, &keys);
+        dkim.process_event(Header("DKIM-Signature".to_string(), value, raw.bytes().collect()));
+        dkim.process_event(EndOfHeaders);
+        let sig = dkim.signatures.pop().unwrap();
+        dkim.check_body_update(sig, Err(DkimVerificationError::HashError));
+        dkim.process_event(BodyChunk(b"Hi.\r\n".to_vec()));
+        dkim.process_event(End);
+    }
+
+    // the key isn't looked up for a signature that couldn't be hashed
+    let events = sink.events();
+    let results: Vec<&MessageParserEvent> = events.iter()
+        .filter(|e| match **e { DkimResult(_) => true, _ => false })
+        .collect();
+    assert_eq!(vec![&test_result(Some(DkimReason::HashError))], results);
+}
+
+#[cfg(test)]
+const EXPIRING_SIGNATURE: &'static str = "DKIM-Signature: v=1; a=rsa-sha256; c=relaxed/relaxed; d=example.com;\r\n s=brisbane; t=1117574938; x=1118006938; h=From:To:Subject:Date:Message-ID;\r\n bh=2jUSOH9NhtVGCQWNr9BrIAPreKQjO6Sn7XIkfJVOzv8=;\r\n b=gXCNSKNSH4aciGqsZz+m+pmM7DtfLIdUjxWfqDZHnewloFsgaogFlDYhJZ8BYNUsm65uyj1KZCaE4Tw7T7clWL5M4T4Gh8cRJrtwxSOVHqfuyVCnNPb5Ybz+ipSBFcDcJ/jXVAVMM2u0ZuvKz4ez9dxWb5KM+76Dyzz/g6KB2NE=\r\n";
+
+// TEST_MESSAGE with its signature replaced by one that also has an expiry
+#[cfg(test)]
+fn expiring_test_message() -> String {
+    let headers_start = TEST_MESSAGE.find("\r\nFrom:").unwrap() + 2;
+    format!("{}{}", EXPIRING_SIGNATURE, &TEST_MESSAGE[headers_start..])
+}
+
+#[test]
+fn dkim_expired_signature_test() {
+    let mut keys = ZoneFile::new();
+    keys.add_txt("brisbane._domainkey.example.com", 
+                 &format!("v=DKIM1; k=rsa; p={}", dkim::TEST_PUBLIC_KEY));
+
+    let expected_events = vec![test_result(Some(DkimReason::SignatureExpired))];
+
+    test_message_parser_at(expiring_test_message(), &keys, &FixedClock::new(1118006938 + DEFAULT_CLOCK_SKEW + 1), 
+                           expected_events);
+    test_message_parser_at(expiring_test_message(), &keys, &FixedClock::new(1118006938 + DEFAULT_CLOCK_SKEW), 
+                           vec![test_result(None)]);
+}
+
+#[test]
+fn dkim_future_dated_signature_test() {
+    let mut keys = ZoneFile::new();
+    keys.add_txt("brisbane._domainkey.example.com", 
+                 &format!("v=DKIM1; k=rsa; p={}", dkim::TEST_PUBLIC_KEY));
+
+    let mut future_dated = test_result(None);
+    match future_dated {
+        DkimResult(ref mut result) => result.future_dated = true,
+        _ => ()
+    }
+
+    test_message_parser_at(expiring_test_message(), &keys, 
+                           &FixedClock::new(1117574938 - DEFAULT_CLOCK_SKEW - 1), vec![future_dated]);
+    test_message_parser_at(expiring_test_message(), &keys, 
+                           &FixedClock::new(1117574938 - DEFAULT_CLOCK_SKEW), 
+                           vec![test_result(None)]);
+}
+
+#[cfg(test)]
+fn test_message_parser(msg: String, keys: &ZoneFile, expected_events: Vec<MessageParserEvent>) 
+    -> Vec<MessageParserEvent> {
+    test_message_parser_at(msg, keys, &SYSTEM_CLOCK, expected_events)
 }
 
 #[cfg(test)]
-fn test_message_parser(msg: String, expected_events: Vec<MessageParserEvent>) {
+fn test_message_parser_at(msg: String, keys: &ZoneFile, clock: &Clock, 
+                          expected_events: Vec<MessageParserEvent>) -> Vec<MessageParserEvent> {
     use message_parser_sink::MessageParserSink;
     use reader_parser::ReaderParser;
     use message_scanner::MessageScanner;
@@ -137,7 +359,7 @@ fn test_message_parser(msg: String, expected_events: Vec<MessageParserEvent>) {
     let mut sink = MessageParserSink::new();
     {
         let r = msg.as_bytes();
-        let mut dkim: DkimChecker = MessageParserFilter::new(&mut sink);
+        let mut dkim = DkimChecker::with_clock(&mut sink, keys, clock, DEFAULT_CLOCK_SKEW);
         let mut parser: HeaderParser = MessageParserFilter::new(&mut dkim);
         let mut scanner: MessageScanner = MessageParserFilter::new(&mut parser);
         let mut rp = ReaderParser::new(&mut scanner, r);
@@ -149,4 +371,5 @@ fn test_message_parser(msg: String, expected_events: Vec<MessageParserEvent>) {
         assert!(sink.contains(e));
     }
 
+    sink.events()
 }
@@ -0,0 +1,146 @@
+use std::mem;
+
+use events::MessageParserEvent;
+use events::MessageParserStage;
+use events::MessageParserEvent::{Envelope, Header, HeaderName, HeaderValue, BodyChunk, End};
+
+use dkim::{DkimSigner, BodyHasher};
+
+// Adds a DKIM-Signature header to each message passing through.  The signature
+// depends on the whole body but has to be emitted ahead of the other headers,
+// so the message is held back until End.  The envelope isn't part of the
+// message and is passed on straight away.
+pub struct DkimSigningStage<'a> {
+    signer: &'a DkimSigner,
+    headers: Vec<(String, String, Vec<u8>)>,
+    body_hasher: BodyHasher,
+    hash_failed: bool,
+    events: Vec<MessageParserEvent>,
+    next_stage: &'a mut (MessageParserStage + 'a)
+}
+
+impl<'a> DkimSigningStage<'a> {
+    pub fn new(next_stage: &'a mut MessageParserStage, signer: &'a DkimSigner) -> DkimSigningStage<'a> {
+        DkimSigningStage {
+            signer: signer,
+            headers: vec![],
+            body_hasher: signer.body_hasher(),
+            hash_failed: false,
+            events: vec![],
+            next_stage: next_stage
+        }
+    }
+
+    // a message that can't be signed is passed on unsigned
+    fn emit_signature(&mut self) {
+        if self.hash_failed {
+            return;
+        }
+        let signature = match self.body_hasher.finish() {
+            Ok(body_hash) => self.signer.sign(&self.headers, &body_hash),
+            Err(_) => return
+        };
+
+        match signature {
+            Ok(value) => {
+                let name = "DKIM-Signature".to_string();
+                let raw = format!("{}: {}\r\n", name, value).into_bytes();
+                self.next_stage.process_event(HeaderName(format!("{}:", name)));
+                self.next_stage.process_event(HeaderValue(format!(" {}\r\n", value)));
+                self.next_stage.process_event(Header(name, value, raw));
+            }
+            Err(_) => ()
+        }
+    }
+}
+
+impl<'a> MessageParserStage for DkimSigningStage<'a> {
+    fn process_event(&mut self, event: MessageParserEvent) {
+        match event {
+            Envelope(_) => {
+                self.next_stage.process_event(event);
+                return;
+            }
+            Header(ref name, ref value, ref raw) => {
+                self.headers.push((name.clone(), value.clone(), raw.clone()));
+            }
+            BodyChunk(ref data) => {
+                if !self.hash_failed {
+                    self.hash_failed = self.body_hasher.update(data).is_err();
+                }
+            }
+            End => {
+                self.emit_signature();
+                for e in mem::replace(&mut self.events, vec![]).into_iter() {
+                    self.next_stage.process_event(e);
+                }
+                self.next_stage.process_event(End);
+                return;
+            }
+            _ => ()
+        }
+        self.events.push(event);
+    }
+}
+
+#[test]
+fn sign_and_check_test() {
+    use std::str::from_utf8;
+    use message_parser_sink::MessageParserSink;
+    use reader_parser::ReaderParser;
+    use message_scanner::MessageScanner;
+    use header_parser::HeaderParser;
+    use dkim_checker::DkimChecker;
+    use events::MessageParserFilter;
+    use dns::ZoneFile;
+    use envelope;
+    use dkim::{DkimSigningKey, DkimStatus, CanonicalizationType, TEST_ED25519_PUBLIC_KEY};
+
+    let msg = "From: Joe SixPack <joe@football.example.com>\r\n\
+               To: Suzie Q <suzie@shopping.example.net>\r\n\
+               Subject: Is dinner ready?\r\n\
+               \r\n\
+               Hi.\r\n\r\nWe lost the game. Are you hungry yet?\r\n\r\nJoe.\r\n";
+
+    let mut keys = ZoneFile::new();
+    keys.add_txt("test._domainkey.example.com",
+                 &format!("v=DKIM1; k=ed25519; p={}", TEST_ED25519_PUBLIC_KEY));
+
+    let seed = [0x9d, 0x61, 0xb1, 0x9d, 0xef, 0xfd, 0x5a, 0x60, 0xba, 0x84, 0x4a, 0xf4, 0x92, 0xec, 0x2c, 0xc4,
+                0x44, 0x49, 0xc5, 0x69, 0x7b, 0x32, 0x69, 0x19, 0x70, 0x3b, 0xac, 0x03, 0x1c, 0xae, 0x7f, 0x60];
+    let signer = DkimSigner::new(DkimSigningKey::ed25519_from_seed(&seed).unwrap(),
+                                 "test", "example.com",
+                                 vec!["from".to_string(), "to".to_string(), "subject".to_string()],
+                                 CanonicalizationType::Relaxed, CanonicalizationType::Relaxed);
+
+    let mut envelope = envelope::Envelope::new();
+    envelope.mail_from = Some("joe@football.example.com".to_string());
+
+    let mut sink = MessageParserSink::new();
+    {
+        let r = msg.as_bytes();
+        let mut dkim = DkimChecker::new(&mut sink, &keys);
+        let mut signing = DkimSigningStage::new(&mut dkim, &signer);
+        let mut parser: HeaderParser = MessageParserFilter::new(&mut signing);
+        let mut scanner: MessageScanner = MessageParserFilter::new(&mut parser);
+        let mut rp = ReaderParser::with_envelope(&mut scanner, r, envelope.clone());
+
+        rp.read_to_end();
+    }
+
+    // the envelope still comes first, ahead of the new signature
+    let events = sink.events();
+    assert_eq!(Envelope(envelope), events[0]);
+    assert!(match events[1] {
+        HeaderName(ref name) => name == "DKIM-Signature:",
+        _ => false
+    });
+    assert!(events.iter().any(|e| match *e {
+        MessageParserEvent::DkimResult(ref result) => result.status == DkimStatus::Pass,
+        _ => false
+    }));
+    assert!(events.iter().any(|e| match *e {
+        BodyChunk(ref data) => from_utf8(data).unwrap().starts_with("Hi."),
+        _ => false
+    }));
+}
@@ -0,0 +1,414 @@
+use std::ascii::AsciiExt;
+
+use dns::{DnsError, ZoneFile};
+use dkim::{DkimResult, DkimStatus, parse_dkim_signature};
+use public_suffix::organizational_domain;
+#[cfg(test)]
+use dkim::{DkimReason, test_dkim_result};
+
+mod result;
+mod report;
+mod report_parser;
+mod report_summary;
+
+pub use self::result::{DmarcResult, DmarcStatus, DmarcReason, DmarcPolicy};
+pub use self::report::{DmarcReporter, AggregateReport, PublishedPolicy, ReportRecord};
+pub use self::report::{DkimAuthResult, SpfAuthResult, ReportOutput, ReportDirectory};
+pub use self::report_parser::{ReportParseError, MAX_REPORT_SIZE};
+pub use self::report_summary::{ReportSummary, PassRate};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum DmarcRecordError {
+    // the record doesn't start with v=DMARC1
+    NotDmarc,
+    MissingPolicy,
+    BadTag(String)
+}
+
+// Identifier alignment modes, from the adkim= and aspf= tags
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DmarcAlignment {
+    Relaxed,
+    Strict
+}
+
+impl DmarcAlignment {
+    // The adkim= or aspf= value, which aggregate reports use too
+    pub fn tag(&self) -> &'static str {
+        match *self {
+            DmarcAlignment::Relaxed => "r",
+            DmarcAlignment::Strict => "s"
+        }
+    }
+}
+
+// Retrieves the policy records published at _dmarc.domain
+pub trait DmarcLookup {
+    fn lookup_dmarc(&self, domain: &str) -> Result<Vec<String>, DnsError>;
+}
+
+impl DmarcLookup for ZoneFile {
+    fn lookup_dmarc(&self, domain: &str) -> Result<Vec<String>, DnsError> {
+        self.txt(&format!("_dmarc.{}", domain))
+    }
+}
+
+// A DMARC policy record (RFC 7489 section 6.3)
+#[derive(Debug, PartialEq, Clone)]
+pub struct DmarcRecord {
+    pub policy: DmarcPolicy,
+    pub subdomain_policy: Option<DmarcPolicy>,
+    pub dkim_alignment: DmarcAlignment,
+    pub spf_alignment: DmarcAlignment,
+    pub percent: u32,
+    pub aggregate_report_uris: Vec<String>,
+    pub failure_report_uris: Vec<String>,
+    // fo=, the colon separated failure reporting options
+    pub failure_options: String,
+    pub report_interval: u32
+}
+
+impl DmarcRecord {
+    pub fn parse(record: &str) -> Result<DmarcRecord, DmarcRecordError> {
+        use self::DmarcRecordError::{NotDmarc, MissingPolicy, BadTag};
+
+        // v= has to be the first tag
+        let version: Vec<&str> = record.splitn(2, ';').next().unwrap_or("").splitn(2, '=')
+            .map(|s| s.trim()).collect();
+        if version != ["v", "DMARC1"] {
+            return Err(NotDmarc);
+        }
+
+        let tags = try!(parse_dkim_signature(record).map_err(|_| BadTag(record.to_string())));
+        let tag = |name: &str| tags.get(&name).map(|v| v.to_string());
+
+        let alignment = |name: &str| match tag(name) {
+            None => Ok(DmarcAlignment::Relaxed),
+            Some(ref a) if *a == "r" => Ok(DmarcAlignment::Relaxed),
+            Some(ref a) if *a == "s" => Ok(DmarcAlignment::Strict),
+            Some(_) => Err(BadTag(name.to_string()))
+        };
+        let uris = |name: &str| tag(name).map_or(vec![], |uris| {
+            uris.split(',').map(|u| u.trim().to_string()).filter(|u| !u.is_empty()).collect()
+        });
+        let aggregate_report_uris = uris("rua");
+
+        // a record with reporting but no valid policy is treated as p=none
+        // (RFC 7489 section 6.6.3)
+        let policy = match tag("p").and_then(|p| DmarcPolicy::from_name(&p.to_ascii_lowercase())) {
+            Some(policy) => policy,
+            None if !aggregate_report_uris.is_empty() => DmarcPolicy::None,
+            None => return Err(MissingPolicy)
+        };
+        let subdomain_policy = match tag("sp") {
+            Some(sp) => Some(try!(DmarcPolicy::from_name(&sp.to_ascii_lowercase()).ok_or(BadTag("sp".to_string())))),
+            None => None
+        };
+        let percent = match tag("pct") {
+            Some(pct) => match pct.parse() {
+                Ok(pct) if pct <= 100 => pct,
+                _ => return Err(BadTag("pct".to_string()))
+            },
+            None => 100
+        };
+        let report_interval = match tag("ri") {
+            Some(ri) => try!(ri.parse().map_err(|_| BadTag("ri".to_string()))),
+            None => 86400
+        };
+
+        Ok(DmarcRecord {
+            policy: policy,
+            subdomain_policy: subdomain_policy,
+            dkim_alignment: try!(alignment("adkim")),
+            spf_alignment: try!(alignment("aspf")),
+            percent: percent,
+            aggregate_report_uris: aggregate_report_uris,
+            failure_report_uris: uris("ruf"),
+            failure_options: tag("fo").unwrap_or("0".to_string()),
+            report_interval: report_interval
+        })
+    }
+}
+
+// Finds the domain of the RFC5322.From address in a message's headers.  A
+// message must have exactly one From header, and if it lists several authors
+// they must all be in the same domain.
+pub fn from_domain(headers: &[(String, String, Vec<u8>)]) -> Result<String, DmarcReason> {
+    let from: Vec<&String> = headers.iter()
+        .filter(|&&(ref name, _, _)| name.trim().eq_ignore_ascii_case("From"))
+        .map(|&(_, ref value, _)| value)
+        .collect();
+
+    let value = match from.len() {
+        0 => return Err(DmarcReason::MissingFrom),
+        1 => from[0],
+        _ => return Err(DmarcReason::MultipleFrom)
+    };
+
+    let mut domain: Option<String> = None;
+    for address in split_addresses(value).iter() {
+        let address_domain = match address.rfind('@') {
+            Some(at) if at + 1 < address.len() =>
+                address[at + 1..].trim_right_matches('.').to_ascii_lowercase(),
+            _ => return Err(DmarcReason::BadFromAddress(value.trim().to_string()))
+        };
+        match domain {
+            Some(ref d) if *d != address_domain => return Err(DmarcReason::MultipleFrom),
+            _ => ()
+        }
+        domain = Some(address_domain);
+    }
+
+    domain.ok_or(DmarcReason::BadFromAddress(value.trim().to_string()))
+}
+
+// The addr-specs in an address list, with display names, group names and
+// comments removed
+fn split_addresses(value: &str) -> Vec<String> {
+    let mut addresses = vec![];
+    let mut current = String::new();
+    let mut angle: Option<String> = None;
+    let mut angle_addr: Option<String> = None;
+    let mut in_quote = false;
+    let mut depth = 0;
+
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if in_quote || depth > 0 {
+            match c {
+                '\\' => { chars.next(); }
+                '"' if in_quote => in_quote = false,
+                '(' if depth > 0 => depth = depth + 1,
+                ')' if depth > 0 => depth = depth - 1,
+                _ => ()
+            }
+            continue;
+        }
+
+        match c {
+            '"' => in_quote = true,
+            '(' => depth = depth + 1,
+            '<' => angle = Some(String::new()),
+            '>' => angle_addr = angle.take(),
+            ',' | ';' => {
+                match angle_addr.take() {
+                    Some(addr) => addresses.push(addr),
+                    None if current.contains('@') => addresses.push(current.clone()),
+                    None => ()
+                }
+                current.clear();
+            }
+            // the end of a group name
+            ':' if angle.is_none() => current.clear(),
+            _ if c.is_whitespace() => (),
+            _ => match angle {
+                Some(ref mut a) => a.push(c),
+                None => current.push(c)
+            }
+        }
+    }
+
+    match angle_addr {
+        Some(addr) => addresses.push(addr),
+        None if current.contains('@') => addresses.push(current),
+        None => ()
+    }
+    addresses
+}
+
+fn aligned(domain: &str, from_domain: &str, alignment: DmarcAlignment) -> bool {
+    match alignment {
+        DmarcAlignment::Strict => domain.eq_ignore_ascii_case(from_domain),
+        DmarcAlignment::Relaxed => organizational_domain(domain) == organizational_domain(from_domain)
+    }
+}
+
+// Applies the DMARC policy of a message's author domain (RFC 7489 section 6.6)
+pub struct DmarcEvaluator<'a> {
+    lookup: &'a (DmarcLookup + 'a)
+}
+
+impl<'a> DmarcEvaluator<'a> {
+    pub fn new(lookup: &'a DmarcLookup) -> DmarcEvaluator<'a> {
+        DmarcEvaluator { lookup: lookup }
+    }
+
+    // Looks up the policy for a From domain, falling back to its organizational
+    // domain.  Returns the domain the record was found at.
+    pub fn find_policy(&self, from_domain: &str) -> Result<(String, DmarcRecord), DmarcReason> {
+        match try!(self.lookup_record(from_domain)) {
+            Some(record) => return Ok((from_domain.to_string(), record)),
+            None => ()
+        }
+        let org_domain = organizational_domain(from_domain);
+        if org_domain != from_domain {
+            match try!(self.lookup_record(&org_domain)) {
+                Some(record) => return Ok((org_domain, record)),
+                None => ()
+            }
+        }
+        Err(DmarcReason::NoPolicy)
+    }
+
+    fn lookup_record(&self, domain: &str) -> Result<Option<DmarcRecord>, DmarcReason> {
+        let records = match self.lookup.lookup_dmarc(domain) {
+            Ok(records) => records,
+            Err(DnsError::NotFound) => return Ok(None),
+            Err(DnsError::TempFail(_)) => return Err(DmarcReason::PolicyUnavailable)
+        };
+
+        // anything other than exactly one DMARC record means there's no policy
+        let mut dmarc_records: Vec<Result<DmarcRecord, DmarcRecordError>> = records.iter()
+            .map(|r| DmarcRecord::parse(r))
+            .filter(|r| *r != Err(DmarcRecordError::NotDmarc))
+            .collect();
+        if dmarc_records.len() != 1 {
+            return Ok(None);
+        }
+        Ok(dmarc_records.pop().unwrap().ok())
+    }
+
+    // Evaluates a message from from_domain with the given DKIM results.
+    // spf_domain is the RFC5321.MailFrom domain, if SPF passed for it.
+    // sample, from 0 to 99, decides whether the policy is applied to a
+    // message when the record's pct= is below 100.
+    pub fn evaluate(&self, from_domain: &str, dkim_results: &[DkimResult],
+                    spf_domain: Option<&str>, sample: u32) -> DmarcResult {
+        let from_domain = from_domain.to_ascii_lowercase();
+        let (policy_domain, record) = match self.find_policy(&from_domain) {
+            Ok(policy) => policy,
+            Err(reason) => {
+                let status = match reason {
+                    DmarcReason::PolicyUnavailable => DmarcStatus::TempError,
+                    _ => DmarcStatus::None
+                };
+                return DmarcResult::error(status, reason, Some(from_domain));
+            }
+        };
+
+        let dkim_domain = dkim_results.iter()
+            .filter(|r| r.status == DkimStatus::Pass)
+            .filter_map(|r| r.sdid.as_ref())
+            .find(|d| aligned(d, &from_domain, record.dkim_alignment))
+            .map(|d| d.to_ascii_lowercase());
+        let spf_aligned = spf_domain.map_or(false, |d| aligned(d, &from_domain, record.spf_alignment));
+
+        // sp= only applies when the record came from the organizational domain
+        let policy = if policy_domain != from_domain {
+            record.subdomain_policy.unwrap_or(record.policy)
+        }
+        else {
+            record.policy
+        };
+
+        let passed = dkim_domain.is_some() || spf_aligned;
+        let disposition = if passed {
+            DmarcPolicy::None
+        }
+        else if sample < record.percent {
+            policy
+        }
+        else {
+            // messages not sampled get the next less strict policy
+            // (RFC 7489 section 6.6.4)
+            match policy {
+                DmarcPolicy::Reject => DmarcPolicy::Quarantine,
+                _ => DmarcPolicy::None
+            }
+        };
+
+        DmarcResult {
+            status: if passed { DmarcStatus::Pass } else { DmarcStatus::Fail },
+            reason: if passed { None } else { Some(DmarcReason::NotAligned) },
+            from_domain: Some(from_domain),
+            policy_domain: Some(policy_domain),
+            record: Some(record),
+            policy: Some(policy),
+            disposition: disposition,
+            dkim_domain: dkim_domain,
+            spf_aligned: spf_aligned
+        }
+    }
+}
+
+#[test]
+fn test_parse_dmarc_record() {
+    let record = DmarcRecord::parse("v=DMARC1; p=Reject; sp=quarantine; adkim=s; pct=20; fo=1:d; \
+                                     rua=mailto:dmarc@example.com, mailto:dmarc@example.net").unwrap();
+    assert_eq!(DmarcPolicy::Reject, record.policy);
+    assert_eq!(Some(DmarcPolicy::Quarantine), record.subdomain_policy);
+    assert_eq!(DmarcAlignment::Strict, record.dkim_alignment);
+    assert_eq!(DmarcAlignment::Relaxed, record.spf_alignment);
+    assert_eq!(20, record.percent);
+    assert_eq!(vec!["mailto:dmarc@example.com".to_string(), "mailto:dmarc@example.net".to_string()],
+               record.aggregate_report_uris);
+    assert_eq!("1:d", record.failure_options);
+    assert_eq!(86400, record.report_interval);
+    assert_eq!("0", DmarcRecord::parse("v=DMARC1; p=none").unwrap().failure_options);
+
+    assert_eq!(DmarcPolicy::None, DmarcRecord::parse("v=DMARC1; rua=mailto:d@example.com").unwrap().policy);
+    assert_eq!(Err(DmarcRecordError::NotDmarc), DmarcRecord::parse("p=reject; v=DMARC1"));
+    assert_eq!(Err(DmarcRecordError::MissingPolicy), DmarcRecord::parse("v=DMARC1; p=bounce"));
+    assert_eq!(Err(DmarcRecordError::BadTag("pct".to_string())), DmarcRecord::parse("v=DMARC1; p=none; pct=150"));
+}
+
+#[test]
+fn test_from_domain() {
+    let from = |values: &[&str]| {
+        let headers: Vec<(String, String, Vec<u8>)> = values.iter()
+            .map(|v| ("From".to_string(), v.to_string(), vec![])).collect();
+        from_domain(&headers)
+    };
+
+    assert_eq!(Ok("football.example.com".to_string()), from(&[" Joe SixPack <joe@Football.Example.com>"]));
+    assert_eq!(Ok("example.com".to_string()), from(&["joe@example.com (Joe <joe@example.net>)"]));
+    assert_eq!(Ok("example.com".to_string()), from(&["\"Smith, Joe\" <joe@example.com>, sue@example.com"]));
+    assert_eq!(Ok("example.com".to_string()), from(&["Friends: joe@example.com, <sue@example.com>;"]));
+    assert_eq!(Err(DmarcReason::MultipleFrom), from(&["joe@example.com, sue@example.net"]));
+    assert_eq!(Err(DmarcReason::MultipleFrom), from(&["joe@example.com", "joe@example.com"]));
+    assert_eq!(Err(DmarcReason::MissingFrom), from(&[]));
+    assert_eq!(Err(DmarcReason::BadFromAddress("Joe".to_string())), from(&["Joe"]));
+}
+
+#[test]
+fn test_evaluate_dmarc() {
+    let mut zone = ZoneFile::new();
+    zone.add_txt("_dmarc.example.com", "v=DMARC1; p=reject; sp=quarantine; pct=50");
+    zone.add_txt("_dmarc.example.net", "v=DMARC1; p=quarantine; adkim=s");
+    let evaluator = DmarcEvaluator::new(&zone);
+
+    // relaxed alignment accepts a signature from the parent domain
+    let result = evaluator.evaluate("mail.example.com", &[test_dkim_result("example.com", None)], None, 0);
+    assert_eq!(DmarcStatus::Pass, result.status);
+    assert_eq!(Some("example.com".to_string()), result.dkim_domain);
+    assert_eq!(Some("example.com".to_string()), result.policy_domain);
+    assert_eq!(DmarcPolicy::None, result.disposition);
+
+    // the subdomain policy applies to subdomains without their own record
+    let result = evaluator.evaluate("mail.example.com", &[test_dkim_result("example.com", Some(DkimReason::SignatureMismatch))], None, 0);
+    assert_eq!(DmarcStatus::Fail, result.status);
+    assert_eq!(Some(DmarcReason::NotAligned), result.reason);
+    assert_eq!(DmarcPolicy::Quarantine, result.disposition);
+
+    // messages outside the pct= sample get a weaker disposition
+    let result = evaluator.evaluate("example.com", &[], None, 10);
+    assert_eq!(DmarcPolicy::Reject, result.disposition);
+    let result = evaluator.evaluate("example.com", &[], None, 60);
+    assert_eq!(Some(DmarcPolicy::Reject), result.policy);
+    assert_eq!(DmarcPolicy::Quarantine, result.disposition);
+
+    // SPF can pass on its own
+    let result = evaluator.evaluate("example.com", &[], Some("bounces.example.com"), 0);
+    assert_eq!(DmarcStatus::Pass, result.status);
+    assert!(result.spf_aligned);
+
+    // strict alignment needs an exact match
+    let result = evaluator.evaluate("example.net", &[test_dkim_result("mail.example.net", None)], None, 0);
+    assert_eq!(DmarcStatus::Fail, result.status);
+    let result = evaluator.evaluate("example.net", &[test_dkim_result("Example.NET", None)], None, 0);
+    assert_eq!(DmarcStatus::Pass, result.status);
+
+    let result = evaluator.evaluate("example.org", &[], None, 0);
+    assert_eq!(DmarcStatus::None, result.status);
+    assert_eq!(Some(DmarcReason::NoPolicy), result.reason);
+}
@@ -0,0 +1,453 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::mem;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use clock::{Clock, SYSTEM_CLOCK};
+use dkim::DkimResult;
+use spf::{SpfResult, SpfIdentity};
+use super::{DmarcResult, DmarcStatus, DmarcPolicy, DmarcAlignment, DmarcRecord};
+#[cfg(test)]
+use clock::FixedClock;
+
+// The policy a domain published, as it's given in an aggregate report
+#[derive(Debug, PartialEq, Clone)]
+pub struct PublishedPolicy {
+    pub domain: String,
+    pub dkim_alignment: DmarcAlignment,
+    pub spf_alignment: DmarcAlignment,
+    pub policy: DmarcPolicy,
+    pub subdomain_policy: DmarcPolicy,
+    pub percent: u32,
+    pub failure_options: String
+}
+
+impl PublishedPolicy {
+    pub fn new(domain: &str, record: &DmarcRecord) -> PublishedPolicy {
+        PublishedPolicy {
+            domain: domain.to_string(),
+            dkim_alignment: record.dkim_alignment,
+            spf_alignment: record.spf_alignment,
+            policy: record.policy,
+            subdomain_policy: record.subdomain_policy.unwrap_or(record.policy),
+            percent: record.percent,
+            failure_options: record.failure_options.clone()
+        }
+    }
+}
+
+// A DKIM signature on the reported messages, whether or not it's aligned
+#[derive(Debug, PartialEq, Clone)]
+pub struct DkimAuthResult {
+    pub domain: String,
+    pub selector: Option<String>,
+    pub result: String
+}
+
+// An SPF check of the reported messages, whether or not it's aligned
+#[derive(Debug, PartialEq, Clone)]
+pub struct SpfAuthResult {
+    pub domain: String,
+    // "mfrom" or "helo"
+    pub scope: Option<String>,
+    pub result: String
+}
+
+// A row of an aggregate report: the messages from one source IP with the same
+// header-from domain and the same outcome
+#[derive(Debug, PartialEq, Clone)]
+pub struct ReportRecord {
+    pub source_ip: IpAddr,
+    pub count: u32,
+    pub disposition: DmarcPolicy,
+    // whether a passing DKIM signature or SPF check was aligned
+    pub dkim_aligned: bool,
+    pub spf_aligned: bool,
+    // why the disposition is weaker than the policy, like "sampled_out"
+    pub override_reason: Option<String>,
+    pub header_from: String,
+    pub dkim_results: Vec<DkimAuthResult>,
+    pub spf_results: Vec<SpfAuthResult>
+}
+
+impl ReportRecord {
+    fn same_row(&self, other: &ReportRecord) -> bool {
+        ReportRecord { count: other.count, ..self.clone() } == *other
+    }
+}
+
+// An aggregate feedback report for one policy domain (RFC 7489 appendix C)
+#[derive(Debug, PartialEq, Clone)]
+pub struct AggregateReport {
+    pub org_name: String,
+    pub email: String,
+    pub report_id: String,
+    // the reporting period, in seconds since the Unix epoch
+    pub begin: u64,
+    pub end: u64,
+    pub policy: PublishedPolicy,
+    pub records: Vec<ReportRecord>
+}
+
+impl AggregateReport {
+    // receiver!policy-domain!begin!end.xml (RFC 7489 section 7.2.1.1), where
+    // the receiver is the domain of the report's email address
+    pub fn filename(&self) -> String {
+        let receiver = match self.email.rfind('@') {
+            Some(at) => &self.email[at + 1..],
+            None => &self.org_name[..]
+        };
+        format!("{}!{}!{}!{}.xml", receiver, self.policy.domain, self.begin, self.end)
+    }
+
+    pub fn to_xml(&self) -> String {
+        let mut xml = XmlWriter { xml: String::new(), depth: 0 };
+        xml.xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.open("feedback");
+        xml.element("version", "1.0");
+
+        xml.open("report_metadata");
+        xml.element("org_name", &self.org_name);
+        xml.element("email", &self.email);
+        xml.element("report_id", &self.report_id);
+        xml.open("date_range");
+        xml.element("begin", &self.begin.to_string());
+        xml.element("end", &self.end.to_string());
+        xml.close("date_range");
+        xml.close("report_metadata");
+
+        xml.open("policy_published");
+        xml.element("domain", &self.policy.domain);
+        xml.element("adkim", self.policy.dkim_alignment.tag());
+        xml.element("aspf", self.policy.spf_alignment.tag());
+        xml.element("p", self.policy.policy.name());
+        xml.element("sp", self.policy.subdomain_policy.name());
+        xml.element("pct", &self.policy.percent.to_string());
+        xml.element("fo", &self.policy.failure_options);
+        xml.close("policy_published");
+
+        let pass_or_fail = |pass: bool| if pass { "pass" } else { "fail" };
+        for record in self.records.iter() {
+            xml.open("record");
+            xml.open("row");
+            xml.element("source_ip", &format!("{}", record.source_ip));
+            xml.element("count", &record.count.to_string());
+            xml.open("policy_evaluated");
+            xml.element("disposition", record.disposition.name());
+            xml.element("dkim", pass_or_fail(record.dkim_aligned));
+            xml.element("spf", pass_or_fail(record.spf_aligned));
+            match record.override_reason {
+                Some(ref reason) => {
+                    xml.open("reason");
+                    xml.element("type", reason);
+                    xml.close("reason");
+                }
+                None => ()
+            }
+            xml.close("policy_evaluated");
+            xml.close("row");
+
+            xml.open("identifiers");
+            xml.element("header_from", &record.header_from);
+            xml.close("identifiers");
+
+            xml.open("auth_results");
+            for dkim in record.dkim_results.iter() {
+                xml.open("dkim");
+                xml.element("domain", &dkim.domain);
+                match dkim.selector {
+                    Some(ref selector) => xml.element("selector", selector),
+                    None => ()
+                }
+                xml.element("result", &dkim.result);
+                xml.close("dkim");
+            }
+            // the schema requires an SPF result, so a record without one is
+            // reported as SPF none for the header-from domain
+            let unchecked = [SpfAuthResult {
+                domain: record.header_from.clone(),
+                scope: Some("mfrom".to_string()),
+                result: "none".to_string()
+            }];
+            let spf_results = if record.spf_results.is_empty() { &unchecked[..] } else { &record.spf_results[..] };
+            for spf in spf_results.iter() {
+                xml.open("spf");
+                xml.element("domain", &spf.domain);
+                match spf.scope {
+                    Some(ref scope) => xml.element("scope", scope),
+                    None => ()
+                }
+                xml.element("result", &spf.result);
+                xml.close("spf");
+            }
+            xml.close("auth_results");
+            xml.close("record");
+        }
+
+        xml.close("feedback");
+        xml.xml
+    }
+}
+
+struct XmlWriter {
+    xml: String,
+    depth: usize
+}
+
+impl XmlWriter {
+    fn open(&mut self, name: &str) {
+        self.indent();
+        self.xml.push_str(&format!("<{}>\n", name));
+        self.depth += 1;
+    }
+
+    fn close(&mut self, name: &str) {
+        self.depth -= 1;
+        self.indent();
+        self.xml.push_str(&format!("</{}>\n", name));
+    }
+
+    fn element(&mut self, name: &str, value: &str) {
+        self.indent();
+        self.xml.push_str(&format!("<{}>{}</{}>\n", name, escape(value), name));
+    }
+
+    fn indent(&mut self) {
+        for _ in 0..self.depth {
+            self.xml.push_str("  ");
+        }
+    }
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c)
+        }
+    }
+    escaped
+}
+
+// Somewhere to deliver finished reports.  rua is the list of URIs the policy
+// domain asked for them to be sent to.
+pub trait ReportOutput {
+    fn write_report(&mut self, report: &AggregateReport, rua: &[String]) -> io::Result<()>;
+}
+
+// Writes each report to its own file in a directory
+pub struct ReportDirectory {
+    path: PathBuf
+}
+
+impl ReportDirectory {
+    pub fn new(path: &Path) -> ReportDirectory {
+        ReportDirectory { path: path.to_path_buf() }
+    }
+}
+
+impl ReportOutput for ReportDirectory {
+    fn write_report(&mut self, report: &AggregateReport, _rua: &[String]) -> io::Result<()> {
+        let mut file = try!(File::create(&self.path.join(&report.filename())));
+        file.write_all(report.to_xml().as_bytes())
+    }
+}
+
+struct DomainRecords {
+    policy: PublishedPolicy,
+    rua: Vec<String>,
+    records: HashMap<(IpAddr, String), Vec<ReportRecord>>
+}
+
+// Accumulates the DMARC outcome of each message for the policy domains that
+// asked for aggregate reports (rua=), and reports them per domain at the end
+// of each reporting period
+pub struct DmarcReporter<'a> {
+    org_name: String,
+    email: String,
+    // the start of the current reporting period
+    begin: u64,
+    domains: HashMap<String, DomainRecords>,
+    clock: &'a (Clock + 'a)
+}
+
+impl<'a> DmarcReporter<'a> {
+    pub fn new(org_name: &str, email: &str) -> DmarcReporter<'a> {
+        DmarcReporter::with_clock(org_name, email, &SYSTEM_CLOCK)
+    }
+
+    pub fn with_clock(org_name: &str, email: &str, clock: &'a Clock) -> DmarcReporter<'a> {
+        DmarcReporter {
+            org_name: org_name.to_string(),
+            email: email.to_string(),
+            begin: clock.now(),
+            domains: HashMap::new(),
+            clock: clock
+        }
+    }
+
+    // Records the outcome of one message from source_ip, along with the DKIM
+    // and SPF results it was based on.  Messages with no policy, or whose
+    // policy domain doesn't want aggregate reports, aren't recorded.
+    pub fn add(&mut self, source_ip: IpAddr, result: &DmarcResult,
+               dkim_results: &[DkimResult], spf_results: &[SpfResult]) {
+        let (header_from, policy_domain, record, policy) =
+            match (&result.from_domain, &result.policy_domain, &result.record, result.policy) {
+                (&Some(ref from), &Some(ref domain), &Some(ref record), Some(policy))
+                    if !record.aggregate_report_uris.is_empty() => (from, domain, record, policy),
+                _ => return
+            };
+
+        let override_reason = if result.status == DmarcStatus::Fail && result.disposition != policy {
+            Some("sampled_out".to_string())
+        }
+        else {
+            None
+        };
+        let row = ReportRecord {
+            source_ip: source_ip,
+            count: 1,
+            disposition: result.disposition,
+            dkim_aligned: result.dkim_domain.is_some(),
+            spf_aligned: result.spf_aligned,
+            override_reason: override_reason,
+            header_from: header_from.clone(),
+            dkim_results: dkim_results.iter().filter_map(|r| r.sdid.as_ref().map(|sdid| DkimAuthResult {
+                domain: sdid.clone(),
+                selector: r.selector.clone(),
+                result: r.status.name().to_string()
+            })).collect(),
+            spf_results: spf_results.iter().map(|r| SpfAuthResult {
+                domain: r.domain.clone(),
+                scope: Some(match r.identity {
+                    SpfIdentity::MailFrom => "mfrom",
+                    SpfIdentity::Helo => "helo"
+                }.to_string()),
+                result: r.status.name().to_string()
+            }).collect()
+        };
+
+        let domain = self.domains.entry(policy_domain.clone()).or_insert(DomainRecords {
+            policy: PublishedPolicy::new(policy_domain, record),
+            rua: vec![],
+            records: HashMap::new()
+        });
+        // the latest record is the one that's reported
+        domain.policy = PublishedPolicy::new(policy_domain, record);
+        domain.rua = record.aggregate_report_uris.clone();
+
+        let rows = domain.records.entry((source_ip, header_from.clone())).or_insert(vec![]);
+        match rows.iter_mut().find(|r| r.same_row(&row)) {
+            Some(existing) => {
+                existing.count += 1;
+                return;
+            }
+            None => ()
+        }
+        rows.push(row);
+    }
+
+    // Ends the current reporting period, returning a report for each policy
+    // domain along with its rua= URIs
+    pub fn reports(&mut self) -> Vec<(AggregateReport, Vec<String>)> {
+        let begin = self.begin;
+        let end = self.clock.now();
+        let domains = mem::replace(&mut self.domains, HashMap::new());
+        self.begin = end;
+
+        let mut reports: Vec<(AggregateReport, Vec<String>)> = domains.into_iter().map(|(_, domain)| {
+            let mut records: Vec<ReportRecord> = domain.records.into_iter()
+                .flat_map(|(_, rows)| rows.into_iter())
+                .collect();
+            records.sort_by(|a, b| {
+                (format!("{}", a.source_ip), &a.header_from).cmp(&(format!("{}", b.source_ip), &b.header_from))
+            });
+
+            let report = AggregateReport {
+                org_name: self.org_name.clone(),
+                email: self.email.clone(),
+                report_id: format!("{}.{}", domain.policy.domain, begin),
+                begin: begin,
+                end: end,
+                policy: domain.policy,
+                records: records
+            };
+            (report, domain.rua)
+        }).collect();
+        reports.sort_by(|a, b| a.0.policy.domain.cmp(&b.0.policy.domain));
+        reports
+    }
+
+    // Ends the current reporting period and writes its reports to output,
+    // returning how many there were
+    pub fn write_reports(&mut self, output: &mut ReportOutput) -> io::Result<usize> {
+        let reports = self.reports();
+        for &(ref report, ref rua) in reports.iter() {
+            try!(output.write_report(report, rua));
+        }
+        Ok(reports.len())
+    }
+}
+
+#[test]
+fn test_aggregate_report() {
+    use std::net::Ipv4Addr;
+    use dns::ZoneFile;
+    use dkim::{DkimReason, test_dkim_result};
+    use super::DmarcEvaluator;
+
+    let mut zone = ZoneFile::new();
+    zone.add_txt("_dmarc.example.com", "v=DMARC1; p=reject; sp=quarantine; rua=mailto:dmarc@example.com");
+    zone.add_txt("_dmarc.example.net", "v=DMARC1; p=none");
+    let evaluator = DmarcEvaluator::new(&zone);
+
+    let pass = [test_dkim_result("example.com", None)];
+    let fail = [test_dkim_result("example.com", Some(DkimReason::SignatureMismatch))];
+    let ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+    let clock = FixedClock::new(1117574938);
+    let mut reporter = DmarcReporter::with_clock("Receiver", "dmarc-reports@receiver.example", &clock);
+    reporter.add(ip, &evaluator.evaluate("example.com", &pass, None, 0), &pass, &[]);
+    reporter.add(ip, &evaluator.evaluate("example.com", &pass, None, 0), &pass, &[]);
+    reporter.add(ip, &evaluator.evaluate("example.com", &fail, None, 0), &fail, &[]);
+    reporter.add(ip, &evaluator.evaluate("mail.example.com", &pass, None, 0), &pass, &[]);
+    // example.net doesn't ask for reports
+    reporter.add(ip, &evaluator.evaluate("example.net", &pass, None, 0), &pass, &[]);
+
+    let reports = reporter.reports();
+    assert_eq!(1, reports.len());
+    let (ref report, ref rua) = reports[0];
+    assert_eq!(vec!["mailto:dmarc@example.com".to_string()], *rua);
+    assert_eq!("receiver.example!example.com!1117574938!1117574938.xml", report.filename());
+    assert_eq!(DmarcPolicy::Quarantine, report.policy.subdomain_policy);
+
+    let counts: Vec<(&str, u32, DmarcPolicy)> = report.records.iter()
+        .map(|r| (&r.header_from[..], r.count, r.disposition)).collect();
+    assert_eq!(3, counts.len());
+    assert!(counts.contains(&("example.com", 2, DmarcPolicy::None)));
+    assert!(counts.contains(&("example.com", 1, DmarcPolicy::Reject)));
+    assert!(counts.contains(&("mail.example.com", 1, DmarcPolicy::None)));
+
+    let xml = report.to_xml();
+    assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<feedback>\n  <version>1.0</version>\n"));
+    assert!(xml.contains("    <date_range>\n      <begin>1117574938</begin>\n"));
+    assert!(xml.contains("    <domain>example.com</domain>\n    <adkim>r</adkim>\n"));
+    assert!(xml.contains("    <pct>100</pct>\n    <fo>0</fo>\n  </policy_published>\n"));
+    assert!(xml.contains("      <source_ip>192.0.2.1</source_ip>\n      <count>2</count>\n"));
+    assert!(xml.contains("<dkim>\n        <domain>example.com</domain>\n        \
+                          <selector>brisbane</selector>\n        <result>fail</result>\n"));
+    // SPF wasn't checked, but the schema needs an SPF result
+    assert!(xml.contains("<spf>\n        <domain>example.com</domain>\n        \
+                          <scope>mfrom</scope>\n        <result>none</result>\n"));
+
+    // the next period starts empty
+    assert!(reporter.reports().is_empty());
+}
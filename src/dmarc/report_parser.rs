@@ -0,0 +1,615 @@
+extern crate flate2;
+
+use std::io::Read;
+use std::net::IpAddr;
+
+use self::flate2::Crc;
+use self::flate2::read::{GzDecoder, DeflateDecoder};
+
+use super::{DmarcPolicy, DmarcAlignment};
+use super::report::{AggregateReport, PublishedPolicy, ReportRecord, DkimAuthResult, SpfAuthResult};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ReportParseError {
+    // a gzip or zip attachment couldn't be unpacked
+    BadArchive(String),
+    BadXml(String),
+    MissingElement(String),
+    // an element whose content isn't valid for it
+    BadValue(String)
+}
+
+type ParseResult<T> = Result<T, ReportParseError>;
+
+// The most XML a report attachment may unpack to.  Even the largest
+// receivers' daily reports are a small fraction of this, and it keeps a
+// small, highly compressed attachment from exhausting memory.
+pub const MAX_REPORT_SIZE: usize = 64 * 1024 * 1024;
+
+impl AggregateReport {
+    // Parses an aggregate report as an attachment carries it: gzipped, in a
+    // zip file, or as plain XML
+    pub fn from_attachment(data: &[u8]) -> ParseResult<AggregateReport> {
+        AggregateReport::from_attachment_with_limit(data, MAX_REPORT_SIZE)
+    }
+
+    // As from_attachment, but an archive that unpacks to more than limit
+    // bytes is rejected
+    pub fn from_attachment_with_limit(data: &[u8], limit: usize) -> ParseResult<AggregateReport> {
+        let xml = if data.starts_with(&[0x1f, 0x8b]) {
+            try!(gunzip(data, limit))
+        }
+        else if data.starts_with(b"PK\x03\x04") {
+            try!(unzip(data, limit))
+        }
+        else {
+            data.to_vec()
+        };
+        match String::from_utf8(xml) {
+            Ok(xml) => AggregateReport::parse(&xml),
+            Err(_) => Err(ReportParseError::BadXml("not UTF-8".to_string()))
+        }
+    }
+
+    // Parses the XML of an aggregate report (RFC 7489 appendix C).  Elements
+    // that aren't used here, like those of later schema versions, are ignored.
+    pub fn parse(xml: &str) -> ParseResult<AggregateReport> {
+        let feedback = try!(XmlParser { xml: xml, pos: 0 }.document());
+        if feedback.name != "feedback" {
+            return Err(ReportParseError::MissingElement("feedback".to_string()));
+        }
+
+        let metadata = try!(feedback.required_child("report_metadata"));
+        let date_range = try!(metadata.required_child("date_range"));
+        let published = try!(feedback.required_child("policy_published"));
+
+        let policy = try!(parse_value(published, "p", DmarcPolicy::from_name));
+        let subdomain_policy = match published.text("sp") {
+            Some(_) => try!(parse_value(published, "sp", DmarcPolicy::from_name)),
+            None => policy
+        };
+        let percent = match published.text("pct") {
+            Some(_) => try!(parse_value(published, "pct", |pct| pct.parse().ok())),
+            None => 100
+        };
+
+        let mut records = vec![];
+        for record in feedback.children("record") {
+            records.push(try!(parse_record(record)));
+        }
+
+        Ok(AggregateReport {
+            org_name: try!(metadata.required_text("org_name")),
+            email: metadata.text("email").unwrap_or(String::new()),
+            report_id: try!(metadata.required_text("report_id")),
+            begin: try!(parse_value(date_range, "begin", |t| t.parse().ok())),
+            end: try!(parse_value(date_range, "end", |t| t.parse().ok())),
+            policy: PublishedPolicy {
+                domain: try!(published.required_text("domain")),
+                dkim_alignment: try!(parse_alignment(published, "adkim")),
+                spf_alignment: try!(parse_alignment(published, "aspf")),
+                policy: policy,
+                subdomain_policy: subdomain_policy,
+                percent: percent,
+                failure_options: published.text("fo").unwrap_or("0".to_string())
+            },
+            records: records
+        })
+    }
+}
+
+fn parse_record(record: &XmlElement) -> ParseResult<ReportRecord> {
+    let row = try!(record.required_child("row"));
+    let evaluated = try!(row.required_child("policy_evaluated"));
+    let identifiers = try!(record.required_child("identifiers"));
+    let pass = |name: &str| evaluated.text(name).map_or(false, |r| r == "pass");
+
+    let mut dkim_results = vec![];
+    let mut spf_results = vec![];
+    match record.child("auth_results") {
+        Some(auth_results) => {
+            for dkim in auth_results.children("dkim") {
+                dkim_results.push(DkimAuthResult {
+                    domain: try!(dkim.required_text("domain")),
+                    selector: dkim.text("selector"),
+                    result: try!(dkim.required_text("result"))
+                });
+            }
+            for spf in auth_results.children("spf") {
+                spf_results.push(SpfAuthResult {
+                    domain: try!(spf.required_text("domain")),
+                    scope: spf.text("scope"),
+                    result: try!(spf.required_text("result"))
+                });
+            }
+        }
+        None => ()
+    }
+
+    Ok(ReportRecord {
+        source_ip: try!(parse_value(row, "source_ip", |ip| ip.parse::<IpAddr>().ok())),
+        count: try!(parse_value(row, "count", |count| count.parse().ok())),
+        disposition: try!(parse_value(evaluated, "disposition", DmarcPolicy::from_name)),
+        dkim_aligned: pass("dkim"),
+        spf_aligned: pass("spf"),
+        override_reason: evaluated.child("reason").and_then(|reason| reason.text("type")),
+        header_from: try!(identifiers.required_text("header_from")),
+        dkim_results: dkim_results,
+        spf_results: spf_results
+    })
+}
+
+fn parse_value<T, F>(element: &XmlElement, name: &str, parse: F) -> ParseResult<T>
+    where F: Fn(&str) -> Option<T> {
+    let text = try!(element.required_text(name));
+    parse(&text).ok_or(ReportParseError::BadValue(name.to_string()))
+}
+
+// adkim and aspf default to relaxed
+fn parse_alignment(element: &XmlElement, name: &str) -> ParseResult<DmarcAlignment> {
+    match element.text(name) {
+        None => Ok(DmarcAlignment::Relaxed),
+        Some(ref a) if *a == "r" => Ok(DmarcAlignment::Relaxed),
+        Some(ref a) if *a == "s" => Ok(DmarcAlignment::Strict),
+        Some(_) => Err(ReportParseError::BadValue(name.to_string()))
+    }
+}
+
+// Reads everything from an unpacking reader, up to limit bytes
+fn read_limited<R: Read>(reader: R, limit: usize) -> ParseResult<Vec<u8>> {
+    let mut xml = vec![];
+    match reader.take(limit as u64 + 1).read_to_end(&mut xml) {
+        Ok(_) if xml.len() > limit =>
+            Err(ReportParseError::BadArchive(format!("unpacks to more than {} bytes", limit))),
+        Ok(_) => Ok(xml),
+        Err(e) => Err(ReportParseError::BadArchive(format!("{}", e)))
+    }
+}
+
+// GzDecoder checks the CRC in the gzip trailer itself
+fn gunzip(data: &[u8], limit: usize) -> ParseResult<Vec<u8>> {
+    match GzDecoder::new(data) {
+        Ok(decoder) => read_limited(decoder, limit),
+        Err(e) => Err(ReportParseError::BadArchive(format!("{}", e)))
+    }
+}
+
+// Extracts the first file of a zip archive, going by its central directory
+// since the sizes in a local header can be left out
+fn unzip(data: &[u8], limit: usize) -> ParseResult<Vec<u8>> {
+    let bad_archive = |reason: &str| ReportParseError::BadArchive(reason.to_string());
+    let u16_at = |pos: usize| data[pos] as usize | (data[pos + 1] as usize) << 8;
+    let u32_at = |pos: usize| u16_at(pos) | u16_at(pos + 2) << 16;
+
+    // the end of central directory record is at least 22 bytes from the end
+    if data.len() < 22 {
+        return Err(bad_archive("truncated"));
+    }
+    let eocd = match (0..data.len() - 21).rev().find(|&pos| data[pos..].starts_with(b"PK\x05\x06")) {
+        Some(eocd) => eocd,
+        None => return Err(bad_archive("no central directory"))
+    };
+    let central = u32_at(eocd + 16);
+    if central + 46 > data.len() || !data[central..].starts_with(b"PK\x01\x02") {
+        return Err(bad_archive("bad central directory"));
+    }
+    let method = u16_at(central + 10);
+    let crc32 = u32_at(central + 16);
+    let compressed_size = u32_at(central + 20);
+    let local = u32_at(central + 42);
+    if local + 30 > data.len() || !data[local..].starts_with(b"PK\x03\x04") {
+        return Err(bad_archive("bad local header"));
+    }
+    let start = local + 30 + u16_at(local + 26) + u16_at(local + 28);
+    if start + compressed_size > data.len() {
+        return Err(bad_archive("truncated"));
+    }
+    let compressed = &data[start..start + compressed_size];
+
+    let xml = match method {
+        0 => try!(read_limited(compressed, limit)),
+        8 => try!(read_limited(DeflateDecoder::new(compressed), limit)),
+        _ => return Err(bad_archive("unsupported compression method"))
+    };
+
+    let mut crc = Crc::new();
+    crc.update(&xml);
+    if crc.sum() as usize != crc32 {
+        return Err(bad_archive("CRC mismatch"));
+    }
+    Ok(xml)
+}
+
+// Just enough XML for aggregate reports: elements and their text, without
+// attributes or namespaces
+struct XmlElement {
+    name: String,
+    text: String,
+    children: Vec<XmlElement>
+}
+
+impl XmlElement {
+    fn child(&self, name: &str) -> Option<&XmlElement> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    fn children(&self, name: &str) -> Vec<&XmlElement> {
+        self.children.iter().filter(|c| c.name == name).collect()
+    }
+
+    fn required_child(&self, name: &str) -> ParseResult<&XmlElement> {
+        self.child(name).ok_or(ReportParseError::MissingElement(name.to_string()))
+    }
+
+    // the trimmed text of a child element, if it has any
+    fn text(&self, name: &str) -> Option<String> {
+        self.child(name).map(|c| c.text.trim().to_string()).and_then(|t| {
+            if t.is_empty() { None } else { Some(t) }
+        })
+    }
+
+    fn required_text(&self, name: &str) -> ParseResult<String> {
+        self.text(name).ok_or(ReportParseError::MissingElement(name.to_string()))
+    }
+}
+
+// Aggregate reports nest elements five deep.  Elements are parsed
+// recursively, so a limit keeps a hostile report from overflowing the stack.
+const MAX_ELEMENT_DEPTH: usize = 16;
+
+struct XmlParser<'a> {
+    xml: &'a str,
+    pos: usize
+}
+
+impl<'a> XmlParser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.xml[self.pos..]
+    }
+
+    fn error(&self, reason: &str) -> ReportParseError {
+        ReportParseError::BadXml(format!("{} at offset {}", reason, self.pos))
+    }
+
+    // Moves past the next occurrence of end
+    fn skip_past(&mut self, end: &str) -> ParseResult<()> {
+        match self.rest().find(end) {
+            Some(i) => {
+                self.pos += i + end.len();
+                Ok(())
+            }
+            None => Err(self.error("unterminated markup"))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_left().len();
+    }
+
+    // Skips a comment, processing instruction or declaration
+    fn skip_misc(&mut self) -> ParseResult<bool> {
+        if self.rest().starts_with("<!--") {
+            try!(self.skip_past("-->"));
+        }
+        else if self.rest().starts_with("<?") {
+            try!(self.skip_past("?>"));
+        }
+        else if self.rest().starts_with("<!") && !self.rest().starts_with("<![CDATA[") {
+            try!(self.skip_past(">"));
+        }
+        else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn document(&mut self) -> ParseResult<XmlElement> {
+        loop {
+            self.skip_whitespace();
+            if !try!(self.skip_misc()) {
+                break;
+            }
+        }
+        let element = try!(self.element(1));
+        loop {
+            self.skip_whitespace();
+            if !try!(self.skip_misc()) {
+                break;
+            }
+        }
+        if !self.rest().is_empty() {
+            return Err(self.error("content after the document element"));
+        }
+        Ok(element)
+    }
+
+    fn name(&mut self) -> String {
+        let rest = self.rest();
+        let end = rest.find(|c: char| c.is_whitespace() || c == '/' || c == '>').unwrap_or(rest.len());
+        self.pos += end;
+        // namespace prefixes are dropped
+        let name = &rest[..end];
+        match name.rfind(':') {
+            Some(colon) => name[colon + 1..].to_string(),
+            None => name.to_string()
+        }
+    }
+
+    // depth counts this element and its ancestors
+    fn element(&mut self, depth: usize) -> ParseResult<XmlElement> {
+        if !self.rest().starts_with("<") {
+            return Err(self.error("expected an element"));
+        }
+        if depth > MAX_ELEMENT_DEPTH {
+            return Err(self.error("elements nested too deeply"));
+        }
+        self.pos += 1;
+        let name = self.name();
+        if name.is_empty() {
+            return Err(self.error("missing element name"));
+        }
+
+        // attributes are skipped, minding any '>' in their quoted values
+        let mut quote = None;
+        let mut empty = false;
+        loop {
+            let c = match self.rest().chars().next() {
+                Some(c) => c,
+                None => return Err(self.error("unterminated tag"))
+            };
+            self.pos += c.len_utf8();
+            match (quote, c) {
+                (Some(q), c) if c == q => quote = None,
+                (Some(_), _) => (),
+                (None, '"') | (None, '\'') => quote = Some(c),
+                (None, '/') if self.rest().starts_with(">") => {
+                    self.pos += 1;
+                    empty = true;
+                    break;
+                }
+                (None, '>') => break,
+                _ => ()
+            }
+        }
+
+        let mut element = XmlElement { name: name, text: String::new(), children: vec![] };
+        if empty {
+            return Ok(element);
+        }
+
+        loop {
+            if self.rest().starts_with("</") {
+                self.pos += 2;
+                if self.name() != element.name {
+                    return Err(self.error("mismatched end tag"));
+                }
+                self.skip_whitespace();
+                if !self.rest().starts_with(">") {
+                    return Err(self.error("bad end tag"));
+                }
+                self.pos += 1;
+                return Ok(element);
+            }
+            else if self.rest().starts_with("<![CDATA[") {
+                self.pos += 9;
+                let start = self.pos;
+                try!(self.skip_past("]]>"));
+                element.text.push_str(&self.xml[start..self.pos - 3]);
+            }
+            else if try!(self.skip_misc()) {
+                continue;
+            }
+            else if self.rest().starts_with("<") {
+                element.children.push(try!(self.element(depth + 1)));
+            }
+            else if self.rest().is_empty() {
+                return Err(self.error("unterminated element"));
+            }
+            else {
+                let rest = self.rest();
+                let end = rest.find('<').unwrap_or(rest.len());
+                self.pos += end;
+                element.text.push_str(&try!(self.unescape(&rest[..end])));
+            }
+        }
+    }
+
+    fn unescape(&self, text: &str) -> ParseResult<String> {
+        let mut unescaped = String::new();
+        let mut rest = text;
+        while let Some(amp) = rest.find('&') {
+            unescaped.push_str(&rest[..amp]);
+            let semicolon = match rest[amp..].find(';') {
+                Some(semicolon) => amp + semicolon,
+                None => return Err(self.error("unterminated entity"))
+            };
+            let entity = &rest[amp + 1..semicolon];
+            let c = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16).ok().and_then(::std::char::from_u32),
+                _ if entity.starts_with("#") => entity[1..].parse().ok().and_then(::std::char::from_u32),
+                _ => None
+            };
+            match c {
+                Some(c) => unescaped.push(c),
+                None => return Err(self.error("unknown entity"))
+            }
+            rest = &rest[semicolon + 1..];
+        }
+        unescaped.push_str(rest);
+        Ok(unescaped)
+    }
+}
+
+#[cfg(test)]
+fn test_report() -> AggregateReport {
+    AggregateReport {
+        org_name: "Receiver & Co".to_string(),
+        email: "dmarc-reports@receiver.example".to_string(),
+        report_id: "example.com.1117574938".to_string(),
+        begin: 1117574938,
+        end: 1117661338,
+        policy: PublishedPolicy {
+            domain: "example.com".to_string(),
+            dkim_alignment: DmarcAlignment::Relaxed,
+            spf_alignment: DmarcAlignment::Strict,
+            policy: DmarcPolicy::Reject,
+            subdomain_policy: DmarcPolicy::Quarantine,
+            percent: 50,
+            failure_options: "1".to_string()
+        },
+        records: vec![ReportRecord {
+            source_ip: "2001:db8::cb01".parse().unwrap(),
+            count: 3,
+            disposition: DmarcPolicy::Quarantine,
+            dkim_aligned: false,
+            spf_aligned: false,
+            override_reason: Some("sampled_out".to_string()),
+            header_from: "example.com".to_string(),
+            dkim_results: vec![DkimAuthResult {
+                domain: "example.net".to_string(),
+                selector: Some("brisbane".to_string()),
+                result: "pass".to_string()
+            }],
+            spf_results: vec![SpfAuthResult {
+                domain: "example.com".to_string(),
+                scope: Some("mfrom".to_string()),
+                result: "fail".to_string()
+            }]
+        }]
+    }
+}
+
+#[test]
+fn test_parse_aggregate_report() {
+    // what we write, we can read back
+    let report = test_report();
+    assert_eq!(Ok(report.clone()), AggregateReport::parse(&report.to_xml()));
+
+    // reports from elsewhere have namespaces, comments, later schema elements
+    // and optional elements left out
+    let xml = "<?xml version=\"1.0\"?>\r\n\
+               <!-- a report -->\r\n\
+               <feedback xmlns=\"urn:ietf:params:xml:ns:dmarc-2.0\">\r\n\
+                 <version>1.0</version>\r\n\
+                 <report_metadata><org_name><![CDATA[Receiver <Mail>]]></org_name>\
+                   <report_id>42</report_id>\
+                   <date_range><begin>1117574938</begin><end>1117661338</end></date_range>\
+                 </report_metadata>\r\n\
+                 <policy_published><domain>example.com</domain><p>none</p><np>reject</np></policy_published>\r\n\
+                 <record>\
+                   <row><source_ip>192.0.2.1</source_ip><count>1</count>\
+                     <policy_evaluated><disposition>none</disposition><dkim>pass</dkim><spf>fail</spf>\
+                     </policy_evaluated></row>\
+                   <identifiers><envelope_from>example.com</envelope_from>\
+                     <header_from>example.com</header_from></identifiers>\
+                   <auth_results><dkim><domain>example.com</domain><result>pass</result></dkim></auth_results>\
+                 </record>\r\n\
+               </feedback>\r\n";
+    let report = AggregateReport::parse(xml).unwrap();
+    assert_eq!("Receiver <Mail>", report.org_name);
+    assert_eq!("", report.email);
+    assert_eq!(DmarcPolicy::None, report.policy.subdomain_policy);
+    assert_eq!(100, report.policy.percent);
+    assert_eq!(1, report.records.len());
+    assert!(report.records[0].dkim_aligned);
+    assert_eq!(None, report.records[0].dkim_results[0].selector);
+    assert!(report.records[0].spf_results.is_empty());
+
+    let missing_row = xml.replace("<row>", "<rows>").replace("</row>", "</rows>");
+    assert_eq!(Err(ReportParseError::MissingElement("row".to_string())), AggregateReport::parse(&missing_row));
+    assert_eq!(Err(ReportParseError::BadValue("source_ip".to_string())),
+               AggregateReport::parse(&xml.replace("192.0.2.1", "192.0.2")));
+    match AggregateReport::parse(&xml.replace("</count>", "</cuont>")) {
+        Err(ReportParseError::BadXml(_)) => (),
+        r => panic!("expected BadXml, got {:?}", r)
+    }
+}
+
+#[test]
+fn test_parse_deeply_nested_report() {
+    let nested = |depth: usize| {
+        let mut xml = String::new();
+        for _ in 0..depth {
+            xml.push_str("<a>");
+        }
+        for _ in 0..depth {
+            xml.push_str("</a>");
+        }
+        xml
+    };
+
+    // 100000 is deep enough to overflow the stack without the limit
+    for &depth in [MAX_ELEMENT_DEPTH + 1, 100000].iter() {
+        match AggregateReport::parse(&nested(depth)) {
+            Err(ReportParseError::BadXml(ref reason)) if reason.starts_with("elements nested too deeply") => (),
+            r => panic!("expected BadXml, got {:?}", r)
+        }
+    }
+
+    // within the limit, the document is parsed and found not to be a report
+    assert_eq!(Err(ReportParseError::MissingElement("feedback".to_string())),
+               AggregateReport::parse(&nested(MAX_ELEMENT_DEPTH)));
+}
+
+#[test]
+fn test_report_attachments() {
+    use std::io::Write;
+    use self::flate2::Compression;
+    use self::flate2::write::GzEncoder;
+
+    let report = test_report();
+    let xml = report.to_xml();
+
+    let mut encoder = GzEncoder::new(vec![], Compression::Default);
+    encoder.write_all(xml.as_bytes()).unwrap();
+    let gzipped = encoder.finish().unwrap();
+    assert_eq!(Ok(report.clone()), AggregateReport::from_attachment(&gzipped));
+
+    // a zip file with one stored member
+    let name = b"receiver.example!example.com!1117574938!1117661338.xml";
+    let le16 = |n: usize| vec![n as u8, (n >> 8) as u8];
+    let le32 = |n: usize| vec![n as u8, (n >> 8) as u8, (n >> 16) as u8, (n >> 24) as u8];
+    let mut crc = Crc::new();
+    crc.update(xml.as_bytes());
+    let crc32 = crc.sum() as usize;
+    let mut zip = vec![];
+    zip.extend(b"PK\x03\x04\x14\x00\x00\x00\x00\x00\x00\x00\x00\x00".iter().cloned());
+    zip.extend(le32(crc32).into_iter().chain(le32(xml.len()).into_iter()).chain(le32(xml.len()).into_iter()));
+    zip.extend(le16(name.len()).into_iter().chain(le16(0).into_iter()));
+    zip.extend(name.iter().cloned());
+    let data_start = zip.len();
+    zip.extend(xml.bytes());
+    let central = zip.len();
+    zip.extend(b"PK\x01\x02\x14\x00\x14\x00\x00\x00\x00\x00\x00\x00\x00\x00".iter().cloned());
+    zip.extend(le32(crc32).into_iter().chain(le32(xml.len()).into_iter()).chain(le32(xml.len()).into_iter()));
+    zip.extend(le16(name.len()).into_iter().chain(vec![0; 12].into_iter()).chain(le32(0).into_iter()));
+    zip.extend(name.iter().cloned());
+    let central_size = zip.len() - central;
+    zip.extend(b"PK\x05\x06\x00\x00\x00\x00\x01\x00\x01\x00".iter().cloned());
+    zip.extend(le32(central_size).into_iter().chain(le32(central).into_iter()).chain(le16(0).into_iter()));
+    assert_eq!(Ok(report.clone()), AggregateReport::from_attachment(&zip));
+
+    assert_eq!(Ok(report.clone()), AggregateReport::from_attachment(xml.as_bytes()));
+    match AggregateReport::from_attachment(&gzipped[..20]) {
+        Err(ReportParseError::BadArchive(_)) => (),
+        r => panic!("expected BadArchive, got {:?}", r)
+    }
+
+    // a zip member must match its CRC
+    let mut corrupted = zip.clone();
+    corrupted[data_start + 10] ^= 1;
+    assert_eq!(Err(ReportParseError::BadArchive("CRC mismatch".to_string())),
+               AggregateReport::from_attachment(&corrupted));
+
+    // archives may not unpack to more than the limit
+    let too_large = Err(ReportParseError::BadArchive(format!("unpacks to more than {} bytes", xml.len() - 1)));
+    assert_eq!(too_large, AggregateReport::from_attachment_with_limit(&gzipped, xml.len() - 1));
+    assert_eq!(too_large, AggregateReport::from_attachment_with_limit(&zip, xml.len() - 1));
+    assert_eq!(Ok(report), AggregateReport::from_attachment_with_limit(&gzipped, xml.len()));
+}
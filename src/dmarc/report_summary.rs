@@ -0,0 +1,167 @@
+use std::ascii::AsciiExt;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use super::report::{AggregateReport, ReportRecord};
+
+// How many of a set of messages passed
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct PassRate {
+    pub messages: u64,
+    pub passed: u64
+}
+
+impl PassRate {
+    pub fn rate(&self) -> f64 {
+        if self.messages == 0 {
+            0.0
+        }
+        else {
+            self.passed as f64 / self.messages as f64
+        }
+    }
+
+    // Totals can come from any number of reports, so they stop at the
+    // largest u64 rather than overflow
+    fn add(&mut self, count: u32, passed: bool) {
+        self.messages = self.messages.saturating_add(count as u64);
+        if passed {
+            self.passed = self.passed.saturating_add(count as u64);
+        }
+    }
+}
+
+// Totals over a set of aggregate reports received from other domains
+pub struct ReportSummary {
+    // DMARC passes, by the IP that sent the messages
+    pub by_source_ip: HashMap<IpAddr, PassRate>,
+    // DKIM passes by signing domain (d=), whether or not they were aligned
+    pub by_dkim_domain: HashMap<String, PassRate>,
+    // records where DKIM or SPF passed, but only for a domain not aligned with
+    // the header-from domain, so DMARC failed anyway
+    pub alignment_failures: Vec<ReportRecord>
+}
+
+impl ReportSummary {
+    pub fn new() -> ReportSummary {
+        ReportSummary {
+            by_source_ip: HashMap::new(),
+            by_dkim_domain: HashMap::new(),
+            alignment_failures: vec![]
+        }
+    }
+
+    pub fn summarize(reports: &[AggregateReport]) -> ReportSummary {
+        let mut summary = ReportSummary::new();
+        for report in reports.iter() {
+            summary.add(report);
+        }
+        summary
+    }
+
+    pub fn add(&mut self, report: &AggregateReport) {
+        for record in report.records.iter() {
+            let dmarc_pass = record.dkim_aligned || record.spf_aligned;
+            self.by_source_ip.entry(record.source_ip).or_insert(PassRate { messages: 0, passed: 0 })
+                .add(record.count, dmarc_pass);
+
+            // a domain with several signatures on the same messages counts once
+            let mut dkim_domains: Vec<(String, bool)> = vec![];
+            for dkim in record.dkim_results.iter() {
+                let domain = dkim.domain.to_ascii_lowercase();
+                let pass = dkim.result == "pass";
+                let existing = dkim_domains.iter().position(|&(ref d, _)| *d == domain);
+                match existing {
+                    Some(i) => dkim_domains[i].1 = dkim_domains[i].1 || pass,
+                    None => dkim_domains.push((domain, pass))
+                }
+            }
+            for (domain, pass) in dkim_domains.into_iter() {
+                self.by_dkim_domain.entry(domain).or_insert(PassRate { messages: 0, passed: 0 })
+                    .add(record.count, pass);
+            }
+
+            let authenticated = record.dkim_results.iter().any(|r| r.result == "pass") ||
+                record.spf_results.iter().any(|r| r.result == "pass");
+            if !dmarc_pass && authenticated {
+                self.alignment_failures.push(record.clone());
+            }
+        }
+    }
+}
+
+#[test]
+fn test_summarize_reports() {
+    use super::DmarcPolicy;
+    use super::report::{DkimAuthResult, SpfAuthResult, PublishedPolicy};
+    use super::DmarcAlignment;
+
+    let dkim = |domain: &str, result: &str| DkimAuthResult {
+        domain: domain.to_string(),
+        selector: None,
+        result: result.to_string()
+    };
+    let record = |ip: &str, count: u32, dkim_aligned: bool, dkim_results: Vec<DkimAuthResult>| ReportRecord {
+        source_ip: ip.parse().unwrap(),
+        count: count,
+        disposition: DmarcPolicy::None,
+        dkim_aligned: dkim_aligned,
+        spf_aligned: false,
+        override_reason: None,
+        header_from: "example.com".to_string(),
+        dkim_results: dkim_results,
+        spf_results: vec![SpfAuthResult {
+            domain: "example.com".to_string(),
+            scope: Some("mfrom".to_string()),
+            result: "softfail".to_string()
+        }]
+    };
+    let report = |records: Vec<ReportRecord>| AggregateReport {
+        org_name: "Receiver".to_string(),
+        email: "dmarc-reports@receiver.example".to_string(),
+        report_id: "1".to_string(),
+        begin: 1117574938,
+        end: 1117661338,
+        policy: PublishedPolicy {
+            domain: "example.com".to_string(),
+            dkim_alignment: DmarcAlignment::Relaxed,
+            spf_alignment: DmarcAlignment::Relaxed,
+            policy: DmarcPolicy::None,
+            subdomain_policy: DmarcPolicy::None,
+            percent: 100,
+            failure_options: "0".to_string()
+        },
+        records: records
+    };
+
+    let reports = vec![
+        report(vec![record("192.0.2.1", 8, true, vec![dkim("example.com", "pass")]),
+                    record("192.0.2.1", 2, false, vec![dkim("example.com", "fail")])]),
+        report(vec![record("192.0.2.2", 5, false, vec![dkim("Example.COM", "fail"), dkim("mailer.example.net", "pass"),
+                                                       dkim("mailer.example.net", "fail")])])
+    ];
+    let summary = ReportSummary::summarize(&reports);
+
+    let ip = |ip: &str| summary.by_source_ip[&ip.parse::<IpAddr>().unwrap()];
+    assert_eq!(PassRate { messages: 10, passed: 8 }, ip("192.0.2.1"));
+    assert_eq!(0.8, ip("192.0.2.1").rate());
+    assert_eq!(PassRate { messages: 5, passed: 0 }, ip("192.0.2.2"));
+
+    assert_eq!(PassRate { messages: 15, passed: 8 }, summary.by_dkim_domain["example.com"]);
+    assert_eq!(PassRate { messages: 5, passed: 5 }, summary.by_dkim_domain["mailer.example.net"]);
+
+    // the messages from 192.0.2.2 were signed, just not aligned
+    assert_eq!(1, summary.alignment_failures.len());
+    assert_eq!(5, summary.alignment_failures[0].count);
+
+    // totals beyond a u32 don't wrap
+    let large = report(vec![record("192.0.2.3", u32::max_value(), true, vec![]),
+                            record("192.0.2.3", u32::max_value(), true, vec![])]);
+    let summary = ReportSummary::summarize(&[large]);
+    assert_eq!(PassRate { messages: 2 * u32::max_value() as u64, passed: 2 * u32::max_value() as u64 },
+               summary.by_source_ip[&"192.0.2.3".parse::<IpAddr>().unwrap()]);
+
+    let mut rate = PassRate { messages: u64::max_value() - 1, passed: 0 };
+    rate.add(2, true);
+    assert_eq!(PassRate { messages: u64::max_value(), passed: 2 }, rate);
+}
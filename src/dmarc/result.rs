@@ -0,0 +1,102 @@
+use super::DmarcRecord;
+
+// The p= and sp= policies, which are also the dispositions applied to a message
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DmarcPolicy {
+    None,
+    Quarantine,
+    Reject
+}
+
+impl DmarcPolicy {
+    pub fn from_name(name: &str) -> Option<DmarcPolicy> {
+        match name {
+            "none" => Some(DmarcPolicy::None),
+            "quarantine" => Some(DmarcPolicy::Quarantine),
+            "reject" => Some(DmarcPolicy::Reject),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            DmarcPolicy::None => "none",
+            DmarcPolicy::Quarantine => "quarantine",
+            DmarcPolicy::Reject => "reject"
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DmarcStatus {
+    Pass,
+    Fail,
+    None,
+    TempError,
+    PermError
+}
+
+impl DmarcStatus {
+    // The result name used in Authentication-Results headers (RFC 8601)
+    pub fn name(&self) -> &'static str {
+        match *self {
+            DmarcStatus::Pass => "pass",
+            DmarcStatus::Fail => "fail",
+            DmarcStatus::None => "none",
+            DmarcStatus::TempError => "temperror",
+            DmarcStatus::PermError => "permerror"
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum DmarcReason {
+    // the author domain couldn't be determined (RFC 7489 section 6.6.1)
+    MissingFrom,
+    MultipleFrom,
+    BadFromAddress(String),
+
+    // the From domain has no usable policy record, or it couldn't be retrieved
+    NoPolicy,
+    PolicyUnavailable,
+
+    // no passing DKIM signature or SPF check was aligned with the From domain
+    NotAligned
+}
+
+// The outcome of checking a message against its author domain's DMARC policy
+#[derive(Debug, PartialEq, Clone)]
+pub struct DmarcResult {
+    pub status: DmarcStatus,
+    pub reason: Option<DmarcReason>,
+    // the RFC5322.From domain
+    pub from_domain: Option<String>,
+    // the domain whose record was used: the From domain or its
+    // organizational domain
+    pub policy_domain: Option<String>,
+    // the record found there, and the policy it requests for the From domain
+    // (p= or sp=)
+    pub record: Option<DmarcRecord>,
+    pub policy: Option<DmarcPolicy>,
+    // the policy actually applied, after pct= sampling
+    pub disposition: DmarcPolicy,
+    // the d= of a passing, aligned DKIM signature
+    pub dkim_domain: Option<String>,
+    pub spf_aligned: bool
+}
+
+impl DmarcResult {
+    pub fn error(status: DmarcStatus, reason: DmarcReason, from_domain: Option<String>) -> DmarcResult {
+        DmarcResult {
+            status: status,
+            reason: Some(reason),
+            from_domain: from_domain,
+            policy_domain: None,
+            record: None,
+            policy: None,
+            disposition: DmarcPolicy::None,
+            dkim_domain: None,
+            spf_aligned: false
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use events::MessageParserEvent;
+use events::MessageParserStage;
+use events::MessageParserEvent::{Header, DkimResult, SpfResult, DmarcResult, End};
+
+use dkim;
+use spf::{SpfStatus, SpfIdentity};
+use dmarc::{DmarcEvaluator, DmarcLookup, DmarcStatus, from_domain};
+use dmarc;
+use sampler::{Sampler, RANDOM_SAMPLER};
+#[cfg(test)]
+use dmarc::{DmarcPolicy, DmarcReason};
+#[cfg(test)]
+use dns::ZoneFile;
+
+// Checks each message against the DMARC policy of its From domain, using the
+// DkimResult and SpfResult events from an earlier DkimChecker and SpfChecker.
+// The result is reported with a DmarcResult event just before End.
+pub struct DmarcChecker<'a> {
+    headers: Vec<(String, String, Vec<u8>)>,
+    dkim_results: Vec<dkim::DkimResult>,
+    // the domain SPF authorized the client for
+    spf_domain: Option<String>,
+    evaluator: DmarcEvaluator<'a>,
+    sampler: &'a (Sampler + 'a),
+    next_stage: &'a mut (MessageParserStage + 'a)
+}
+
+impl<'a> DmarcChecker<'a> {
+    pub fn new(next_stage: &'a mut MessageParserStage, lookup: &'a DmarcLookup) -> DmarcChecker<'a> {
+        DmarcChecker::with_sampler(next_stage, lookup, &RANDOM_SAMPLER)
+    }
+
+    pub fn with_sampler(next_stage: &'a mut MessageParserStage, lookup: &'a DmarcLookup,
+                        sampler: &'a Sampler) -> DmarcChecker<'a> {
+        DmarcChecker {
+            headers: vec![],
+            dkim_results: vec![],
+            spf_domain: None,
+            evaluator: DmarcEvaluator::new(lookup),
+            sampler: sampler,
+            next_stage: next_stage
+        }
+    }
+
+    fn evaluate(&self) -> dmarc::DmarcResult {
+        match from_domain(&self.headers) {
+            Ok(domain) => self.evaluator.evaluate(&domain, &self.dkim_results,
+                                                  self.spf_domain.as_ref().map(|d| &d[..]), self.sampler.sample()),
+            Err(reason) => dmarc::DmarcResult::error(DmarcStatus::PermError, reason, None)
+        }
+    }
+}
+
+impl<'a> MessageParserStage for DmarcChecker<'a> {
+    fn process_event(&mut self, event: MessageParserEvent) {
+        match event {
+            Header(ref name, ref value, ref raw) => {
+                self.headers.push((name.clone(), value.clone(), raw.clone()));
+            }
+            DkimResult(ref result) => {
+                self.dkim_results.push(result.clone());
+            }
+            SpfResult(ref result) if result.status == SpfStatus::Pass && result.identity == SpfIdentity::MailFrom => {
+                self.spf_domain = Some(result.domain.clone());
+            }
+            End => {
+                let result = self.evaluate();
+                self.next_stage.process_event(DmarcResult(result));
+            }
+            _ => ()
+        }
+        self.next_stage.process_event(event);
+    }
+}
+
+#[cfg(test)]
+fn test_dmarc_result(msg: &str, zone: &ZoneFile, sampler: &Sampler) -> dmarc::DmarcResult {
+    use message_parser_sink::MessageParserSink;
+    use reader_parser::ReaderParser;
+    use message_scanner::MessageScanner;
+    use header_parser::HeaderParser;
+    use dkim_checker::DkimChecker;
+    use events::MessageParserFilter;
+
+    let mut sink = MessageParserSink::new();
+    {
+        let r = msg.as_bytes();
+        let mut dmarc = DmarcChecker::with_sampler(&mut sink, zone, sampler);
+        let mut dkim = DkimChecker::new(&mut dmarc, zone);
+        let mut parser: HeaderParser = MessageParserFilter::new(&mut dkim);
+        let mut scanner: MessageScanner = MessageParserFilter::new(&mut parser);
+        let mut rp = ReaderParser::new(&mut scanner, r);
+
+        rp.read_to_end();
+    }
+
+    let results: Vec<dmarc::DmarcResult> = sink.events().into_iter().filter_map(|e| match e {
+        DmarcResult(result) => Some(result),
+        _ => None
+    }).collect();
+    assert_eq!(1, results.len());
+    results[0].clone()
+}
+
+#[test]
+fn dmarc_checker_test() {
+    use dkim::TEST_PUBLIC_KEY;
+    use dkim_checker::TEST_MESSAGE;
+    use sampler::FixedSampler;
+
+    let sampler = FixedSampler::new(0);
+    let mut zone = ZoneFile::new();
+    zone.add_txt("brisbane._domainkey.example.com", &format!("v=DKIM1; k=rsa; p={}", TEST_PUBLIC_KEY));
+    zone.add_txt("_dmarc.example.com", "v=DMARC1; p=reject");
+
+    // the From domain, football.example.com, is aligned with d=example.com
+    let result = test_dmarc_result(TEST_MESSAGE, &zone, &sampler);
+    assert_eq!(DmarcStatus::Pass, result.status);
+    assert_eq!(Some("football.example.com".to_string()), result.from_domain);
+    assert_eq!(Some("example.com".to_string()), result.dkim_domain);
+
+    let msg = TEST_MESSAGE.replace("Is dinner ready?", "Is lunch ready?");
+    let result = test_dmarc_result(&msg, &zone, &sampler);
+    assert_eq!(DmarcStatus::Fail, result.status);
+    assert_eq!(DmarcPolicy::Reject, result.disposition);
+
+    // with pct=, the sampler decides whether a failing message gets the policy
+    zone.add_txt("_dmarc.football.example.com", "v=DMARC1; p=reject; pct=25");
+    assert_eq!(DmarcPolicy::Reject, test_dmarc_result(&msg, &zone, &FixedSampler::new(24)).disposition);
+    assert_eq!(DmarcPolicy::Quarantine, test_dmarc_result(&msg, &zone, &FixedSampler::new(25)).disposition);
+
+    let result = test_dmarc_result("To: Suzie Q <suzie@shopping.example.net>\r\n\r\nHi.\r\n", &zone, &sampler);
+    assert_eq!(DmarcStatus::PermError, result.status);
+    assert_eq!(Some(DmarcReason::MissingFrom), result.reason);
+}
@@ -0,0 +1,119 @@
+use std::net::IpAddr;
+
+use events::MessageParserEvent;
+use events::MessageParserStage;
+use events::MessageParserEvent::{Envelope, DkimResult, SpfResult, DmarcResult, End};
+
+use dkim;
+use spf;
+use dmarc;
+use dmarc::DmarcReporter;
+
+// Records each message's DMARC outcome with a DmarcReporter, for aggregate
+// reports.  It goes after a DmarcChecker, and only messages whose Envelope
+// event has a client IP are recorded.
+pub struct DmarcReportStage<'a, 'r: 'a> {
+    client_ip: Option<IpAddr>,
+    dkim_results: Vec<dkim::DkimResult>,
+    spf_results: Vec<spf::SpfResult>,
+    dmarc_result: Option<dmarc::DmarcResult>,
+    reporter: &'a mut DmarcReporter<'r>,
+    next_stage: &'a mut (MessageParserStage + 'a)
+}
+
+impl<'a, 'r: 'a> DmarcReportStage<'a, 'r> {
+    pub fn new(next_stage: &'a mut MessageParserStage, reporter: &'a mut DmarcReporter<'r>) -> DmarcReportStage<'a, 'r> {
+        DmarcReportStage {
+            client_ip: None,
+            dkim_results: vec![],
+            spf_results: vec![],
+            dmarc_result: None,
+            reporter: reporter,
+            next_stage: next_stage
+        }
+    }
+}
+
+impl<'a, 'r: 'a> MessageParserStage for DmarcReportStage<'a, 'r> {
+    fn process_event(&mut self, event: MessageParserEvent) {
+        match event {
+            Envelope(ref envelope) => {
+                self.client_ip = envelope.client_ip;
+            }
+            DkimResult(ref result) => {
+                self.dkim_results.push(result.clone());
+            }
+            SpfResult(ref result) => {
+                self.spf_results.push(result.clone());
+            }
+            DmarcResult(ref result) => {
+                self.dmarc_result = Some(result.clone());
+            }
+            End => {
+                match (self.client_ip, &self.dmarc_result) {
+                    (Some(ip), &Some(ref result)) => {
+                        self.reporter.add(ip, result, &self.dkim_results, &self.spf_results);
+                    }
+                    _ => ()
+                }
+            }
+            _ => ()
+        }
+        self.next_stage.process_event(event);
+    }
+}
+
+#[test]
+fn dmarc_report_stage_test() {
+    use message_parser_sink::MessageParserSink;
+    use reader_parser::ReaderParser;
+    use message_scanner::MessageScanner;
+    use header_parser::HeaderParser;
+    use spf_checker::SpfChecker;
+    use dmarc_checker::DmarcChecker;
+    use events::MessageParserFilter;
+    use envelope;
+    use clock::FixedClock;
+    use dns::ZoneFile;
+
+    let msg = "From: Joe SixPack <joe@football.example.com>\r\n\
+               To: Suzie Q <suzie@shopping.example.net>\r\n\
+               Subject: Is dinner ready?\r\n\
+               \r\n\
+               Hi.\r\n";
+
+    let mut zone = ZoneFile::new();
+    zone.add_txt("example.com", "v=spf1 ip4:192.0.2.0/24 -all");
+    zone.add_txt("_dmarc.example.com", "v=DMARC1; p=reject; rua=mailto:dmarc@example.com");
+
+    let mut envelope = envelope::Envelope::new();
+    envelope.client_ip = Some("192.0.2.1".parse().unwrap());
+    envelope.mail_from = Some("joe@example.com".to_string());
+
+    let clock = FixedClock::new(1117574938);
+    let mut reporter = DmarcReporter::with_clock("Receiver", "dmarc-reports@receiver.example", &clock);
+    for _ in 0..2 {
+        let mut sink = MessageParserSink::new();
+        let r = msg.as_bytes();
+        let mut report = DmarcReportStage::new(&mut sink, &mut reporter);
+        let mut dmarc = DmarcChecker::new(&mut report, &zone);
+        let mut spf = SpfChecker::new(&mut dmarc, &zone);
+        let mut parser: HeaderParser = MessageParserFilter::new(&mut spf);
+        let mut scanner: MessageScanner = MessageParserFilter::new(&mut parser);
+        let mut rp = ReaderParser::with_envelope(&mut scanner, r, envelope.clone());
+
+        rp.read_to_end();
+    }
+
+    let reports = reporter.reports();
+    assert_eq!(1, reports.len());
+    let record = &reports[0].0.records[0];
+    assert_eq!("football.example.com", record.header_from);
+    assert_eq!(2, record.count);
+    assert!(record.spf_aligned);
+    assert_eq!(vec![dmarc::SpfAuthResult {
+        domain: "example.com".to_string(),
+        scope: Some("mfrom".to_string()),
+        result: "pass".to_string()
+    }], record.spf_results);
+}
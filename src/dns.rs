@@ -0,0 +1,355 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::ascii::AsciiExt;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum DnsError {
+    NotFound,
+    TempFail(String)
+}
+
+#[derive(Debug)]
+pub enum ZoneFileError {
+    Io(io::Error),
+    Syntax(usize, String)
+}
+
+// An in-memory set of DNS records, loaded from a master (zone) file or
+// added directly.  Useful for tests, and for scanning mail without live DNS.
+pub struct ZoneFile {
+    txt_records: HashMap<String, Vec<String>>,
+    a_records: HashMap<String, Vec<Ipv4Addr>>,
+    aaaa_records: HashMap<String, Vec<Ipv6Addr>>,
+    mx_records: HashMap<String, Vec<(u16, String)>>
+}
+
+struct ZoneEntry {
+    line: usize,
+    inherits_owner: bool,
+    tokens: Vec<String>
+}
+
+impl ZoneFile {
+    pub fn new() -> ZoneFile {
+        ZoneFile {
+            txt_records: HashMap::new(),
+            a_records: HashMap::new(),
+            aaaa_records: HashMap::new(),
+            mx_records: HashMap::new()
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<ZoneFile, ZoneFileError> {
+        let mut zone = String::new();
+        let result = File::open(path).and_then(|mut f| f.read_to_string(&mut zone));
+        match result {
+            Ok(_) => ZoneFile::parse(&zone),
+            Err(e) => Err(ZoneFileError::Io(e))
+        }
+    }
+
+    pub fn parse(zone: &str) -> Result<ZoneFile, ZoneFileError> {
+        let mut zone_file = ZoneFile::new();
+        let mut origin = String::new();
+        let mut owner: Option<String> = None;
+
+        for entry in try!(tokenize(zone)) {
+            let mut tokens = entry.tokens.iter();
+
+            if !entry.inherits_owner {
+                let first = match tokens.next() {
+                    Some(t) => t,
+                    None => continue
+                };
+                if first.eq_ignore_ascii_case("$ORIGIN") {
+                    match tokens.next() {
+                        Some(o) => origin = normalize_name(o),
+                        None => return Err(ZoneFileError::Syntax(entry.line, "missing origin".to_string()))
+                    }
+                    continue;
+                }
+                if first.starts_with("$") {
+                    // $TTL and $INCLUDE don't affect the records we keep
+                    continue;
+                }
+                owner = Some(absolute_name(first, &origin));
+            }
+
+            let name = match owner {
+                Some(ref name) => name.clone(),
+                None => return Err(ZoneFileError::Syntax(entry.line, "record with no owner".to_string()))
+            };
+
+            // skip the optional TTL and class, which may appear in either order
+            let mut record_type = None;
+            for token in tokens.by_ref() {
+                if token.chars().all(|c| c.is_digit(10)) || is_class(token) {
+                    continue;
+                }
+                record_type = Some(token.to_ascii_uppercase());
+                break;
+            }
+
+            let rdata: Vec<String> = tokens.cloned().collect();
+            let bad_rdata = |t: &str| ZoneFileError::Syntax(entry.line, format!("bad {} record", t));
+            match record_type {
+                Some(ref t) if *t == "TXT" => zone_file.add_txt(&name, &rdata.concat()),
+                Some(ref t) if *t == "A" => {
+                    match rdata.first().and_then(|a| a.parse().ok()) {
+                        Some(address) => zone_file.add_a(&name, address),
+                        None => return Err(bad_rdata(&t[..]))
+                    }
+                }
+                Some(ref t) if *t == "AAAA" => {
+                    match rdata.first().and_then(|a| a.parse().ok()) {
+                        Some(address) => zone_file.add_aaaa(&name, address),
+                        None => return Err(bad_rdata(&t[..]))
+                    }
+                }
+                Some(ref t) if *t == "MX" => {
+                    match (rdata.get(0).and_then(|p| p.parse().ok()), rdata.get(1)) {
+                        (Some(preference), Some(exchange)) =>
+                            zone_file.add_mx(&name, preference, &absolute_name(exchange, &origin)),
+                        _ => return Err(bad_rdata(&t[..]))
+                    }
+                }
+                Some(_) => (),
+                None => return Err(ZoneFileError::Syntax(entry.line, "missing record type".to_string()))
+            }
+        }
+
+        Ok(zone_file)
+    }
+
+    pub fn add_txt(&mut self, name: &str, txt: &str) {
+        let records = self.txt_records.entry(normalize_name(name)).or_insert(vec![]);
+        records.push(txt.to_string());
+    }
+
+    pub fn add_a(&mut self, name: &str, address: Ipv4Addr) {
+        let records = self.a_records.entry(normalize_name(name)).or_insert(vec![]);
+        records.push(address);
+    }
+
+    pub fn add_aaaa(&mut self, name: &str, address: Ipv6Addr) {
+        let records = self.aaaa_records.entry(normalize_name(name)).or_insert(vec![]);
+        records.push(address);
+    }
+
+    pub fn add_mx(&mut self, name: &str, preference: u16, exchange: &str) {
+        let records = self.mx_records.entry(normalize_name(name)).or_insert(vec![]);
+        records.push((preference, normalize_name(exchange)));
+    }
+
+    pub fn txt(&self, name: &str) -> Result<Vec<String>, DnsError> {
+        lookup(&self.txt_records, name)
+    }
+
+    pub fn a(&self, name: &str) -> Result<Vec<Ipv4Addr>, DnsError> {
+        lookup(&self.a_records, name)
+    }
+
+    pub fn aaaa(&self, name: &str) -> Result<Vec<Ipv6Addr>, DnsError> {
+        lookup(&self.aaaa_records, name)
+    }
+
+    // MX records in order of preference
+    pub fn mx(&self, name: &str) -> Result<Vec<(u16, String)>, DnsError> {
+        let mut records = try!(lookup(&self.mx_records, name));
+        records.sort();
+        Ok(records)
+    }
+}
+
+fn lookup<T: Clone>(records: &HashMap<String, Vec<T>>, name: &str) -> Result<Vec<T>, DnsError> {
+    match records.get(&normalize_name(name)) {
+        Some(records) => Ok(records.clone()),
+        None => Err(DnsError::NotFound)
+    }
+}
+
+fn normalize_name(name: &str) -> String {
+    name.trim_right_matches('.').to_ascii_lowercase()
+}
+
+fn absolute_name(name: &str, origin: &str) -> String {
+    if name == "@" {
+        origin.to_string()
+    }
+    else if name.ends_with(".") || origin.is_empty() {
+        normalize_name(name)
+    }
+    else {
+        normalize_name(&format!("{}.{}", name, origin))
+    }
+}
+
+fn is_class(token: &str) -> bool {
+    ["IN", "CH", "HS", "CS"].iter().any(|c| token.eq_ignore_ascii_case(c))
+}
+
+// Splits a zone file into entries, joining lines inside parentheses and
+// removing comments and quoting.
+fn tokenize(zone: &str) -> Result<Vec<ZoneEntry>, ZoneFileError> {
+    let mut entries = vec![];
+    let mut tokens: Vec<String> = vec![];
+    let mut token = String::new();
+    let mut in_token = false;
+    let mut in_quote = false;
+    let mut in_comment = false;
+    let mut depth = 0;
+    let mut line = 1;
+    let mut entry_line = 1;
+    let mut inherits_owner = false;
+    let mut at_line_start = true;
+
+    let mut chars = zone.chars().peekable();
+    while let Some(c) = chars.next() {
+        if at_line_start && depth == 0 {
+            inherits_owner = c == ' ' || c == '\t';
+            entry_line = line;
+        }
+        at_line_start = false;
+
+        if in_comment && c != '\n' {
+            continue;
+        }
+
+        if c == '\\' {
+            let mut digits = String::new();
+            while digits.len() < 3 && chars.peek().map_or(false, |d| d.is_digit(10)) {
+                digits.push(chars.next().unwrap());
+            }
+            let escaped = if digits.len() == 3 {
+                match digits.parse::<u8>() {
+                    Ok(b) => b as char,
+                    Err(_) => return Err(ZoneFileError::Syntax(line, "bad escape".to_string()))
+                }
+            }
+            else if digits.is_empty() {
+                match chars.next() {
+                    Some(e) => e,
+                    None => return Err(ZoneFileError::Syntax(line, "bad escape".to_string()))
+                }
+            }
+            else {
+                return Err(ZoneFileError::Syntax(line, "bad escape".to_string()));
+            };
+            token.push(escaped);
+            in_token = true;
+            continue;
+        }
+
+        if in_quote {
+            match c {
+                '"' => in_quote = false,
+                '\n' => return Err(ZoneFileError::Syntax(line, "unterminated string".to_string())),
+                _ => token.push(c)
+            }
+            continue;
+        }
+
+        match c {
+            '"' => {
+                in_quote = true;
+                in_token = true;
+            }
+            ';' => in_comment = true,
+            '(' | ')' | ' ' | '\t' | '\r' | '\n' => {
+                if in_token {
+                    tokens.push(token.clone());
+                    token.clear();
+                    in_token = false;
+                }
+                match c {
+                    '(' => depth = depth + 1,
+                    ')' if depth == 0 => return Err(ZoneFileError::Syntax(line, "unbalanced parentheses".to_string())),
+                    ')' => depth = depth - 1,
+                    '\n' => {
+                        in_comment = false;
+                        line = line + 1;
+                        at_line_start = true;
+                        if depth == 0 && !tokens.is_empty() {
+                            entries.push(ZoneEntry { line: entry_line, inherits_owner: inherits_owner, tokens: tokens.clone() });
+                            tokens.clear();
+                        }
+                    }
+                    _ => ()
+                }
+            }
+            _ => {
+                token.push(c);
+                in_token = true;
+            }
+        }
+    }
+
+    if in_quote || depth != 0 {
+        return Err(ZoneFileError::Syntax(line, "unexpected end of zone file".to_string()));
+    }
+    if in_token {
+        tokens.push(token);
+    }
+    if !tokens.is_empty() {
+        entries.push(ZoneEntry { line: entry_line, inherits_owner: inherits_owner, tokens: tokens });
+    }
+
+    Ok(entries)
+}
+
+#[test]
+fn test_parse_zone_file() {
+    let zone = "$ORIGIN example.com.\n\
+                $TTL 3600\n\
+                @        IN SOA ns.example.com. hostmaster.example.com. ( 1 3600 600 86400 300 )\n\
+                brisbane._domainkey IN TXT ( \"v=DKIM1; k=rsa; \" ; the key\n\
+                                             \"p=MIGfMA0\" )\n\
+                other.example.net. 300 TXT \"a\\\"b\" \"c\\059d\"\n\
+                \x20                  TXT \"second\"\n";
+
+    let zone_file = ZoneFile::parse(zone).unwrap();
+
+    assert_eq!(Ok(vec!["v=DKIM1; k=rsa; p=MIGfMA0".to_string()]),
+               zone_file.txt("Brisbane._domainkey.example.com."));
+    assert_eq!(Ok(vec!["a\"bc;d".to_string(), "second".to_string()]),
+               zone_file.txt("other.example.net"));
+    assert_eq!(Err(DnsError::NotFound), zone_file.txt("example.com"));
+}
+
+#[test]
+fn test_parse_address_records() {
+    let zone = "$ORIGIN example.com.\n\
+                @     IN MX 20 mx2\n\
+                \x20     IN MX 10 mx1.example.com.\n\
+                mx1   IN A 192.0.2.1\n\
+                \x20     IN AAAA 2001:db8::1\n";
+
+    let zone_file = ZoneFile::parse(zone).unwrap();
+
+    assert_eq!(Ok(vec![(10, "mx1.example.com".to_string()), (20, "mx2.example.com".to_string())]),
+               zone_file.mx("example.com"));
+    assert_eq!(Ok(vec![Ipv4Addr::new(192, 0, 2, 1)]), zone_file.a("mx1.example.com"));
+    assert_eq!(Ok(vec![Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)]), zone_file.aaaa("mx1.example.com"));
+    assert_eq!(Err(DnsError::NotFound), zone_file.a("mx2.example.com"));
+    assert!(ZoneFile::parse("mx1.example.com. A 192.0.2\n").is_err());
+}
+
+#[test]
+fn test_zone_file_errors() {
+    assert!(match ZoneFile::parse("name TXT \"unterminated\n") {
+        Err(ZoneFileError::Syntax(1, _)) => true,
+        _ => false
+    });
+    assert!(match ZoneFile::parse("name TXT ( \"a\"\n\n") {
+        Err(ZoneFileError::Syntax(_, _)) => true,
+        _ => false
+    });
+    assert!(match ZoneFile::parse("  TXT \"no owner\"\n") {
+        Err(ZoneFileError::Syntax(1, _)) => true,
+        _ => false
+    });
+}
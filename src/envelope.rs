@@ -0,0 +1,24 @@
+use std::net::IpAddr;
+
+// What the SMTP session told us about a message, which isn't in the message
+// itself.  It starts a pipeline as an Envelope event, so that any stage can
+// read it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Envelope {
+    pub client_ip: Option<IpAddr>,
+    pub helo: Option<String>,
+    // the MAIL FROM reverse-path, which is empty for bounces
+    pub mail_from: Option<String>,
+    pub recipients: Vec<String>
+}
+
+impl Envelope {
+    pub fn new() -> Envelope {
+        Envelope {
+            client_ip: None,
+            helo: None,
+            mail_from: None,
+            recipients: vec![]
+        }
+    }
+}
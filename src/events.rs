@@ -1,12 +1,22 @@
+use dkim::DkimResult;
+use arc::ArcResult;
+use dmarc::DmarcResult;
+use spf::SpfResult;
+use envelope::Envelope;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum MessageParserEvent {
+    Envelope(Envelope),
     MessageByte(u8),
     HeaderName(String),
     HeaderValue(String),
     Header(String,String,Vec<u8>),
     EndOfHeaders,
     BodyChunk(Vec<u8>),
+    DkimResult(DkimResult),
+    ArcResult(ArcResult),
+    SpfResult(SpfResult),
+    DmarcResult(DmarcResult),
     ParseError,
     End,
     NonEvent
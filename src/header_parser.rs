@@ -1,5 +1,5 @@
 use events::MessageParserEvent::{HeaderName, HeaderValue, Header, 
-    EndOfHeaders, ParseError};
+    EndOfHeaders, ParseError, Envelope};
 #[cfg(test)]
 use events::MessageParserEvent::End;
 
@@ -63,6 +63,10 @@ impl<'a> HeaderParser<'a> {
                 self.next_stage.process_event(event);
                 ParseFinished
             },
+            Envelope(_) => {
+                self.next_stage.process_event(event);
+                ParseHeaderName
+            }
             _ => { 
                 self.next_stage.process_event(ParseError);
                 ParseFinished
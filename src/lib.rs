@@ -1,4 +1,6 @@
 extern crate regex;
+extern crate time;
+extern crate rand;
 
 pub use self::events::{MessageParserEvent, MessageParserStage, MessageParserFilter};
 pub use self::message_scanner::MessageScanner;
@@ -7,7 +9,33 @@ pub use self::header_decoder::HeaderDecoder;
 pub use self::rfc2047::FromRFC2047;
 pub use self::reader_parser::ReaderParser;
 pub use self::message_parser_sink::MessageParserSink;
-pub use self::dkim_checker::DkimChecker;
+pub use self::envelope::Envelope;
+pub use self::dkim_checker::{DkimChecker, DEFAULT_CLOCK_SKEW};
+pub use self::dkim_signing_stage::DkimSigningStage;
+pub use self::arc_checker::ArcChecker;
+pub use self::spf_checker::SpfChecker;
+pub use self::dmarc_checker::DmarcChecker;
+pub use self::dmarc_report_stage::DmarcReportStage;
+pub use self::authentication_results_stage::AuthenticationResultsStage;
+pub use self::dkim::{DkimKeyCache, DEFAULT_KEY_TTL, DEFAULT_NEGATIVE_KEY_TTL};
+pub use self::dkim::{DkimKeyLookup, DkimResult, DkimStatus, DkimReason, DkimAlgorithm};
+pub use self::dkim::{DkimPublicKey, DkimKeyType, DkimKey, DkimHeaderChange};
+pub use self::dkim::DkimSignatureParseError;
+pub use self::dkim::{DkimSigner, DkimSigningKey, DkimSigningError, CanonicalizationType};
+pub use self::arc::{ArcResult, ArcChainStatus, ArcReason, ArcValidator};
+pub use self::arc::{ArcSealer, ArcSealError};
+pub use self::authentication_results::{AuthenticationResults, MethodResult, Property};
+pub use self::authentication_results::{AuthenticationResultsParseError, trusted_authentication_results};
+pub use self::dmarc::{DmarcLookup, DmarcEvaluator, DmarcRecord, DmarcRecordError, DmarcAlignment};
+pub use self::dmarc::{DmarcResult, DmarcStatus, DmarcReason, DmarcPolicy};
+pub use self::dmarc::{DmarcReporter, AggregateReport, PublishedPolicy, ReportRecord};
+pub use self::dmarc::{DkimAuthResult, SpfAuthResult, ReportOutput, ReportDirectory};
+pub use self::dmarc::{ReportParseError, MAX_REPORT_SIZE, ReportSummary, PassRate};
+pub use self::spf::{SpfResolver, SpfEvaluator, SpfResult, SpfStatus, SpfReason, SpfIdentity};
+pub use self::public_suffix::{PublicSuffixList, organizational_domain};
+pub use self::dns::{DnsError, ZoneFile, ZoneFileError};
+pub use self::clock::{Clock, SystemClock, FixedClock};
+pub use self::sampler::{Sampler, RandomSampler, FixedSampler};
 
 mod events;
 mod message_scanner;
@@ -16,5 +44,21 @@ mod header_decoder;
 mod rfc2047;
 mod message_parser_sink;
 mod reader_parser;
+mod envelope;
 mod dkim_checker;
+mod dkim_signing_stage;
 mod dkim;
+mod arc_checker;
+mod arc;
+mod spf_checker;
+mod dmarc_checker;
+mod dmarc_report_stage;
+mod dmarc;
+mod authentication_results_stage;
+mod authentication_results;
+mod spf;
+mod public_suffix;
+mod punycode;
+mod dns;
+mod clock;
+mod sampler;
@@ -6,11 +6,14 @@
 extern crate mailcheck;
 extern crate time;
 use mailcheck::MessageParserEvent;
-use std::sync::Future;
+use mailcheck::{DkimKeyLookup, DkimKeyCache, ZoneFile};
+use std::sync::{Arc, Future};
 use std::fs;
+use std::io::{self, Write};
 use std::path::{Path,PathBuf};
+use std::process;
 
-fn parse_msg(path: &Path) -> Vec<MessageParserEvent>
+fn parse_msg(path: &Path, key_lookup: &DkimKeyLookup) -> Vec<MessageParserEvent>
 {
     use std::fs::File;
     use mailcheck::MessageParserFilter;
@@ -23,7 +26,7 @@ fn parse_msg(path: &Path) -> Vec<MessageParserEvent>
             {
                 let reader = file;
                 let mut header_decoder: HeaderDecoder= MessageParserFilter::new(&mut sink);
-                let mut dkim_checker: DkimChecker = MessageParserFilter::new(&mut header_decoder);
+                let mut dkim_checker = DkimChecker::new(&mut header_decoder, key_lookup);
                 let mut header_parser: HeaderParser = MessageParserFilter::new(&mut dkim_checker);
                 let mut message_scanner: MessageScanner = MessageParserFilter::new(&mut header_parser);
                 let mut rp = ReaderParser::new(&mut message_scanner, reader);
@@ -37,13 +40,16 @@ fn parse_msg(path: &Path) -> Vec<MessageParserEvent>
 }
 
 
-fn process_msgs_mt(msgs: fs::ReadDir) -> Vec<Future<usize>> {
+// keys are shared between the worker threads, so each selector is looked up
+// only once
+fn process_msgs_mt(msgs: fs::ReadDir, keys: Arc<DkimKeyCache<'static, ZoneFile>>) -> Vec<Future<usize>> {
     msgs.map(|msg| {
         match msg {
             Ok(dir_entry) => {
                 let path = dir_entry.path();
+                let keys = keys.clone();
                 Future::spawn(move || { 
-                    parse_msg(&path).iter().count() 
+                    parse_msg(&path, &*keys).iter().count() 
                 })
             }
             Err(_) => panic!("Error processing  message")
@@ -51,14 +57,28 @@ fn process_msgs_mt(msgs: fs::ReadDir) -> Vec<Future<usize>> {
     }).collect()
 }
 
+// DKIM keys are read from a zone file rather than live DNS, so that
+// results are repeatable.  Without the keys every signature would fail, so
+// there's no point carrying on.
+fn load_keys(path: &Path) -> ZoneFile {
+    match ZoneFile::load(path) {
+        Ok(keys) => keys,
+        Err(e) => {
+            let _ = writeln!(&mut io::stderr(), "Error loading DKIM keys from {}: {:?}", path.display(), e);
+            process::exit(1);
+        }
+    }
+}
+
 #[allow(dead_code)]
 fn process_dir(dir: &Path) {
 
     match fs::read_dir(dir) {
         Ok(msgs) => {
+            let keys = Arc::new(DkimKeyCache::new(load_keys(&dir.with_extension("zone")), 10000));
             let start = time::precise_time_ns();
 
-            let mut events = process_msgs_mt(msgs);
+            let mut events = process_msgs_mt(msgs, keys.clone());
 
             let msg_count = events.len();
             let event_count = events.iter_mut().fold(0, |sum, x| sum + x.get());
@@ -71,6 +91,7 @@ fn process_dir(dir: &Path) {
                      msg_count, duration_s, rate);
             println!("{} events in {:.3} seconds ({:.0} events/second)", 
                      event_count, duration_s, event_rate);
+            println!("{} key cache hits, {} misses", keys.hits(), keys.misses());
         },
         Err(e) => {
             println!("Error reading directory: {}", e);
@@ -83,7 +104,8 @@ fn process_msg(dir: PathBuf, msg: &str) {
     use mailcheck::MessageParserEvent::BodyChunk;
 
     let path = dir.join(Path::new(msg));
-    let events = parse_msg(&path);
+    let keys = load_keys(&dir.with_extension("zone"));
+    let events = parse_msg(&path, &keys);
 
     for event in events.iter() {
         match event {
@@ -0,0 +1,160 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::mem;
+use std::path::Path;
+use std::sync::{Once, ONCE_INIT};
+use std::ascii::AsciiExt;
+
+use punycode;
+
+// A snapshot of https://publicsuffix.org/list/public_suffix_list.dat, so that
+// organizational domains can be found without fetching the list
+const BUNDLED_LIST: &'static str = include_str!("../data/public_suffix_list.dat");
+
+// The rules of a Public Suffix List, used to find the organizational domain
+// of a name (RFC 7489 section 3.2)
+pub struct PublicSuffixList {
+    rules: HashSet<String>,
+    // the parents of wildcard rules: "ck" for "*.ck"
+    wildcards: HashSet<String>,
+    // exception rules, without the "!"
+    exceptions: HashSet<String>
+}
+
+impl PublicSuffixList {
+    pub fn load(path: &Path) -> io::Result<PublicSuffixList> {
+        let mut list = String::new();
+        try!(File::open(path).and_then(|mut f| f.read_to_string(&mut list)));
+        Ok(PublicSuffixList::parse(&list))
+    }
+
+    // Each rule is the first word of a line; comments start with "//"
+    pub fn parse(list: &str) -> PublicSuffixList {
+        let mut psl = PublicSuffixList {
+            rules: HashSet::new(),
+            wildcards: HashSet::new(),
+            exceptions: HashSet::new()
+        };
+
+        for line in list.lines() {
+            let rule = match line.trim().split(|c: char| c.is_whitespace()).next() {
+                Some(rule) if !rule.is_empty() && !rule.starts_with("//") => normalize_name(rule),
+                _ => continue
+            };
+            if rule.starts_with("!") {
+                psl.exceptions.insert(rule[1..].to_string());
+            }
+            else if rule.starts_with("*.") {
+                psl.wildcards.insert(rule[2..].to_string());
+            }
+            else {
+                psl.rules.insert(rule);
+            }
+        }
+
+        psl
+    }
+
+    // The list built into the crate
+    pub fn bundled() -> &'static PublicSuffixList {
+        static INIT: Once = ONCE_INIT;
+        static mut BUNDLED: *const PublicSuffixList = 0 as *const PublicSuffixList;
+
+        unsafe {
+            INIT.call_once(|| {
+                BUNDLED = mem::transmute(Box::new(PublicSuffixList::parse(BUNDLED_LIST)));
+            });
+            &*BUNDLED
+        }
+    }
+
+    // The number of labels in the public suffix of a domain.  An exception
+    // rule beats any other match, and otherwise the rule with the most labels
+    // wins; a name no rule matches has a one label suffix.
+    fn suffix_labels(&self, labels: &[&str]) -> usize {
+        for i in 0..labels.len() {
+            if self.exceptions.contains(&labels[i..].connect(".")) {
+                return labels.len() - i - 1;
+            }
+        }
+        for i in 0..labels.len() {
+            if self.rules.contains(&labels[i..].connect(".")) ||
+                (i + 1 < labels.len() && self.wildcards.contains(&labels[i + 1..].connect("."))) {
+                return labels.len() - i;
+            }
+        }
+        1
+    }
+
+    pub fn public_suffix(&self, domain: &str) -> String {
+        let domain = normalize_name(domain);
+        let labels: Vec<&str> = domain.split('.').collect();
+        let suffix_labels = self.suffix_labels(&labels);
+        labels[labels.len() - suffix_labels..].connect(".")
+    }
+
+    // The public suffix and one more label.  A name that is itself a public
+    // suffix is its own organizational domain.
+    pub fn organizational_domain(&self, domain: &str) -> String {
+        let domain = normalize_name(domain);
+        let labels: Vec<&str> = domain.split('.').collect();
+        let suffix_labels = self.suffix_labels(&labels);
+        if labels.len() <= suffix_labels {
+            return domain.clone();
+        }
+        labels[labels.len() - suffix_labels - 1..].connect(".")
+    }
+}
+
+// The organizational domain of a name, using the bundled list
+pub fn organizational_domain(domain: &str) -> String {
+    PublicSuffixList::bundled().organizational_domain(domain)
+}
+
+// Names are compared in lower case, with internationalized labels in the
+// A-label form found in mail; the list writes them as U-labels
+fn normalize_name(name: &str) -> String {
+    let labels: Vec<String> = name.trim_right_matches('.').to_ascii_lowercase()
+        .split('.').map(|label| punycode::to_ascii_label(label)).collect();
+    labels.connect(".")
+}
+
+#[test]
+fn test_public_suffix_rules() {
+    let psl = PublicSuffixList::parse("// comment\n\
+                                       com\n\
+                                       uk\n\
+                                       co.uk  extra words are ignored\n\
+                                       *.ck\n\
+                                       !www.ck\n");
+
+    assert_eq!("co.uk", psl.public_suffix("www.example.co.uk"));
+    assert_eq!("example.co.uk", psl.organizational_domain("www.Example.co.uk."));
+    assert_eq!("co.uk", psl.organizational_domain("co.uk"));
+
+    // a wildcard covers one more label, unless there's an exception
+    assert_eq!("foo.ck", psl.public_suffix("www.foo.ck"));
+    assert_eq!("www.foo.ck", psl.organizational_domain("a.www.foo.ck"));
+    assert_eq!("ck", psl.public_suffix("www.ck"));
+    assert_eq!("www.ck", psl.organizational_domain("a.www.ck"));
+
+    // names under an unlisted TLD take their last label as the suffix
+    assert_eq!("example.test", psl.organizational_domain("mail.example.test"));
+}
+
+#[test]
+fn test_bundled_public_suffix_list() {
+    assert_eq!("example.com", organizational_domain("mail.example.com"));
+    assert_eq!("example.co.uk", organizational_domain("a.b.example.co.uk"));
+    assert_eq!("city.kawasaki.jp", organizational_domain("www.city.kawasaki.jp"));
+    assert_eq!("b.c.kawasaki.jp", organizational_domain("a.b.c.kawasaki.jp"));
+    assert_eq!("example.github.io", organizational_domain("www.example.github.io"));
+
+    // rules for internationalized suffixes match both label forms
+    assert_eq!("xn--55qx5d.cn", PublicSuffixList::bundled().public_suffix("a.example.xn--55qx5d.cn"));
+    assert_eq!("example.xn--55qx5d.cn", organizational_domain("a.example.xn--55qx5d.cn"));
+    assert_eq!("example.xn--55qx5d.cn", organizational_domain("a.example.公司.cn"));
+    assert_eq!("example.xn--55qx5d.xn--j6w193g", organizational_domain("www.example.xn--55qx5d.xn--j6w193g"));
+}
@@ -0,0 +1,119 @@
+// Punycode (RFC 3492), for turning the U-label of an internationalized
+// domain name into the A-label (with its "xn--" prefix) used in DNS and mail
+
+const BASE: u64 = 36;
+const TMIN: u64 = 1;
+const TMAX: u64 = 26;
+const SKEW: u64 = 38;
+const DAMP: u64 = 700;
+const INITIAL_BIAS: u64 = 72;
+const INITIAL_N: u64 = 128;
+
+// The A-label of a label; ASCII labels are returned unchanged
+pub fn to_ascii_label(label: &str) -> String {
+    if label.chars().all(|c| (c as u32) < 0x80) {
+        label.to_string()
+    }
+    else {
+        format!("xn--{}", encode(label))
+    }
+}
+
+// RFC 3492 section 6.3
+pub fn encode(input: &str) -> String {
+    let code_points: Vec<u64> = input.chars().map(|c| c as u64).collect();
+    let mut output: String = input.chars().filter(|&c| (c as u32) < 0x80).collect();
+    let basic = output.len() as u64;
+    if basic > 0 {
+        output.push('-');
+    }
+
+    let mut n = INITIAL_N;
+    let mut delta = 0;
+    let mut bias = INITIAL_BIAS;
+    let mut handled = basic;
+    while handled < code_points.len() as u64 {
+        let m = code_points.iter().cloned().filter(|&c| c >= n).min().unwrap();
+        delta += (m - n) * (handled + 1);
+        n = m;
+
+        for &c in code_points.iter() {
+            if c < n {
+                delta += 1;
+            }
+            if c == n {
+                let mut q = delta;
+                let mut k = BASE;
+                loop {
+                    let t = threshold(k, bias);
+                    if q < t {
+                        break;
+                    }
+                    output.push(digit(t + (q - t) % (BASE - t)));
+                    q = (q - t) / (BASE - t);
+                    k += BASE;
+                }
+                output.push(digit(q));
+                bias = adapt(delta, handled + 1, handled == basic);
+                delta = 0;
+                handled += 1;
+            }
+        }
+        delta += 1;
+        n += 1;
+    }
+
+    output
+}
+
+fn threshold(k: u64, bias: u64) -> u64 {
+    if k <= bias {
+        TMIN
+    }
+    else if k >= bias + TMAX {
+        TMAX
+    }
+    else {
+        k - bias
+    }
+}
+
+// RFC 3492 section 6.1
+fn adapt(delta: u64, num_points: u64, first_time: bool) -> u64 {
+    let mut delta = if first_time { delta / DAMP } else { delta / 2 };
+    delta += delta / num_points;
+    let mut k = 0;
+    while delta > ((BASE - TMIN) * TMAX) / 2 {
+        delta /= BASE - TMIN;
+        k += BASE;
+    }
+    k + (BASE - TMIN + 1) * delta / (delta + SKEW)
+}
+
+fn digit(d: u64) -> char {
+    if d < 26 {
+        (b'a' + d as u8) as char
+    }
+    else {
+        (b'0' + (d - 26) as u8) as char
+    }
+}
+
+#[test]
+fn test_encode() {
+    assert_eq!("55qx5d", encode("公司"));
+    assert_eq!("bcher-kva", encode("bücher"));
+    assert_eq!("mnchen-3ya", encode("münchen"));
+    // RFC 3492 section 7.1, (A) Arabic and (L)
+    assert_eq!("egbpdaj6bu4bxfgehfvwxn",
+               encode("\u{644}\u{64a}\u{647}\u{645}\u{627}\u{628}\u{62a}\u{643}\u{644}\u{645}\u{648}\u{634}\u{639}\u{631}\u{628}\u{64a}\u{61f}"));
+    assert_eq!("3B-ww4c5e180e575a65lsy2b",
+               encode("3\u{5e74}B\u{7d44}\u{91d1}\u{516b}\u{5148}\u{751f}"));
+}
+
+#[test]
+fn test_to_ascii_label() {
+    assert_eq!("example", to_ascii_label("example"));
+    assert_eq!("xn--55qx5d", to_ascii_label("公司"));
+    assert_eq!("xn--j6w193g", to_ascii_label("香港"));
+}
@@ -1,11 +1,14 @@
 use std::io::Read;
 
 use events::MessageParserStage;
-use events::MessageParserEvent::{End, MessageByte, ParseError};
+use events::MessageParserEvent::{End, MessageByte, ParseError, Envelope};
+
+use envelope;
 
 
 pub struct ReaderParser<'a, R: Read> {
     reader: R,
+    envelope: Option<envelope::Envelope>,
     next_stage: &'a mut (MessageParserStage + 'a)
 }
 
@@ -14,6 +17,17 @@ impl<'a, R: Read> ReaderParser<'a, R> {
     pub fn new(next_stage: &'a mut MessageParserStage, reader: R) -> ReaderParser<'a, R> {
         ReaderParser {
             reader: reader,
+            envelope: None,
+            next_stage: next_stage
+        }
+    }
+
+    // The envelope is sent as the first event of the message
+    pub fn with_envelope(next_stage: &'a mut MessageParserStage, reader: R,
+                         envelope: envelope::Envelope) -> ReaderParser<'a, R> {
+        ReaderParser {
+            reader: reader,
+            envelope: Some(envelope),
             next_stage: next_stage
         }
     }
@@ -21,6 +35,10 @@ impl<'a, R: Read> ReaderParser<'a, R> {
     pub fn read_to_end(&mut self) {
         const BUF_SIZE: usize = 4 * 1024;
         let mut prev_char: u8 = b'\0';
+        match self.envelope.take() {
+            Some(envelope) => self.next_stage.process_event(Envelope(envelope)),
+            None => ()
+        }
         loop {
             let mut buf: [u8; BUF_SIZE] = [b'\0'; BUF_SIZE];
             match self.reader.read(&mut buf) {
@@ -0,0 +1,45 @@
+use rand::{self, Rng};
+
+// A source of the numbers from 0 to 99 that a DMARC record's pct= is
+// compared against.  Like Clock, it's taken by the checks that use it so
+// that tests can pin it.
+pub trait Sampler {
+    fn sample(&self) -> u32;
+}
+
+pub struct RandomSampler;
+
+pub static RANDOM_SAMPLER: RandomSampler = RandomSampler;
+
+impl Sampler for RandomSampler {
+    fn sample(&self) -> u32 {
+        rand::thread_rng().gen_range(0, 100)
+    }
+}
+
+pub struct FixedSampler {
+    sample: u32
+}
+
+impl FixedSampler {
+    pub fn new(sample: u32) -> FixedSampler {
+        FixedSampler { sample: sample }
+    }
+}
+
+impl Sampler for FixedSampler {
+    fn sample(&self) -> u32 {
+        self.sample
+    }
+}
+
+#[test]
+fn test_samplers() {
+    assert_eq!(42, FixedSampler::new(42).sample());
+
+    let mut seen = [false; 100];
+    for _ in 0..10000 {
+        seen[RANDOM_SAMPLER.sample() as usize] = true;
+    }
+    assert!(seen.iter().all(|s| *s));
+}
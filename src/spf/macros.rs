@@ -0,0 +1,190 @@
+use std::ascii::AsciiExt;
+use std::net::IpAddr;
+
+use super::{Session, ipv6_octets};
+use super::SpfReason;
+
+// Expands the macros in a domain-spec, or with explanation set, in an exp=
+// explanation string (RFC 7208 section 7)
+pub fn expand(spec: &str, session: &Session, domain: &str, explanation: bool) -> Result<String, SpfReason> {
+    let bad_macro = || SpfReason::BadMacro(spec.to_string());
+    let mut expanded = String::new();
+
+    let mut chars = spec.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('%') => expanded.push('%'),
+            Some('_') => expanded.push(' '),
+            Some('-') => expanded.push_str("%20"),
+            Some('{') => {
+                let mut body = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => body.push(c),
+                        None => return Err(bad_macro())
+                    }
+                }
+                expanded.push_str(&try!(expand_macro(&body, session, domain, explanation).ok_or(bad_macro())));
+            }
+            _ => return Err(bad_macro())
+        }
+    }
+
+    if explanation {
+        return Ok(expanded);
+    }
+
+    // long names lose labels from the left (RFC 7208 section 7.3)
+    while expanded.len() > 253 {
+        match expanded.find('.') {
+            Some(dot) => expanded = expanded[dot + 1..].to_string(),
+            None => return Err(bad_macro())
+        }
+    }
+    Ok(expanded)
+}
+
+// A macro-letter, then an optional count of labels to keep, an optional "r"
+// to reverse them, and optional delimiters to split on
+fn expand_macro(body: &str, session: &Session, domain: &str, explanation: bool) -> Option<String> {
+    let mut chars = body.chars();
+    let letter = match chars.next() {
+        Some(letter) => letter,
+        None => return None
+    };
+    let rest: String = chars.collect();
+
+    let (sender_local, sender_domain) = match session.sender.rfind('@') {
+        Some(at) => (&session.sender[..at], &session.sender[at + 1..]),
+        None => ("postmaster", &session.sender[..])
+    };
+
+    let value = match letter.to_ascii_lowercase() {
+        's' => session.sender.clone(),
+        'l' => sender_local.to_string(),
+        'o' => sender_domain.to_string(),
+        'd' => domain.to_string(),
+        'i' => dotted_ip(&session.ip),
+        // PTR lookups aren't made, which RFC 7208 section 7.3 allows for
+        'p' => "unknown".to_string(),
+        'v' => match session.ip {
+            IpAddr::V4(_) => "in-addr".to_string(),
+            IpAddr::V6(_) => "ip6".to_string()
+        },
+        'h' => session.helo.clone(),
+        'c' if explanation => format!("{}", session.ip),
+        'r' if explanation => "unknown".to_string(),
+        't' if explanation => format!("{}", session.now),
+        _ => return None
+    };
+
+    let digits: String = rest.chars().take_while(|c| c.is_digit(10)).collect();
+    let mut transformers = rest[digits.len()..].chars().peekable();
+    let reverse = transformers.peek() == Some(&'r') || transformers.peek() == Some(&'R');
+    if reverse {
+        transformers.next();
+    }
+    let delimiters: Vec<char> = transformers.collect();
+    if delimiters.iter().any(|d| !".-+,/_=".contains(*d)) {
+        return None;
+    }
+
+    let mut parts: Vec<&str> = if delimiters.is_empty() {
+        value.split('.').collect()
+    }
+    else {
+        value.split(|c: char| delimiters.contains(&c)).collect()
+    };
+    if reverse {
+        parts.reverse();
+    }
+    if !digits.is_empty() {
+        let keep: usize = match digits.parse() {
+            Ok(0) | Err(_) => return None,
+            Ok(keep) => keep
+        };
+        if keep < parts.len() {
+            let skip = parts.len() - keep;
+            parts = parts[skip..].to_vec();
+        }
+    }
+
+    let transformed = parts.connect(".");
+    if letter.is_uppercase() {
+        Some(url_escape(&transformed))
+    }
+    else {
+        Some(transformed)
+    }
+}
+
+// IPv4 addresses as usual, IPv6 as dot-separated nibbles
+fn dotted_ip(ip: &IpAddr) -> String {
+    match *ip {
+        IpAddr::V4(ref ip) => format!("{}", ip),
+        IpAddr::V6(ref ip) => {
+            let nibbles: Vec<String> = ipv6_octets(ip).iter()
+                .flat_map(|o| vec![o >> 4, o & 0xf].into_iter())
+                .map(|n| format!("{:x}", n))
+                .collect();
+            nibbles.connect(".")
+        }
+    }
+}
+
+fn url_escape(value: &str) -> String {
+    let mut escaped = String::new();
+    for b in value.bytes() {
+        match b as char {
+            'a'...'z' | 'A'...'Z' | '0'...'9' | '-' | '.' | '_' | '~' => escaped.push(b as char),
+            _ => escaped.push_str(&format!("%{:02X}", b))
+        }
+    }
+    escaped
+}
+
+#[test]
+fn test_expand_macros() {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    // the examples of RFC 7208 section 7.4
+    let session = Session::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 3)),
+                               "strong-bad@email.example.com", "mx.example.org", 0);
+    let expand_d = |spec: &str| expand(spec, &session, "email.example.com", false).unwrap();
+
+    assert_eq!("strong-bad@email.example.com", expand_d("%{s}"));
+    assert_eq!("email.example.com", expand_d("%{o}"));
+    assert_eq!("email.example.com", expand_d("%{d}"));
+    assert_eq!("email.example.com", expand_d("%{d4}"));
+    assert_eq!("email.example.com", expand_d("%{d3}"));
+    assert_eq!("example.com", expand_d("%{d2}"));
+    assert_eq!("com", expand_d("%{d1}"));
+    assert_eq!("com.example.email", expand_d("%{dr}"));
+    assert_eq!("example.email", expand_d("%{d2r}"));
+    assert_eq!("strong-bad", expand_d("%{l}"));
+    assert_eq!("strong.bad", expand_d("%{l-}"));
+    assert_eq!("strong-bad", expand_d("%{lr}"));
+    assert_eq!("bad.strong", expand_d("%{lr-}"));
+    assert_eq!("strong", expand_d("%{l1r-}"));
+    assert_eq!("3.2.0.192.in-addr._spf.example.com", expand_d("%{ir}.%{v}._spf.%{d2}"));
+    assert_eq!("bad.strong.lp._spf.example.com", expand_d("%{lr-}.lp._spf.%{d2}"));
+    assert_eq!("example.com.trusted-domains.example.net", expand_d("%{d2}.trusted-domains.example.net"));
+    assert_eq!("strong-bad%40email.example.com", expand_d("%{S}"));
+    assert_eq!("a b%20c%", expand_d("a%_b%-c%%"));
+
+    let session = Session::new(IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0xcb01)),
+                               "strong-bad@email.example.com", "mx.example.org", 1117574938);
+    assert_eq!("1.0.b.c.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6._spf.example.com",
+               expand("%{ir}.%{v}._spf.%{d2}", &session, "email.example.com", false).unwrap());
+
+    // c, r and t are only for explanations
+    assert_eq!(Err(SpfReason::BadMacro("%{c}".to_string())), expand("%{c}", &session, "example.com", false));
+    assert_eq!("2001:db8::cb01 at 1117574938", expand("%{c} at %{t}", &session, "example.com", true).unwrap());
+    assert_eq!(Err(SpfReason::BadMacro("%{d0}".to_string())), expand("%{d0}", &session, "example.com", false));
+    assert_eq!(Err(SpfReason::BadMacro("%x".to_string())), expand("%x", &session, "example.com", false));
+}
@@ -0,0 +1,575 @@
+use std::ascii::AsciiExt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use dns::{DnsError, ZoneFile};
+use clock::{Clock, SYSTEM_CLOCK};
+
+mod macros;
+mod result;
+
+pub use self::result::{SpfResult, SpfStatus, SpfReason, SpfIdentity};
+
+use self::macros::expand;
+
+// RFC 7208 section 4.6.4
+const MAX_LOOKUPS: u32 = 10;
+const MAX_VOID_LOOKUPS: u32 = 2;
+const MAX_MX_NAMES: usize = 10;
+
+// The DNS queries an SPF check makes
+pub trait SpfResolver {
+    fn txt(&self, name: &str) -> Result<Vec<String>, DnsError>;
+    fn a(&self, name: &str) -> Result<Vec<Ipv4Addr>, DnsError>;
+    fn aaaa(&self, name: &str) -> Result<Vec<Ipv6Addr>, DnsError>;
+    fn mx(&self, name: &str) -> Result<Vec<(u16, String)>, DnsError>;
+}
+
+impl SpfResolver for ZoneFile {
+    fn txt(&self, name: &str) -> Result<Vec<String>, DnsError> {
+        ZoneFile::txt(self, name)
+    }
+
+    fn a(&self, name: &str) -> Result<Vec<Ipv4Addr>, DnsError> {
+        ZoneFile::a(self, name)
+    }
+
+    fn aaaa(&self, name: &str) -> Result<Vec<Ipv6Addr>, DnsError> {
+        ZoneFile::aaaa(self, name)
+    }
+
+    fn mx(&self, name: &str) -> Result<Vec<(u16, String)>, DnsError> {
+        ZoneFile::mx(self, name)
+    }
+}
+
+// The state of one check_host() evaluation, including any include: and
+// redirect= records it leads to
+pub struct Session {
+    ip: IpAddr,
+    sender: String,
+    helo: String,
+    now: u64,
+    lookups: u32,
+    void_lookups: u32
+}
+
+impl Session {
+    fn new(ip: IpAddr, sender: &str, helo: &str, now: u64) -> Session {
+        Session {
+            ip: ipv4_mapped(ip),
+            sender: sender.to_string(),
+            helo: helo.to_string(),
+            now: now,
+            lookups: 0,
+            void_lookups: 0
+        }
+    }
+
+    fn count_lookup(&mut self) -> Result<(), SpfReason> {
+        self.lookups = self.lookups + 1;
+        if self.lookups > MAX_LOOKUPS {
+            return Err(SpfReason::TooManyLookups);
+        }
+        Ok(())
+    }
+
+    // Answers with no records count towards the void lookup limit, except
+    // for the address lookups of each MX name
+    fn answers<T>(&mut self, name: &str, answers: Result<Vec<T>, DnsError>, count_void: bool)
+        -> Result<Vec<T>, SpfReason> {
+        let answers = match answers {
+            Ok(answers) => answers,
+            Err(DnsError::NotFound) => vec![],
+            Err(DnsError::TempFail(_)) => return Err(SpfReason::DnsFailure(name.to_string()))
+        };
+        if answers.is_empty() && count_void {
+            self.void_lookups = self.void_lookups + 1;
+            if self.void_lookups > MAX_VOID_LOOKUPS {
+                return Err(SpfReason::TooManyVoidLookups);
+            }
+        }
+        Ok(answers)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Mechanism {
+    All,
+    Include(String),
+    A(Option<String>, u8, u8),
+    Mx(Option<String>, u8, u8),
+    Ptr(Option<String>),
+    Ip4(Ipv4Addr, u8),
+    Ip6(Ipv6Addr, u8),
+    Exists(String)
+}
+
+// A parsed SPF record: its mechanisms, with their results and the text they
+// were parsed from, and its redirect= and exp= modifiers
+#[derive(Debug, PartialEq, Clone)]
+struct SpfRecord {
+    directives: Vec<(SpfStatus, Mechanism, String)>,
+    redirect: Option<String>,
+    explanation: Option<String>
+}
+
+impl SpfRecord {
+    fn parse(record: &str) -> Result<SpfRecord, SpfReason> {
+        let mut spf_record = SpfRecord { directives: vec![], redirect: None, explanation: None };
+
+        for term in record.split(' ').skip(1).filter(|t| !t.is_empty()) {
+            let bad_term = || SpfReason::BadRecord(term.to_string());
+
+            let name_len = term.find(|c: char| !(c.is_alphanumeric() || "-_.".contains(c))).unwrap_or(term.len());
+            if term[name_len..].starts_with("=") {
+                let value = term[name_len + 1..].to_string();
+                let modifier = match &term[..name_len].to_ascii_lowercase()[..] {
+                    "redirect" => &mut spf_record.redirect,
+                    "exp" => &mut spf_record.explanation,
+                    // unknown modifiers are ignored
+                    _ => continue
+                };
+                if modifier.is_some() {
+                    return Err(bad_term());
+                }
+                *modifier = Some(value);
+                continue;
+            }
+
+            let (qualifier, directive) = match term.chars().next() {
+                Some('+') => (SpfStatus::Pass, &term[1..]),
+                Some('-') => (SpfStatus::Fail, &term[1..]),
+                Some('~') => (SpfStatus::SoftFail, &term[1..]),
+                Some('?') => (SpfStatus::Neutral, &term[1..]),
+                _ => (SpfStatus::Pass, term)
+            };
+            let name_len = directive.find(|c: char| c == ':' || c == '/').unwrap_or(directive.len());
+            let name = directive[..name_len].to_ascii_lowercase();
+            let args = &directive[name_len..];
+            let domain_spec = if args.starts_with(":") { Some(&args[1..]) } else { None };
+
+            let mechanism = match &name[..] {
+                "all" if args.is_empty() => Mechanism::All,
+                "include" => Mechanism::Include(try!(domain_spec.ok_or(bad_term())).to_string()),
+                "exists" => Mechanism::Exists(try!(domain_spec.ok_or(bad_term())).to_string()),
+                "ptr" if !args.starts_with("/") => Mechanism::Ptr(domain_spec.map(|d| d.to_string())),
+                "a" | "mx" => {
+                    let (domain, cidr) = split_cidr(domain_spec.unwrap_or(""));
+                    let domain = if domain.is_empty() { None } else { Some(domain.to_string()) };
+                    let cidr = if args.starts_with("/") { args } else { cidr };
+                    let (ip4_cidr, ip6_cidr) = try!(parse_dual_cidr(cidr).ok_or(bad_term()));
+                    if args.starts_with(":") && domain.is_none() {
+                        return Err(bad_term());
+                    }
+                    if name == "a" {
+                        Mechanism::A(domain, ip4_cidr, ip6_cidr)
+                    }
+                    else {
+                        Mechanism::Mx(domain, ip4_cidr, ip6_cidr)
+                    }
+                }
+                "ip4" => {
+                    let (address, cidr) = split_cidr(try!(domain_spec.ok_or(bad_term())));
+                    let cidr = try!(parse_cidr(cidr, 32).ok_or(bad_term()));
+                    Mechanism::Ip4(try!(address.parse().map_err(|_| bad_term())), cidr)
+                }
+                "ip6" => {
+                    let (address, cidr) = split_cidr(try!(domain_spec.ok_or(bad_term())));
+                    let cidr = try!(parse_cidr(cidr, 128).ok_or(bad_term()));
+                    Mechanism::Ip6(try!(address.parse().map_err(|_| bad_term())), cidr)
+                }
+                _ => return Err(bad_term())
+            };
+            spf_record.directives.push((qualifier, mechanism, term.to_string()));
+        }
+
+        Ok(spf_record)
+    }
+}
+
+// Splits a domain-spec from any CIDR lengths after it.  A "/" inside a macro
+// is a delimiter, not the start of a length.
+fn split_cidr(spec: &str) -> (&str, &str) {
+    let mut in_macro = false;
+    for (i, c) in spec.char_indices() {
+        match c {
+            '{' => in_macro = true,
+            '}' => in_macro = false,
+            '/' if !in_macro => return (&spec[..i], &spec[i..]),
+            _ => ()
+        }
+    }
+    (spec, "")
+}
+
+// "/n" for an IPv4 length or "//n" for IPv6
+fn parse_cidr(cidr: &str, max: u8) -> Option<u8> {
+    if cidr.is_empty() {
+        return Some(max);
+    }
+    if !cidr.starts_with("/") {
+        return None;
+    }
+    match cidr[1..].parse() {
+        Ok(len) if len <= max && (cidr.len() == 2 || !cidr[1..].starts_with("0")) => Some(len),
+        _ => None
+    }
+}
+
+fn parse_dual_cidr(cidr: &str) -> Option<(u8, u8)> {
+    match cidr.find("//") {
+        Some(i) => match (parse_cidr(&cidr[..i], 32), parse_cidr(&cidr[i + 1..], 128)) {
+            (Some(ip4), Some(ip6)) => Some((ip4, ip6)),
+            _ => None
+        },
+        None => parse_cidr(cidr, 32).map(|ip4| (ip4, 128))
+    }
+}
+
+// IPv4 clients connected over IPv6 are checked as IPv4 (RFC 7208 section 5)
+fn ipv4_mapped(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(ref ip6) => {
+            let s = ip6.segments();
+            if s[..5].iter().all(|s| *s == 0) && s[5] == 0xffff {
+                return IpAddr::V4(Ipv4Addr::new((s[6] >> 8) as u8, s[6] as u8, (s[7] >> 8) as u8, s[7] as u8));
+            }
+        }
+        IpAddr::V4(_) => ()
+    }
+    ip
+}
+
+pub fn ipv6_octets(ip: &Ipv6Addr) -> Vec<u8> {
+    ip.segments().iter().flat_map(|s| vec![(s >> 8) as u8, *s as u8].into_iter()).collect()
+}
+
+// Whether the first prefix_len bits of two addresses are the same
+fn same_prefix(a: &[u8], b: &[u8], prefix_len: u8) -> bool {
+    let prefix_len = prefix_len as usize;
+    let whole = prefix_len / 8;
+    if a[..whole] != b[..whole] {
+        return false;
+    }
+    let bits = prefix_len % 8;
+    bits == 0 || (a[whole] ^ b[whole]) >> (8 - bits) == 0
+}
+
+fn in_network(ip: &IpAddr, ip4: &[Ipv4Addr], ip6: &[Ipv6Addr], ip4_cidr: u8, ip6_cidr: u8) -> bool {
+    match *ip {
+        IpAddr::V4(ref ip) => ip4.iter().any(|a| same_prefix(&ip.octets(), &a.octets(), ip4_cidr)),
+        IpAddr::V6(ref ip) => ip6.iter().any(|a| same_prefix(&ipv6_octets(ip), &ipv6_octets(a), ip6_cidr))
+    }
+}
+
+// A domain has to be a multi-label name with labels of 63 octets or less
+// (RFC 7208 section 4.3)
+fn valid_domain(domain: &str) -> bool {
+    let labels: Vec<&str> = domain.trim_right_matches('.').split('.').collect();
+    labels.len() > 1 && labels.iter().all(|l| !l.is_empty() && l.len() <= 63)
+}
+
+// Checks whether a client is authorized to send mail for a domain (RFC 7208)
+pub struct SpfEvaluator<'a> {
+    resolver: &'a (SpfResolver + 'a),
+    clock: &'a (Clock + 'a)
+}
+
+impl<'a> SpfEvaluator<'a> {
+    pub fn new(resolver: &'a SpfResolver) -> SpfEvaluator<'a> {
+        SpfEvaluator::with_clock(resolver, &SYSTEM_CLOCK)
+    }
+
+    pub fn with_clock(resolver: &'a SpfResolver, clock: &'a Clock) -> SpfEvaluator<'a> {
+        SpfEvaluator { resolver: resolver, clock: clock }
+    }
+
+    // Checks the MAIL FROM identity.  A null reverse-path is checked as
+    // postmaster@ the HELO name (RFC 7208 section 2.4).
+    pub fn check_mail_from(&self, ip: IpAddr, helo: &str, mail_from: &str) -> SpfResult {
+        let mail_from = mail_from.trim_left_matches('<').trim_right_matches('>');
+        let sender = match mail_from.rfind('@') {
+            _ if mail_from.is_empty() => format!("postmaster@{}", helo),
+            Some(0) => format!("postmaster{}", mail_from),
+            Some(_) => mail_from.to_string(),
+            None => format!("postmaster@{}", mail_from)
+        };
+        self.check(SpfIdentity::MailFrom, ip, helo, &sender)
+    }
+
+    pub fn check_helo(&self, ip: IpAddr, helo: &str) -> SpfResult {
+        self.check(SpfIdentity::Helo, ip, helo, &format!("postmaster@{}", helo))
+    }
+
+    fn check(&self, identity: SpfIdentity, ip: IpAddr, helo: &str, sender: &str) -> SpfResult {
+        let domain = sender[sender.rfind('@').unwrap() + 1..].to_ascii_lowercase();
+        let mut result = SpfResult {
+            status: SpfStatus::None,
+            reason: None,
+            identity: identity,
+            sender: sender.to_string(),
+            domain: domain.clone(),
+            mechanism: None,
+            explanation: None
+        };
+
+        if !valid_domain(&domain) {
+            result.reason = Some(SpfReason::BadDomain(domain));
+            return result;
+        }
+
+        let mut session = Session::new(ip, sender, helo, self.clock.now());
+        match self.check_host(&mut session, &domain) {
+            Ok((status, mechanism, explanation)) => {
+                result.status = status;
+                result.mechanism = mechanism;
+                result.explanation = explanation;
+            }
+            Err(reason) => {
+                result.status = reason.status();
+                result.reason = Some(reason);
+            }
+        }
+        result
+    }
+
+    // The check_host() function of RFC 7208 section 4, giving the result, the
+    // mechanism that matched, and for a failure the explanation
+    fn check_host(&self, session: &mut Session, domain: &str)
+        -> Result<(SpfStatus, Option<String>, Option<String>), SpfReason> {
+
+        let record = try!(self.lookup_record(domain));
+
+        for &(status, ref mechanism, ref text) in record.directives.iter() {
+            if try!(self.matches(session, domain, mechanism)) {
+                let explanation = match (status, record.explanation.as_ref()) {
+                    (SpfStatus::Fail, Some(exp)) => self.explain(&*session, domain, exp),
+                    _ => None
+                };
+                return Ok((status, Some(text.clone()), explanation));
+            }
+        }
+
+        match record.redirect {
+            Some(ref redirect) => {
+                try!(session.count_lookup());
+                let target = try!(expand(redirect, session, domain, false));
+                match self.check_host(session, &target) {
+                    Err(SpfReason::NoRecord) | Err(SpfReason::BadDomain(_)) => Err(SpfReason::MissingRecord(target)),
+                    result => result
+                }
+            }
+            None => Ok((SpfStatus::Neutral, None, None))
+        }
+    }
+
+    fn lookup_record(&self, domain: &str) -> Result<SpfRecord, SpfReason> {
+        if !valid_domain(domain) {
+            return Err(SpfReason::BadDomain(domain.to_string()));
+        }
+        let records = match self.resolver.txt(domain) {
+            Ok(records) => records,
+            Err(DnsError::NotFound) => vec![],
+            Err(DnsError::TempFail(_)) => return Err(SpfReason::DnsFailure(domain.to_string()))
+        };
+
+        let mut spf_records: Vec<String> = records.into_iter().filter(|r| {
+            let version = r.split(' ').next().unwrap_or("");
+            version.eq_ignore_ascii_case("v=spf1")
+        }).collect();
+        match spf_records.len() {
+            0 => Err(SpfReason::NoRecord),
+            1 => SpfRecord::parse(&spf_records.pop().unwrap()),
+            _ => Err(SpfReason::MultipleRecords(domain.to_string()))
+        }
+    }
+
+    fn matches(&self, session: &mut Session, domain: &str, mechanism: &Mechanism) -> Result<bool, SpfReason> {
+        // a and mx default to the current domain
+        let target = |session: &Session, spec: &Option<String>| match *spec {
+            Some(ref spec) => expand(spec, session, domain, false),
+            None => Ok(domain.to_string())
+        };
+
+        match *mechanism {
+            Mechanism::All => Ok(true),
+            Mechanism::Include(ref spec) => {
+                try!(session.count_lookup());
+                let target = try!(expand(spec, session, domain, false));
+                match self.check_host(session, &target) {
+                    Ok((status, _, _)) => Ok(status == SpfStatus::Pass),
+                    Err(SpfReason::NoRecord) | Err(SpfReason::BadDomain(_)) => Err(SpfReason::MissingRecord(target)),
+                    Err(reason) => Err(reason)
+                }
+            }
+            Mechanism::A(ref spec, ip4_cidr, ip6_cidr) => {
+                try!(session.count_lookup());
+                let target = try!(target(&*session, spec));
+                let (ip4, ip6) = try!(self.addresses(session, &target, true));
+                Ok(in_network(&session.ip, &ip4, &ip6, ip4_cidr, ip6_cidr))
+            }
+            Mechanism::Mx(ref spec, ip4_cidr, ip6_cidr) => {
+                try!(session.count_lookup());
+                let target = try!(target(&*session, spec));
+                let exchanges = try!(session.answers(&target, self.resolver.mx(&target), true));
+                if exchanges.len() > MAX_MX_NAMES {
+                    return Err(SpfReason::TooManyMxNames(target));
+                }
+                for &(_, ref exchange) in exchanges.iter() {
+                    let (ip4, ip6) = try!(self.addresses(session, exchange, false));
+                    if in_network(&session.ip, &ip4, &ip6, ip4_cidr, ip6_cidr) {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            // ptr is deprecated (RFC 7208 section 5.5), and never matches here
+            Mechanism::Ptr(_) => {
+                try!(session.count_lookup());
+                Ok(false)
+            }
+            Mechanism::Ip4(ref network, cidr) => Ok(in_network(&session.ip, &[*network], &[], cidr, 0)),
+            Mechanism::Ip6(ref network, cidr) => Ok(in_network(&session.ip, &[], &[*network], 0, cidr)),
+            Mechanism::Exists(ref spec) => {
+                try!(session.count_lookup());
+                let target = try!(expand(spec, session, domain, false));
+                // always an A query, whatever the client's address family
+                let addresses = try!(session.answers(&target, self.resolver.a(&target), true));
+                Ok(!addresses.is_empty())
+            }
+        }
+    }
+
+    // The addresses of a name in the client's address family
+    fn addresses(&self, session: &mut Session, name: &str, count_void: bool)
+        -> Result<(Vec<Ipv4Addr>, Vec<Ipv6Addr>), SpfReason> {
+        let is_ip4 = match session.ip {
+            IpAddr::V4(_) => true,
+            IpAddr::V6(_) => false
+        };
+        if is_ip4 {
+            Ok((try!(session.answers(name, self.resolver.a(name), count_void)), vec![]))
+        }
+        else {
+            Ok((vec![], try!(session.answers(name, self.resolver.aaaa(name), count_void))))
+        }
+    }
+
+    // The expanded exp= explanation; any problem getting it just means there
+    // isn't one (RFC 7208 section 6.2)
+    fn explain(&self, session: &Session, domain: &str, exp: &str) -> Option<String> {
+        let target = match expand(exp, session, domain, false) {
+            Ok(target) => target,
+            Err(_) => return None
+        };
+        match self.resolver.txt(&target) {
+            Ok(ref records) if records.len() == 1 => expand(&records[0], session, domain, true).ok(),
+            _ => None
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_spf_zone() -> ZoneFile {
+    ZoneFile::parse("$ORIGIN example.com.\n\
+                     @          TXT \"v=spf1 ip4:192.0.2.0/24 mx -a:%{d2}.example.net include:_spf.example.org -all\"\n\
+                     @          MX 10 mx\n\
+                     mx         A 198.51.100.1\n\
+                     \x20          AAAA 2001:db8::1\n\
+                     soft       TXT \"v=spf1 a:mx.example.com//64 ~all\"\n\
+                     redirect   TXT \"v=spf1 ?ip4:203.0.113.5 redirect=example.com\"\n\
+                     explain    TXT \"v=spf1 exists:%{ir}.allow.example.com -all exp=why.example.com\"\n\
+                     why        TXT \"%{c} is not allowed to send for %{d}\"\n\
+                     1.113.0.203.allow A 127.0.0.2\n\
+                     two        TXT \"v=spf1 -all\"\n\
+                     \x20          TXT \"v=spf1 +all\"\n\
+                     bad        TXT \"v=spf1 ip4:192.0.2.300 -all\"\n\
+                     loop       TXT \"v=spf1 include:loop.example.com -all\"\n\
+                     void       TXT \"v=spf1 a:none1.example.com a:none2.example.com a:none3.example.com -all\"\n\
+                     missing    TXT \"v=spf1 include:nothing.example.com -all\"\n\
+                     example.com.example.net. A 203.0.113.9\n\
+                     _spf.example.org. TXT \"v=spf1 ip6:2001:db8:1::/48 -all\"\n").unwrap()
+}
+
+#[test]
+fn test_spf_mechanisms() {
+    let zone = test_spf_zone();
+    let spf = SpfEvaluator::new(&zone);
+    let check = |ip: &str, mail_from: &str| {
+        let result = spf.check_mail_from(ip.parse().unwrap(), "mail.example.com", mail_from);
+        (result.status, result.mechanism)
+    };
+
+    assert_eq!((SpfStatus::Pass, Some("ip4:192.0.2.0/24".to_string())), check("192.0.2.55", "joe@example.com"));
+    assert_eq!((SpfStatus::Pass, Some("mx".to_string())), check("198.51.100.1", "joe@example.com"));
+    assert_eq!((SpfStatus::Pass, Some("mx".to_string())), check("2001:db8::1", "joe@example.com"));
+    assert_eq!((SpfStatus::Pass, Some("ip4:192.0.2.0/24".to_string())), check("::ffff:192.0.2.1", "joe@example.com"));
+    assert_eq!((SpfStatus::Fail, Some("-a:%{d2}.example.net".to_string())), check("203.0.113.9", "joe@example.com"));
+    assert_eq!((SpfStatus::Pass, Some("include:_spf.example.org".to_string())), check("2001:db8:1::25", "<joe@example.com>"));
+    assert_eq!((SpfStatus::Fail, Some("-all".to_string())), check("203.0.113.1", "joe@example.com"));
+
+    assert_eq!((SpfStatus::Pass, Some("a:mx.example.com//64".to_string())), check("2001:db8::99", "joe@soft.example.com"));
+    assert_eq!((SpfStatus::SoftFail, Some("~all".to_string())), check("2001:db8:2::1", "joe@soft.example.com"));
+
+    assert_eq!((SpfStatus::Neutral, Some("?ip4:203.0.113.5".to_string())), check("203.0.113.5", "joe@redirect.example.com"));
+    assert_eq!((SpfStatus::Pass, Some("ip4:192.0.2.0/24".to_string())), check("192.0.2.1", "joe@redirect.example.com"));
+
+    // a null sender is checked as postmaster@ the HELO name
+    let result = spf.check_mail_from("192.0.2.1".parse().unwrap(), "mail.example.com", "<>");
+    assert_eq!("postmaster@mail.example.com", result.sender);
+    assert_eq!(SpfStatus::None, result.status);
+    let result = spf.check_helo("203.0.113.9".parse().unwrap(), "soft.example.com");
+    assert_eq!((SpfIdentity::Helo, SpfStatus::SoftFail), (result.identity, result.status));
+}
+
+#[test]
+fn test_spf_explanation() {
+    let zone = test_spf_zone();
+    let spf = SpfEvaluator::new(&zone);
+
+    let result = spf.check_mail_from("203.0.113.1".parse().unwrap(), "mail.example.com", "joe@explain.example.com");
+    assert_eq!(SpfStatus::Pass, result.status);
+
+    let result = spf.check_mail_from("203.0.113.2".parse().unwrap(), "mail.example.com", "joe@explain.example.com");
+    assert_eq!(SpfStatus::Fail, result.status);
+    assert_eq!(Some("203.0.113.2 is not allowed to send for explain.example.com".to_string()), result.explanation);
+}
+
+#[test]
+fn test_spf_errors() {
+    let zone = test_spf_zone();
+    let spf = SpfEvaluator::new(&zone);
+    let check = |mail_from: &str| {
+        let result = spf.check_mail_from("203.0.113.1".parse().unwrap(), "mail.example.com", mail_from);
+        (result.status, result.reason)
+    };
+
+    assert_eq!((SpfStatus::None, Some(SpfReason::NoRecord)), check("joe@mx.example.com"));
+    assert_eq!((SpfStatus::None, Some(SpfReason::BadDomain("localhost".to_string()))), check("joe@localhost"));
+    assert_eq!((SpfStatus::PermError, Some(SpfReason::MultipleRecords("two.example.com".to_string()))),
+               check("joe@two.example.com"));
+    assert_eq!((SpfStatus::PermError, Some(SpfReason::BadRecord("ip4:192.0.2.300".to_string()))),
+               check("joe@bad.example.com"));
+    assert_eq!((SpfStatus::PermError, Some(SpfReason::TooManyLookups)), check("joe@loop.example.com"));
+    assert_eq!((SpfStatus::PermError, Some(SpfReason::TooManyVoidLookups)), check("joe@void.example.com"));
+    assert_eq!((SpfStatus::PermError, Some(SpfReason::MissingRecord("nothing.example.com".to_string()))),
+               check("joe@missing.example.com"));
+}
+
+#[test]
+fn test_parse_spf_record() {
+    let record = SpfRecord::parse("v=spf1 a/24//64 mx:example.net/28 ptr ip6:2001:db8::/32 \
+                                   exists:%{l1r/}.example.com foo=bar -all").unwrap();
+    assert_eq!(vec![(SpfStatus::Pass, Mechanism::A(None, 24, 64), "a/24//64".to_string()),
+                    (SpfStatus::Pass, Mechanism::Mx(Some("example.net".to_string()), 28, 128), "mx:example.net/28".to_string()),
+                    (SpfStatus::Pass, Mechanism::Ptr(None), "ptr".to_string()),
+                    (SpfStatus::Pass, Mechanism::Ip6("2001:db8::".parse().unwrap(), 32), "ip6:2001:db8::/32".to_string()),
+                    (SpfStatus::Pass, Mechanism::Exists("%{l1r/}.example.com".to_string()), "exists:%{l1r/}.example.com".to_string()),
+                    (SpfStatus::Fail, Mechanism::All, "-all".to_string())],
+               record.directives);
+
+    assert_eq!(Err(SpfReason::BadRecord("redirect=b".to_string())), SpfRecord::parse("v=spf1 redirect=a redirect=b"));
+    assert_eq!(Err(SpfReason::BadRecord("a/33".to_string())), SpfRecord::parse("v=spf1 a/33"));
+    assert_eq!(Err(SpfReason::BadRecord("include".to_string())), SpfRecord::parse("v=spf1 include"));
+    assert_eq!(Err(SpfReason::BadRecord("frobnicate".to_string())), SpfRecord::parse("v=spf1 frobnicate"));
+}
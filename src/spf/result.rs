@@ -0,0 +1,79 @@
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SpfStatus {
+    None,
+    Neutral,
+    Pass,
+    Fail,
+    SoftFail,
+    TempError,
+    PermError
+}
+
+impl SpfStatus {
+    // The result name used in Authentication-Results headers (RFC 8601)
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SpfStatus::None => "none",
+            SpfStatus::Neutral => "neutral",
+            SpfStatus::Pass => "pass",
+            SpfStatus::Fail => "fail",
+            SpfStatus::SoftFail => "softfail",
+            SpfStatus::TempError => "temperror",
+            SpfStatus::PermError => "permerror"
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum SpfReason {
+    // the domain isn't a valid name, or publishes no SPF record
+    BadDomain(String),
+    NoRecord,
+
+    // the record, or one it refers to, can't be used
+    MultipleRecords(String),
+    BadRecord(String),
+    BadMacro(String),
+    // an include: or redirect= names a domain with no SPF record
+    MissingRecord(String),
+
+    // the limits of RFC 7208 section 4.6.4
+    TooManyLookups,
+    TooManyVoidLookups,
+    TooManyMxNames(String),
+
+    DnsFailure(String)
+}
+
+impl SpfReason {
+    pub fn status(&self) -> SpfStatus {
+        match *self {
+            SpfReason::BadDomain(_) => SpfStatus::None,
+            SpfReason::NoRecord => SpfStatus::None,
+            SpfReason::DnsFailure(_) => SpfStatus::TempError,
+            _ => SpfStatus::PermError
+        }
+    }
+}
+
+// The identity an SPF check was for (RFC 7208 section 2.3 and 2.4)
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SpfIdentity {
+    MailFrom,
+    Helo
+}
+
+// The outcome of checking one identity, HELO or MAIL FROM, for a client IP
+#[derive(Debug, PartialEq, Clone)]
+pub struct SpfResult {
+    pub status: SpfStatus,
+    pub reason: Option<SpfReason>,
+    pub identity: SpfIdentity,
+    // the sender checked: the MAIL FROM address, or postmaster@ the HELO name
+    pub sender: String,
+    pub domain: String,
+    // the mechanism that matched, as written in the record
+    pub mechanism: Option<String>,
+    // for a failure, the domain's exp= explanation
+    pub explanation: Option<String>
+}
@@ -0,0 +1,141 @@
+use events::MessageParserEvent;
+use events::MessageParserStage;
+use events::MessageParserEvent::{Envelope, SpfResult};
+
+use spf::{SpfEvaluator, SpfResolver};
+#[cfg(test)]
+use spf::{SpfStatus, SpfIdentity};
+#[cfg(test)]
+use dns::ZoneFile;
+
+// Checks the HELO and MAIL FROM identities of each message against the client
+// IP in its Envelope event, and reports them with an SpfResult event each
+// straight after, HELO first (RFC 7208 section 2.3).  A HELO that is missing
+// or an address literal isn't checked.  Messages without an envelope, or
+// without a client IP, aren't checked at all.
+pub struct SpfChecker<'a> {
+    evaluator: SpfEvaluator<'a>,
+    next_stage: &'a mut (MessageParserStage + 'a)
+}
+
+impl<'a> SpfChecker<'a> {
+    pub fn new(next_stage: &'a mut MessageParserStage, resolver: &'a SpfResolver) -> SpfChecker<'a> {
+        SpfChecker {
+            evaluator: SpfEvaluator::new(resolver),
+            next_stage: next_stage
+        }
+    }
+}
+
+impl<'a> MessageParserStage for SpfChecker<'a> {
+    fn process_event(&mut self, event: MessageParserEvent) {
+        let mut results = vec![];
+        match event {
+            Envelope(ref envelope) => match envelope.client_ip {
+                Some(ip) => {
+                    let helo = envelope.helo.as_ref().map_or("", |h| &h[..]);
+                    let mail_from = envelope.mail_from.as_ref().map_or("", |m| &m[..]);
+                    if !helo.is_empty() && !helo.starts_with('[') {
+                        results.push(self.evaluator.check_helo(ip, helo));
+                    }
+                    results.push(self.evaluator.check_mail_from(ip, helo, mail_from));
+                }
+                None => ()
+            },
+            _ => ()
+        }
+
+        self.next_stage.process_event(event);
+        for result in results.into_iter() {
+            self.next_stage.process_event(SpfResult(result));
+        }
+    }
+}
+
+#[test]
+fn spf_checker_test() {
+    use message_parser_sink::MessageParserSink;
+    use reader_parser::ReaderParser;
+    use message_scanner::MessageScanner;
+    use header_parser::HeaderParser;
+    use dmarc_checker::DmarcChecker;
+    use events::MessageParserFilter;
+    use events::MessageParserEvent::{HeaderName, DmarcResult};
+    use envelope;
+    use dmarc::DmarcStatus;
+
+    let msg = "From: Joe SixPack <joe@football.example.com>\r\n\
+               To: Suzie Q <suzie@shopping.example.net>\r\n\
+               Subject: Is dinner ready?\r\n\
+               \r\n\
+               Hi.\r\n";
+
+    let mut zone = ZoneFile::new();
+    zone.add_txt("bounces.example.com", "v=spf1 ip4:192.0.2.0/24 -all");
+    zone.add_txt("mail.example.com", "v=spf1 ip4:198.51.100.0/24 -all");
+    zone.add_txt("_dmarc.example.com", "v=DMARC1; p=reject");
+
+    let mut envelope = envelope::Envelope::new();
+    envelope.client_ip = Some("192.0.2.1".parse().unwrap());
+    envelope.helo = Some("mail.example.com".to_string());
+    envelope.mail_from = Some("joe@bounces.example.com".to_string());
+    envelope.recipients = vec!["suzie@shopping.example.net".to_string()];
+
+    let mut sink = MessageParserSink::new();
+    {
+        let r = msg.as_bytes();
+        let mut dmarc = DmarcChecker::new(&mut sink, &zone);
+        let mut spf = SpfChecker::new(&mut dmarc, &zone);
+        let mut parser: HeaderParser = MessageParserFilter::new(&mut spf);
+        let mut scanner: MessageScanner = MessageParserFilter::new(&mut parser);
+        let mut rp = ReaderParser::with_envelope(&mut scanner, r, envelope.clone());
+
+        rp.read_to_end();
+    }
+
+    // the envelope comes first, and passes through every stage
+    let events = sink.events();
+    assert_eq!(Envelope(envelope.clone()), events[0]);
+    match events[1] {
+        SpfResult(ref result) => {
+            assert_eq!(SpfIdentity::Helo, result.identity);
+            assert_eq!(SpfStatus::Fail, result.status);
+            assert_eq!("mail.example.com", result.domain);
+        }
+        ref e => panic!("expected an SpfResult, got {:?}", e)
+    }
+    match events[2] {
+        SpfResult(ref result) => {
+            assert_eq!(SpfIdentity::MailFrom, result.identity);
+            assert_eq!(SpfStatus::Pass, result.status);
+            assert_eq!("bounces.example.com", result.domain);
+        }
+        ref e => panic!("expected an SpfResult, got {:?}", e)
+    }
+    assert_eq!(HeaderName("From:".to_string()), events[3]);
+
+    // the SPF pass is aligned with the From domain, and the HELO failure
+    // doesn't count against it
+    assert!(events.iter().any(|e| match *e {
+        DmarcResult(ref result) => result.status == DmarcStatus::Pass && result.spf_aligned,
+        _ => false
+    }));
+
+    // an address literal HELO isn't checked
+    envelope.helo = Some("[192.0.2.1]".to_string());
+    let mut sink = MessageParserSink::new();
+    {
+        let r = msg.as_bytes();
+        let mut spf = SpfChecker::new(&mut sink, &zone);
+        let mut parser: HeaderParser = MessageParserFilter::new(&mut spf);
+        let mut scanner: MessageScanner = MessageParserFilter::new(&mut parser);
+        let mut rp = ReaderParser::with_envelope(&mut scanner, r, envelope.clone());
+
+        rp.read_to_end();
+    }
+    let identities: Vec<SpfIdentity> = sink.events().into_iter().filter_map(|e| match e {
+        SpfResult(result) => Some(result.identity),
+        _ => None
+    }).collect();
+    assert_eq!(vec![SpfIdentity::MailFrom], identities);
+}